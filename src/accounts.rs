@@ -0,0 +1,208 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use argon2::password_hash::phc::PasswordHash;
+use argon2::password_hash::{PasswordHasher, PasswordVerifier};
+use argon2::Argon2;
+use serde::{Deserialize, Serialize};
+
+use crate::chess_errors::ChessErrors;
+use crate::rating::{self, Pool, Rating};
+use crate::session::secret_token;
+
+const MIN_PASSWORD_LEN: usize = 8;
+const MAX_USERNAME_LEN: usize = 20;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct User {
+    pub username: String,
+    // Argon2id, as a PHC string
+    password_hash: String,
+    // seconds since the epoch
+    pub created: u64,
+    // the games they sat down in, oldest first
+    pub games: Vec<String>,
+    // only the pools they have played in
+    #[serde(default)]
+    pub ratings: BTreeMap<Pool, Rating>,
+    // oldest first
+    #[serde(default)]
+    pub rating_history: Vec<RatingChange>,
+}
+
+impl User {
+    pub fn rating(&self, pool: Pool) -> Rating {
+        self.ratings.get(&pool).copied().unwrap_or_default()
+    }
+}
+
+// a user's rating after a rated game
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RatingChange {
+    pub pool: Pool,
+    pub game_id: String,
+    pub opponent: String,
+    // 1 won, 0.5 drawn, 0 lost
+    pub score: f64,
+    pub rating: Rating,
+    // seconds since the epoch
+    pub time: u64,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
+
+// Registered users, kept in a JSON file when given one, and who is logged in. Usernames
+// are unique regardless of case.
+#[derive(Default)]
+pub struct Accounts {
+    path: Option<PathBuf>,
+    users: Mutex<HashMap<String, User>>,
+    // session token to username
+    sessions: Mutex<HashMap<String, String>>,
+}
+
+fn key(username: &str) -> String {
+    username.to_lowercase()
+}
+
+fn storage_error(path: &Path, error: impl std::fmt::Display) -> ChessErrors {
+    ChessErrors::AccountsError(format!("{}: {}", path.display(), error))
+}
+
+impl Accounts {
+    pub fn open(path: &str) -> Result<Accounts, ChessErrors> {
+        let path = PathBuf::from(path);
+        let users: Vec<User> = match fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| storage_error(&path, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(storage_error(&path, e)),
+        };
+        Ok(Accounts {
+            users: Mutex::new(users.into_iter().map(|user| (key(&user.username), user)).collect()),
+            path: Some(path),
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // written to a temporary file first so a crash never leaves half a file
+    fn save(&self, users: &HashMap<String, User>) -> Result<(), ChessErrors> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut list: Vec<&User> = users.values().collect();
+        list.sort_by_key(|user| user.created);
+        let text = serde_json::to_string_pretty(&list).map_err(|e| storage_error(path, e))?;
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, text).map_err(|e| storage_error(path, e))?;
+        fs::rename(&temporary, path).map_err(|e| storage_error(path, e))
+    }
+
+    // Hashing is slow on purpose, so callers on an async runtime should run this and
+    // login on a blocking thread.
+    pub fn register(&self, username: &str, password: &str) -> Result<User, ChessErrors> {
+        let valid_name = !username.is_empty()
+            && username.len() <= MAX_USERNAME_LEN
+            && username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid_name {
+            return Err(ChessErrors::InvalidUsername(username.to_string()));
+        }
+        if password.chars().count() < MIN_PASSWORD_LEN {
+            return Err(ChessErrors::WeakPassword(MIN_PASSWORD_LEN.to_string()));
+        }
+        let password_hash = Argon2::default()
+            .hash_password(password.as_bytes())
+            .map_err(|e| ChessErrors::AccountsError(e.to_string()))?
+            .to_string();
+        let user = User {
+            username: username.to_string(),
+            password_hash,
+            created: now_secs(),
+            games: Vec::new(),
+            ratings: BTreeMap::new(),
+            rating_history: Vec::new(),
+        };
+        let mut users = self.users.lock().unwrap();
+        if users.contains_key(&key(username)) {
+            return Err(ChessErrors::UsernameTaken(username.to_string()));
+        }
+        users.insert(key(username), user.clone());
+        if let Err(e) = self.save(&users) {
+            users.remove(&key(username));
+            return Err(e);
+        }
+        Ok(user)
+    }
+
+    // a new session token for the user, who is returned with their name as registered
+    pub fn login(&self, username: &str, password: &str) -> Result<(String, String), ChessErrors> {
+        let user = self.users.lock().unwrap().get(&key(username)).cloned().ok_or(ChessErrors::InvalidCredentials)?;
+        let hash = PasswordHash::new(&user.password_hash).map_err(|e| ChessErrors::AccountsError(e.to_string()))?;
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .map_err(|_| ChessErrors::InvalidCredentials)?;
+        let token = secret_token()?;
+        self.sessions.lock().unwrap().insert(token.clone(), user.username.clone());
+        Ok((token, user.username))
+    }
+
+    pub fn logout(&self, token: &str) {
+        self.sessions.lock().unwrap().remove(token);
+    }
+
+    pub fn user_for_session(&self, token: &str) -> Option<String> {
+        self.sessions.lock().unwrap().get(token).cloned()
+    }
+
+    pub fn user(&self, username: &str) -> Result<User, ChessErrors> {
+        self.users
+            .lock()
+            .unwrap()
+            .get(&key(username))
+            .cloned()
+            .ok_or_else(|| ChessErrors::UserNotFound(username.to_string()))
+    }
+
+    pub fn add_game(&self, username: &str, game_id: &str) -> Result<(), ChessErrors> {
+        let mut users = self.users.lock().unwrap();
+        let user = users.get_mut(&key(username)).ok_or_else(|| ChessErrors::UserNotFound(username.to_string()))?;
+        user.games.push(game_id.to_string());
+        self.save(&users)
+    }
+
+    // rates a finished game for both players and returns their new ratings
+    pub fn record_rated_game(
+        &self,
+        white: &str,
+        black: &str,
+        pool: Pool,
+        white_score: f64,
+        game_id: &str,
+    ) -> Result<(Rating, Rating), ChessErrors> {
+        let mut users = self.users.lock().unwrap();
+        let before = |username: &str| {
+            users.get(&key(username)).map(|user| user.rating(pool)).ok_or_else(|| ChessErrors::UserNotFound(username.to_string()))
+        };
+        let (white_rating, black_rating) = rating::rate_game(&before(white)?, &before(black)?, white_score);
+        let results = [(white, black, white_score, white_rating), (black, white, 1.0 - white_score, black_rating)];
+        for (username, opponent, score, rating) in results {
+            let user = users.get_mut(&key(username)).ok_or_else(|| ChessErrors::UserNotFound(username.to_string()))?;
+            user.ratings.insert(pool, rating);
+            user.rating_history.push(RatingChange {
+                pool,
+                game_id: game_id.to_string(),
+                opponent: opponent.to_string(),
+                score,
+                rating,
+                time: now_secs(),
+            });
+        }
+        self.save(&users)?;
+        Ok((white_rating, black_rating))
+    }
+}
@@ -2,6 +2,12 @@ use std::io::{stdin,stdout,Write};
 use std::{env, io};
 use chess::visual::{GameState, PLAYER,WebGame};
 use chess::game::{Game};
+use chess::polyglot::{Book, BookPick};
+use chess::prng::Prng;
+use chess::engine::{Engine, SearchLimits, DEFAULT_HASH_MB};
+use chess::skill::{Skill, MAX_SKILL_LEVEL};
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 fn main() {
     //https://hub.qovery.com/guides/tutorial/create-a-blazingly-fast-api-in-rust-part-1/
@@ -10,6 +16,37 @@ fn main() {
 
     //  let mut chess_game = Game::game_from_turn_history(&["a2-a4","b7-b5","a4-b5","f7-f5","b5-b6","b8-c6",
     //      "b6-b7","f5-f4","a1-a7","g7-g6","d2-d4","h7-h5","d4-d5","h5-h4", "b2-b4","c6-a5", "b4-b5","c7-c5"]);  
+    // main_ascii [--book <polyglot .bin>] [--computer white|black] [--skill 0-20 | --elo 800-2400]
+    let args: Vec<String> = env::args().collect();
+    let arg_value = |name: &str| args.iter().position(|arg| arg == name).and_then(|index| args.get(index + 1));
+    let book = match arg_value("--book") {
+        Some(path) => match Book::open(path) {
+            Ok(book) => Some(book),
+            Err(e) => {
+                println!("{}", e);
+                None
+            }
+        },
+        None => None,
+    };
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos() as u64);
+    let mut prng = Prng::new(seed);
+    let computer = match arg_value("--computer").map(|player| player.as_str()) {
+        Some("white") => Some(PLAYER::WHITE),
+        Some("black") => Some(PLAYER::BLACK),
+        _ => None,
+    };
+    let skill = match arg_value("--elo").and_then(|elo| elo.parse::<u32>().ok()) {
+        Some(elo) => Skill::from_elo(elo),
+        None => Skill::new(arg_value("--skill").and_then(|level| level.parse::<u32>().ok()).unwrap_or(MAX_SKILL_LEVEL)),
+    };
+    let mut engine = Engine::new(DEFAULT_HASH_MB);
+    engine.set_skill(skill);
+    engine.set_seed(seed);
+    let computer_limits = SearchLimits {
+        movetime: Some(Duration::from_secs(1)),
+        ..SearchLimits::default()
+    };
     let mut chess_game = Game::default();   
     let d:WebGame=  (&chess_game.state).into();
     
@@ -26,17 +63,47 @@ fn main() {
             }
         };
         println!("{}", chess_game.state);
-        println!("{}", prompt);
-        let _=stdout().flush();
-        stdin().read_line(&mut move_notation).expect("Did not enter a correct move");
-        if let Some('\n')=move_notation.chars().next_back() {
-            move_notation.pop();
-        }
-        if let Some('\r')=move_notation.chars().next_back() {
-            move_notation.pop();
+        if let Some(book) = &book {
+            let book_moves: Vec<String> = book.moves(&chess_game).iter().map(|(book_move, weight)| format!("{} ({})", book_move, weight)).collect();
+            if !book_moves.is_empty() {
+                println!("Book moves: {} (type book to play one)", book_moves.join(", "));
+            }
         }
-        if move_notation == "quit" {
-            break
+        if computer == Some(chess_game.state.player_turn) {
+            let book_move = book.as_ref().and_then(|book| book.pick(&chess_game, BookPick::Weighted, &mut prng));
+            let computer_move = book_move.or_else(|| engine.search(&chess_game, &computer_limits, &AtomicBool::new(false), |_| {}).best_move);
+            match computer_move {
+                Some(computer_move) => {
+                    println!("Computer plays {}", computer_move);
+                    move_notation = computer_move;
+                }
+                None => {
+                    println!("Computer has no moves left");
+                    break;
+                }
+            }
+        } else {
+            println!("{}", prompt);
+            let _=stdout().flush();
+            stdin().read_line(&mut move_notation).expect("Did not enter a correct move");
+            if let Some('\n')=move_notation.chars().next_back() {
+                move_notation.pop();
+            }
+            if let Some('\r')=move_notation.chars().next_back() {
+                move_notation.pop();
+            }
+            if move_notation == "quit" {
+                break
+            }
+            if move_notation == "book" {
+                match book.as_ref().and_then(|book| book.pick(&chess_game, BookPick::Weighted, &mut prng)) {
+                    Some(book_move) => move_notation = book_move,
+                    None => {
+                        println!("no book move for this position");
+                        continue;
+                    }
+                }
+            }
         }
         if let Err(e) =chess_game.move_piece(&move_notation, chess_game.state.player_turn){
             println!("{}",e);
@@ -1,16 +1,94 @@
 // #[macro_use]
 // extern crate serde;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use actix_cors::Cors;
-use actix_web::web::{Data, Json, Path};
-use actix_web::{get, post, App, HttpResponse, HttpServer, Responder};
+use actix_files::Files;
+use actix_web::body::MessageBody;
+use actix_web::cookie::Cookie;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::middleware::{from_fn, Condition, Next};
+use actix_web::web::{self, Data, Json, Path, ReqData};
+use actix_web::{delete, get, post, rt, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder, ResponseError};
+use actix_ws::Message;
+use tokio::sync::broadcast;
 
+use chess::accounts::{Accounts, RatingChange};
+use chess::chess_clock::ChessClock;
+use chess::chess_errors::ChessErrors;
+use chess::engine::{self, Engine, SearchLimits, WdlEstimate};
 use chess::game::Game;
-use chess::visual::WebGame;
+use chess::lobby::{Lobby, Seek, SeekOutcome, SeekRequest, SeekStatus};
+use chess::pgn::START_FEN;
+use chess::rating::{self, Pool, Rating};
+use chess::repository::SqliteGameRepository;
+use chess::server_config::ServerConfig;
+use chess::session::{GameSession, GameStatus, GameStore};
+use chess::skill::Skill;
+use chess::time_control::TimeControl;
+use chess::time_manager::{Clock, SystemClock};
+use chess::visual::{WebGame, PLAYER};
 use serde::{Deserialize, Serialize};
 
+// a ChessErrors as the response body, e.g. {"error": "king_in_check", "message": "..."}
+#[derive(Debug)]
+pub struct ApiError(ChessErrors);
+
+impl From<ChessErrors> for ApiError {
+    fn from(error: ChessErrors) -> Self {
+        ApiError(error)
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+    pub message: String,
+}
+
+impl From<&ChessErrors> for ErrorResponse {
+    fn from(error: &ChessErrors) -> Self {
+        ErrorResponse {
+            error: error.kind().to_string(),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self.0 {
+            ChessErrors::GameNotFound(_) | ChessErrors::SeekNotFound(_) | ChessErrors::UserNotFound(_) => StatusCode::NOT_FOUND,
+            ChessErrors::GameOver(_)
+            | ChessErrors::NotYourTurn(_)
+            | ChessErrors::SeatTaken(_)
+            | ChessErrors::AlreadySeated(_)
+            | ChessErrors::UsernameTaken(_) => StatusCode::CONFLICT,
+            ChessErrors::InvalidCredentials | ChessErrors::NotLoggedIn => StatusCode::UNAUTHORIZED,
+            ChessErrors::NotAPlayer(_) | ChessErrors::InvalidInvite(_) => StatusCode::FORBIDDEN,
+            ChessErrors::RandomError(_) | ChessErrors::AccountsError(_) | ChessErrors::StorageError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(ErrorResponse::from(&self.0))
+    }
+}
+
 #[get("/chess")]
 async fn hello() -> impl Responder {
     HttpResponse::Ok().json("Hello from rust and mongoDB")
@@ -19,44 +97,1196 @@ async fn hello() -> impl Responder {
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ValidMovesRequest {
-    pub message: Option<String>,
+    // a stored game, or else the fen (or the start position) with the moves played on it
+    pub game_id: Option<String>,
+    pub fen: Option<String>,
+    pub moves: Option<Vec<String>>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ValidMovesResponse {
+    // origin square to the squares its piece can go to, e.g. "e2" -> ["e3", "e4"]
     pub moves: HashMap<String,Vec<String>>,
     pub web_game: WebGame,
 }
 
+// the legal moves of the side to move, grouped by the square they start from
+fn moves_by_origin(game: &Game) -> HashMap<String, Vec<String>> {
+    let mut moves_map: HashMap<String, Vec<String>> = HashMap::new();
+    for chess_move in game.get_validated_moves(game.state.player_turn) {
+        let targets = moves_map.entry(chess_move[0..2].to_string()).or_default();
+        // the four promotions all land on the same square
+        if !targets.iter().any(|target| target == &chess_move[3..5]) {
+            targets.push(chess_move[3..5].to_string());
+        }
+    }
+    moves_map
+}
+
 #[post("/valid_moves")]
-async fn valid_moves(req: Json<ValidMovesRequest>) -> impl Responder {
-    let mut chess_game = Game::game_from_turn_history(&["a2-a4"]); 
-    let web_game:WebGame=  (&chess_game.state).into();
-    
-    //println!("webState: {:?}", d);
-    
-    let mut moves_map = HashMap::new();
-    moves_map.insert("a2".to_string(), vec!["a3".to_string()]);
-    moves_map.insert("b2".to_string(), vec![ "b3".to_string(), "b4".to_string()]);
-    moves_map.entry("a2".to_string()).or_insert_with(|| {Vec::new()}).push("a4".to_string());
-    
-    let mut resp = ValidMovesResponse {
-        moves: moves_map,
-        web_game: web_game,
+async fn valid_moves(store: Data<GameStore>, req: Json<ValidMovesRequest>) -> Result<HttpResponse, ApiError> {
+    let req = req.into_inner();
+    let game = match req.game_id {
+        Some(game_id) => store.get(&game_id)?.game,
+        None => build_game(req.fen.as_deref(), &req.moves.unwrap_or_default())?,
+    };
+    Ok(HttpResponse::Ok().json(ValidMovesResponse {
+        moves: moves_by_origin(&game),
+        web_game: (&game.state).into(),
+    }))
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SeatChoice {
+    White,
+    Black,
+    Random,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateGameRequest {
+    pub fen: Option<String>,
+    // the creator's colour, random if not given
+    pub color: Option<SeatChoice>,
+    // only joinable through the invite in the response
+    pub private: Option<bool>,
+    pub time_control: Option<TimeControl>,
+    // changes both players' ratings, so needs a time control and accounts in both seats
+    pub rated: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JoinGameRequest {
+    pub invite: Option<String>,
+}
+
+// what a player gets on taking a seat; the token goes in the X-Player-Token header of
+// their moves, or in ?token= on the socket
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SeatResponse {
+    pub token: String,
+    pub color: String,
+    pub invite: Option<String>,
+    pub game: GameResponse,
+}
+
+const TOKEN_HEADER: &str = "X-Player-Token";
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GameResponse {
+    pub id: String,
+    pub fen: String,
+    // "white" or "black"
+    pub turn: String,
+    pub status: GameStatus,
+    pub result: Option<String>,
+    // the side offering a draw, if one is
+    pub draw_offer: Option<String>,
+    pub white_joined: bool,
+    pub black_joined: bool,
+    // the accounts playing, for those logged in
+    pub white_user: Option<String>,
+    pub black_user: Option<String>,
+    pub private: bool,
+    pub time_control: Option<TimeControl>,
+    pub clock: Option<ClockResponse>,
+    pub rated: bool,
+    pub moves: Vec<String>,
+    pub valid_moves: HashMap<String, Vec<String>>,
+    pub web_game: WebGame,
+}
+
+fn color_choice(player: Option<PLAYER>) -> SeatChoice {
+    match player {
+        Some(PLAYER::WHITE) => SeatChoice::White,
+        Some(PLAYER::BLACK) => SeatChoice::Black,
+        None => SeatChoice::Random,
+    }
+}
+
+fn choose_player(choice: SeatChoice) -> Result<PLAYER, ChessErrors> {
+    match choice {
+        SeatChoice::White => Ok(PLAYER::WHITE),
+        SeatChoice::Black => Ok(PLAYER::BLACK),
+        SeatChoice::Random => match getrandom::u32() {
+            Ok(bits) if bits & 1 == 1 => Ok(PLAYER::BLACK),
+            Ok(_) => Ok(PLAYER::WHITE),
+            Err(e) => Err(ChessErrors::RandomError(e.to_string())),
+        },
+    }
+}
+
+fn opponent(player: PLAYER) -> PLAYER {
+    match player {
+        PLAYER::WHITE => PLAYER::BLACK,
+        PLAYER::BLACK => PLAYER::WHITE,
+    }
+}
+
+fn player_name(player: PLAYER) -> String {
+    match player {
+        PLAYER::WHITE => "white".to_string(),
+        PLAYER::BLACK => "black".to_string(),
+    }
+}
+
+// each side's time left as of the response; the running one keeps going down from there
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ClockResponse {
+    pub white_ms: u64,
+    pub black_ms: u64,
+    pub running: Option<String>,
+}
+
+impl ClockResponse {
+    fn new(clock: &ChessClock, now: Duration) -> Self {
+        ClockResponse {
+            white_ms: clock.remaining(PLAYER::WHITE, now).as_millis() as u64,
+            black_ms: clock.remaining(PLAYER::BLACK, now).as_millis() as u64,
+            running: clock.running().map(player_name),
+        }
+    }
+}
+
+impl GameResponse {
+    fn new(session: &GameSession, now: Duration) -> Self {
+        GameResponse {
+            id: session.id.clone(),
+            fen: session.game.state.to_fen(),
+            turn: player_name(session.game.state.player_turn),
+            status: session.status,
+            result: session.result.clone(),
+            draw_offer: session.draw_offer.map(player_name),
+            white_joined: session.is_seated(PLAYER::WHITE),
+            black_joined: session.is_seated(PLAYER::BLACK),
+            white_user: session.user(PLAYER::WHITE).map(str::to_string),
+            black_user: session.user(PLAYER::BLACK).map(str::to_string),
+            private: session.invite.is_some(),
+            time_control: session.time_control.clone(),
+            clock: session.clock().map(|clock| ClockResponse::new(clock, now)),
+            rated: session.rated,
+            moves: session.moves().to_vec(),
+            valid_moves: if session.is_over() { HashMap::new() } else { moves_by_origin(&session.game) },
+            web_game: (&session.game.state).into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MoveRequest {
+    #[serde(rename = "move")]
+    pub chess_move: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MoveHistoryResponse {
+    pub id: String,
+    pub start_fen: String,
+    pub moves: Vec<String>,
+}
+
+#[post("/games")]
+async fn create_game(
+    store: Data<GameStore>,
+    accounts: Data<Accounts>,
+    clock: Data<dyn Clock>,
+    user: Option<ReqData<CurrentUser>>,
+    req: Option<Json<CreateGameRequest>>,
+) -> Result<HttpResponse, ApiError> {
+    let req = req.map_or(
+        CreateGameRequest { fen: None, color: None, private: None, time_control: None, rated: None },
+        |req| req.into_inner(),
+    );
+    let player = choose_player(req.color.unwrap_or(SeatChoice::Random))?;
+    let user = user.map(|user| user.into_inner().0);
+    let rated = req.rated.unwrap_or(false);
+    if rated && user.is_none() {
+        return Err(ChessErrors::NotLoggedIn.into());
+    }
+    if rated && req.time_control.is_none() {
+        return Err(ChessErrors::InvalidTimeControl("a rated game needs a time control".to_string()).into());
+    }
+    let (session, token) = store.create(req.fen.as_deref(), player, req.private.unwrap_or(false), user.as_deref())?;
+    let session = store.with(&session.id, |session| {
+        session.set_time_control(req.time_control.clone());
+        session.rated = rated;
+        Ok(session.clone())
+    })?;
+    if let Some(user) = &user {
+        accounts.add_game(user, &session.id)?;
+    }
+    Ok(HttpResponse::Created().json(SeatResponse {
+        token,
+        color: player_name(player),
+        invite: session.invite.clone(),
+        game: GameResponse::new(&session, clock.now()),
+    }))
+}
+
+#[post("/games/{id}/join")]
+async fn join_game(
+    store: Data<GameStore>,
+    live: Data<LiveGames>,
+    accounts: Data<Accounts>,
+    clock: Data<dyn Clock>,
+    user: Option<ReqData<CurrentUser>>,
+    id: Path<String>,
+    req: Option<Json<JoinGameRequest>>,
+) -> Result<HttpResponse, ApiError> {
+    let invite = req.and_then(|req| req.into_inner().invite);
+    let user = user.map(|user| user.into_inner().0);
+    let response = store.with(&id, |session| {
+        if session.rated && user.is_none() {
+            return Err(ChessErrors::NotLoggedIn);
+        }
+        let (player, token) = session.join(invite.as_deref(), user.as_deref())?;
+        let game = GameResponse::new(session, clock.now());
+        live.publish(&id, &ServerEvent::Joined { color: player_name(player) });
+        Ok(SeatResponse { token, color: player_name(player), invite: None, game })
+    })?;
+    if let Some(user) = &user {
+        accounts.add_game(user, &id)?;
+    }
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[get("/games/{id}")]
+async fn get_game(store: Data<GameStore>, clock: Data<dyn Clock>, id: Path<String>) -> Result<HttpResponse, ApiError> {
+    let session = store.get(&id)?;
+    Ok(HttpResponse::Ok().json(GameResponse::new(&session, clock.now())))
+}
+
+#[post("/games/{id}/moves")]
+async fn play_move(
+    http: HttpRequest,
+    store: Data<GameStore>,
+    live: Data<LiveGames>,
+    accounts: Data<Accounts>,
+    clock: Data<dyn Clock>,
+    id: Path<String>,
+    req: Json<MoveRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let token = http.headers().get(TOKEN_HEADER).and_then(|token| token.to_str().ok());
+    let response = apply(&store, &live, &accounts, &**clock, &id, token, ClientMessage::Move { chess_move: req.into_inner().chess_move })?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// Bumped whenever a message changes shape. Every message both ways carries it, e.g.
+// {"version": 2, "type": "move", "move": "e2-e4"}.
+//   2: the clock event
+pub const PROTOCOL_VERSION: u32 = 2;
+const LIVE_BACKLOG: usize = 64;
+
+// what the server pushes to everyone watching a game
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    // the whole game, sent first on connecting
+    State { game: GameResponse },
+    Move {
+        #[serde(rename = "move")]
+        chess_move: String,
+        game: GameResponse,
+    },
+    GameOver { status: GameStatus, result: Option<String> },
+    DrawOffer { by: String },
+    DrawDeclined { by: String },
+    // someone took the seat of that colour
+    Joined { color: String },
+    // The clocks as they stopped, just before game_over in a game with a clock. While the
+    // game goes on each move's game carries the clocks, and the running one is counted
+    // down on the client's side.
+    Clock(ClockResponse),
+    // only to the client whose message failed
+    Error(ErrorResponse),
+}
+
+// what a client can send over the socket
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Move {
+        #[serde(rename = "move")]
+        chess_move: String,
+    },
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
+}
+
+#[derive(Serialize)]
+struct OutgoingEnvelope<'a, T> {
+    version: u32,
+    #[serde(flatten)]
+    event: &'a T,
+}
+
+#[derive(Deserialize)]
+struct IncomingEnvelope {
+    version: u32,
+    #[serde(flatten)]
+    message: ClientMessage,
+}
+
+fn event_text<T: Serialize>(event: &T) -> String {
+    serde_json::to_string(&OutgoingEnvelope { version: PROTOCOL_VERSION, event }).unwrap_or_default()
+}
+
+// a feed of events per game for its sockets
+#[derive(Default)]
+pub struct LiveGames {
+    channels: Mutex<HashMap<String, broadcast::Sender<String>>>,
+}
+
+impl LiveGames {
+    fn subscribe(&self, id: &str) -> broadcast::Receiver<String> {
+        let mut channels = self.channels.lock().unwrap();
+        channels.entry(id.to_string()).or_insert_with(|| broadcast::channel(LIVE_BACKLOG).0).subscribe()
+    }
+
+    fn publish(&self, id: &str, event: &ServerEvent) {
+        if let Some(channel) = self.channels.lock().unwrap().get(id) {
+            // nobody listening is fine
+            let _ = channel.send(event_text(event));
+        }
+    }
+}
+
+// a rated game between two accounts moves both their ratings once it is over
+fn rate_game(accounts: &Accounts, session: &GameSession) -> Result<(), ChessErrors> {
+    let (Some(white), Some(black), Some(time_control)) = (session.user(PLAYER::WHITE), session.user(PLAYER::BLACK), &session.time_control) else {
+        return Ok(());
+    };
+    if !session.rated {
+        return Ok(());
+    }
+    let white_score = match session.result.as_deref() {
+        Some("1-0") => 1.0,
+        Some("0-1") => 0.0,
+        _ => 0.5,
     };
-    HttpResponse::Ok().json(resp)
+    accounts.record_rated_game(white, black, Pool::for_time_control(time_control), white_score, &session.id)?;
+    Ok(())
+}
+
+// tells everyone a game ended, and rates it
+fn game_over(live: &LiveGames, accounts: &Accounts, session: &GameSession, now: Duration) {
+    if let Some(clock) = session.clock() {
+        live.publish(&session.id, &ServerEvent::Clock(ClockResponse::new(clock, now)));
+    }
+    live.publish(&session.id, &ServerEvent::GameOver { status: session.status, result: session.result.clone() });
+    // the game stands even if the ratings could not be saved
+    if let Err(e) = rate_game(accounts, session) {
+        eprintln!("game {}: ratings not updated: {}", session.id, e);
+    }
+}
+
+// ends the game if the side to move has run out of time
+fn flag(store: &GameStore, live: &LiveGames, accounts: &Accounts, clock: &dyn Clock, id: &str) -> Result<(), ChessErrors> {
+    if let Some(session) = store.flag(id)? {
+        game_over(live, accounts, &session, clock.now());
+    }
+    Ok(())
+}
+
+// Plays a message from the holder of token on the game and tells everyone watching.
+// Without a seat's token only watching is allowed.
+fn apply(
+    store: &GameStore,
+    live: &LiveGames,
+    accounts: &Accounts,
+    clock: &dyn Clock,
+    id: &str,
+    token: Option<&str>,
+    message: ClientMessage,
+) -> Result<GameResponse, ChessErrors> {
+    // a player out of time can't move any more
+    flag(store, live, accounts, clock, id)?;
+    let now = clock.now();
+    let (response, finished) = store.with(id, |session| {
+        let player = token
+            .and_then(|token| session.seat_of(token))
+            .ok_or_else(|| ChessErrors::NotAPlayer(id.to_string()))?;
+        let event = match message {
+            ClientMessage::Move { chess_move } => {
+                session.play_as(player, &chess_move, now)?;
+                ServerEvent::Move { chess_move, game: GameResponse::new(session, now) }
+            }
+            ClientMessage::OfferDraw => {
+                session.offer_draw(player)?;
+                ServerEvent::DrawOffer { by: player_name(player) }
+            }
+            ClientMessage::AcceptDraw => {
+                session.answer_draw(player, true)?;
+                session.stop_clock(now);
+                ServerEvent::State { game: GameResponse::new(session, now) }
+            }
+            ClientMessage::DeclineDraw => {
+                session.answer_draw(player, false)?;
+                ServerEvent::DrawDeclined { by: player_name(player) }
+            }
+        };
+        // published under the lock so everyone sees events in the order they happened
+        if !matches!(event, ServerEvent::State { .. }) {
+            live.publish(id, &event);
+        }
+        Ok((GameResponse::new(session, now), session.is_over().then(|| session.clone())))
+    })?;
+    // only once the game is saved, so a game that didn't end isn't rated
+    if let Some(session) = finished {
+        game_over(live, accounts, &session, now);
+    }
+    Ok(response)
+}
+
+fn handle_text(
+    store: &GameStore,
+    live: &LiveGames,
+    accounts: &Accounts,
+    clock: &dyn Clock,
+    id: &str,
+    token: Option<&str>,
+    text: &str,
+) -> Result<(), ErrorResponse> {
+    let envelope: IncomingEnvelope = serde_json::from_str(text).map_err(|e| ErrorResponse {
+        error: "bad_message".to_string(),
+        message: e.to_string(),
+    })?;
+    if envelope.version != PROTOCOL_VERSION {
+        return Err(ErrorResponse {
+            error: "unsupported_version".to_string(),
+            message: format!("protocol version {} is not supported, use {}", envelope.version, PROTOCOL_VERSION),
+        });
+    }
+    apply(store, live, accounts, clock, id, token, envelope.message).map(|_| ()).map_err(|e| ErrorResponse::from(&e))
+}
+
+// sends the snapshot, then every event, until the socket goes away
+fn forward(mut socket: actix_ws::Session, snapshot: String, mut events: broadcast::Receiver<String>) {
+    rt::spawn(async move {
+        if socket.text(snapshot).await.is_err() {
+            return;
+        }
+        loop {
+            match events.recv().await {
+                Ok(text) => {
+                    if socket.text(text).await.is_err() {
+                        return;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    });
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SocketQuery {
+    pub token: Option<String>,
 }
 
+// actix hands each piece of shared state over as its own argument
+#[allow(clippy::too_many_arguments)]
+#[get("/games/{id}/ws")]
+async fn game_socket(
+    req: HttpRequest,
+    body: web::Payload,
+    store: Data<GameStore>,
+    live: Data<LiveGames>,
+    accounts: Data<Accounts>,
+    clock: Data<dyn Clock>,
+    id: Path<String>,
+    query: web::Query<SocketQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let id = id.into_inner();
+    // a seat's token to play, none to watch
+    let token = query.into_inner().token;
+    // subscribed before the snapshot is taken so no move falls in between
+    let events = live.subscribe(&id);
+    let session = store.get(&id).map_err(ApiError)?;
+    let (response, mut socket, mut messages) = actix_ws::handle(&req, body)?;
+
+    forward(socket.clone(), event_text(&ServerEvent::State { game: GameResponse::new(&session, clock.now()) }), events);
+    rt::spawn(async move {
+        while let Some(Ok(message)) = messages.recv().await {
+            match message {
+                Message::Text(text) => {
+                    if let Err(error) = handle_text(&store, &live, &accounts, &**clock, &id, token.as_deref(), &text) {
+                        if socket.text(event_text(&ServerEvent::Error(error))).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Message::Ping(bytes) if socket.pong(&bytes).await.is_err() => return,
+                Message::Close(reason) => {
+                    let _ = socket.close(reason).await;
+                    return;
+                }
+                _ => {}
+            }
+        }
+        let _ = socket.close(None).await;
+    });
+    Ok(response)
+}
+
+#[get("/games/{id}/moves")]
+async fn move_history(store: Data<GameStore>, id: Path<String>) -> Result<HttpResponse, ApiError> {
+    let session = store.get(&id)?;
+    Ok(HttpResponse::Ok().json(MoveHistoryResponse {
+        id: session.id.clone(),
+        start_fen: session.start_fen.clone(),
+        moves: session.moves().to_vec(),
+    }))
+}
+
+const SEEK_TOKEN_HEADER: &str = "X-Seek-Token";
+// how often stale seeks are swept out
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(5);
+// how often clocks are checked for a side out of time that isn't moving
+const FLAG_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SeekBody {
+    // "5+3": minutes, then seconds added per move
+    pub time_control: TimeControl,
+    pub color: Option<SeatChoice>,
+    pub rated: Option<bool>,
+    pub rating: Option<i32>,
+    pub min_rating: Option<i32>,
+    pub max_rating: Option<i32>,
+}
+
+// a seek as everyone in the lobby sees it
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SeekResponse {
+    pub id: String,
+    pub time_control: TimeControl,
+    pub color: SeatChoice,
+    pub rated: bool,
+    pub rating: i32,
+    pub provisional: bool,
+    pub min_rating: Option<i32>,
+    pub max_rating: Option<i32>,
+}
+
+impl From<&Seek> for SeekResponse {
+    fn from(seek: &Seek) -> Self {
+        SeekResponse {
+            id: seek.id.clone(),
+            time_control: seek.request.time_control.clone(),
+            color: color_choice(seek.request.color),
+            rated: seek.request.rated,
+            rating: seek.request.rating,
+            provisional: seek.request.provisional,
+            min_rating: seek.request.min_rating,
+            max_rating: seek.request.max_rating,
+        }
+    }
+}
+
+// "open" with the seek while it waits, its token only to the seeker when posting;
+// "paired" with the seat once there is a game
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SeekStatusResponse {
+    Open { seek: SeekResponse, token: Option<String> },
+    Paired { seat: Box<SeatResponse> },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LobbyEvent {
+    // every open seek, sent first on connecting
+    Seeks { seeks: Vec<SeekResponse> },
+    SeekAdded { seek: SeekResponse },
+    // reason is "paired", "cancelled" or "expired"
+    SeekRemoved { id: String, reason: String, game_id: Option<String> },
+}
+
+// the lobby's live feed
+pub struct LobbyFeed {
+    channel: broadcast::Sender<String>,
+}
+
+impl Default for LobbyFeed {
+    fn default() -> Self {
+        LobbyFeed { channel: broadcast::channel(LIVE_BACKLOG).0 }
+    }
+}
+
+impl LobbyFeed {
+    fn publish(&self, event: &LobbyEvent) {
+        let _ = self.channel.send(event_text(event));
+    }
+}
+
+#[post("/lobby/seeks")]
+async fn post_seek(
+    store: Data<GameStore>,
+    lobby: Data<Lobby>,
+    feed: Data<LobbyFeed>,
+    clock: Data<dyn Clock>,
+    accounts: Data<Accounts>,
+    user: Option<ReqData<CurrentUser>>,
+    req: Json<SeekBody>,
+) -> Result<HttpResponse, ApiError> {
+    let req = req.into_inner();
+    let user = user.map(|user| user.into_inner().0);
+    // a rating needs someone to belong to
+    if req.rated.unwrap_or(false) && user.is_none() {
+        return Err(ChessErrors::NotLoggedIn.into());
+    }
+    // a logged in seeker plays at their own rating, whatever the body says
+    let (rating, provisional) = match &user {
+        Some(user) => {
+            let rating = accounts.user(user)?.rating(Pool::for_time_control(&req.time_control));
+            (rating.rating.round() as i32, rating.is_provisional())
+        }
+        None => (req.rating.unwrap_or(rating::DEFAULT_RATING as i32), true),
+    };
+    let request = SeekRequest {
+        time_control: req.time_control.clone(),
+        color: match req.color.unwrap_or(SeatChoice::Random) {
+            SeatChoice::White => Some(PLAYER::WHITE),
+            SeatChoice::Black => Some(PLAYER::BLACK),
+            SeatChoice::Random => None,
+        },
+        rated: req.rated.unwrap_or(false),
+        rating,
+        provisional,
+        min_rating: req.min_rating,
+        max_rating: req.max_rating,
+        user,
+    };
+    let mut seat = None;
+    let outcome = lobby.post(request.clone(), clock.now(), |waiting| {
+        // the waiting seeker's wish comes first
+        let waiting_player = match (waiting.request.color, request.color) {
+            (Some(player), _) => player,
+            (None, Some(player)) => opponent(player),
+            (None, None) => choose_player(SeatChoice::Random)?,
+        };
+        let (session, waiting_token) = store.create(None, waiting_player, false, waiting.request.user.as_deref())?;
+        let player = opponent(waiting_player);
+        let (token, session) = store.with(&session.id, |session| {
+            session.set_time_control(Some(request.time_control.clone()));
+            session.rated = request.rated;
+            Ok((session.take_seat(player, request.user.as_deref())?, session.clone()))
+        })?;
+        for user in [&waiting.request.user, &request.user].into_iter().flatten() {
+            accounts.add_game(user, &session.id)?;
+        }
+        seat = Some(SeatResponse { token, color: player_name(player), invite: None, game: GameResponse::new(&session, clock.now()) });
+        Ok((session.id, waiting_player, waiting_token))
+    })?;
+    match outcome {
+        SeekOutcome::Matched(waiting) => {
+            let seat = seat.expect("a match seats the new seeker");
+            feed.publish(&LobbyEvent::SeekRemoved {
+                id: waiting.id,
+                reason: "paired".to_string(),
+                game_id: Some(seat.game.id.clone()),
+            });
+            Ok(HttpResponse::Ok().json(SeekStatusResponse::Paired { seat: Box::new(seat) }))
+        }
+        SeekOutcome::Posted(seek) => {
+            feed.publish(&LobbyEvent::SeekAdded { seek: SeekResponse::from(&seek) });
+            Ok(HttpResponse::Created().json(SeekStatusResponse::Open { seek: SeekResponse::from(&seek), token: Some(seek.token) }))
+        }
+    }
+}
+
+#[get("/lobby/seeks")]
+async fn list_seeks(lobby: Data<Lobby>) -> impl Responder {
+    let seeks: Vec<SeekResponse> = lobby.open_seeks().iter().map(SeekResponse::from).collect();
+    HttpResponse::Ok().json(seeks)
+}
+
+// polled by the seeker, which keeps the seek from going stale
+#[get("/lobby/seeks/{id}")]
+async fn seek_status(
+    http: HttpRequest,
+    store: Data<GameStore>,
+    lobby: Data<Lobby>,
+    clock: Data<dyn Clock>,
+    id: Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let token = http.headers().get(SEEK_TOKEN_HEADER).and_then(|token| token.to_str().ok()).unwrap_or_default();
+    match lobby.status(&id, token, clock.now())? {
+        SeekStatus::Open => {
+            let seek = lobby
+                .open_seeks()
+                .into_iter()
+                .find(|seek| seek.id == *id)
+                .ok_or_else(|| ChessErrors::SeekNotFound(id.to_string()))?;
+            Ok(HttpResponse::Ok().json(SeekStatusResponse::Open { seek: SeekResponse::from(&seek), token: None }))
+        }
+        SeekStatus::Paired(pairing) => {
+            let session = store.get(&pairing.game_id)?;
+            Ok(HttpResponse::Ok().json(SeekStatusResponse::Paired {
+                seat: Box::new(SeatResponse {
+                    token: pairing.token,
+                    color: player_name(pairing.color),
+                    invite: None,
+                    game: GameResponse::new(&session, clock.now()),
+                }),
+            }))
+        }
+    }
+}
+
+#[delete("/lobby/seeks/{id}")]
+async fn cancel_seek(http: HttpRequest, lobby: Data<Lobby>, feed: Data<LobbyFeed>, id: Path<String>) -> Result<HttpResponse, ApiError> {
+    let token = http.headers().get(SEEK_TOKEN_HEADER).and_then(|token| token.to_str().ok()).unwrap_or_default();
+    let seek = lobby.cancel(&id, token)?;
+    feed.publish(&LobbyEvent::SeekRemoved { id: seek.id, reason: "cancelled".to_string(), game_id: None });
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/lobby/ws")]
+async fn lobby_socket(req: HttpRequest, body: web::Payload, lobby: Data<Lobby>, feed: Data<LobbyFeed>) -> Result<HttpResponse, actix_web::Error> {
+    let events = feed.channel.subscribe();
+    let seeks = lobby.open_seeks().iter().map(SeekResponse::from).collect();
+    let (response, mut socket, mut messages) = actix_ws::handle(&req, body)?;
+    forward(socket.clone(), event_text(&LobbyEvent::Seeks { seeks }), events);
+    // the feed only talks, but pings still need answering
+    rt::spawn(async move {
+        while let Some(Ok(message)) = messages.recv().await {
+            match message {
+                Message::Ping(bytes) if socket.pong(&bytes).await.is_err() => return,
+                Message::Close(reason) => {
+                    let _ = socket.close(reason).await;
+                    return;
+                }
+                _ => {}
+            }
+        }
+    });
+    Ok(response)
+}
+
+// the logged in user, put on the request by attach_user
+#[derive(Clone, Debug)]
+pub struct CurrentUser(pub String);
+
+const SESSION_COOKIE: &str = "session";
+
+// the session token from "Authorization: Bearer ..." or the session cookie
+fn session_token(req: &HttpRequest) -> Option<String> {
+    let bearer = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string);
+    bearer.or_else(|| req.cookie(SESSION_COOKIE).map(|cookie| cookie.value().to_string()))
+}
+
+// middleware: requests with a live session carry a CurrentUser
+async fn attach_user(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let user = match (session_token(req.request()), req.app_data::<Data<Accounts>>()) {
+        (Some(token), Some(accounts)) => accounts.user_for_session(&token),
+        _ => None,
+    };
+    if let Some(user) = user {
+        req.extensions_mut().insert(CurrentUser(user));
+    }
+    next.call(req).await
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CredentialsRequest {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LoginResponse {
+    pub username: String,
+    // also set as the session cookie; send it as a bearer token where cookies don't go
+    pub token: String,
+}
+
+// one of a user's games, as their profile lists it
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GameSummary {
+    pub id: String,
+    pub color: String,
+    pub opponent: Option<String>,
+    pub status: GameStatus,
+    pub result: Option<String>,
+    pub time_control: Option<TimeControl>,
+    pub rated: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RatingResponse {
+    pub rating: i32,
+    pub deviation: i32,
+    pub volatility: f64,
+    pub games: u32,
+    // too few games yet to trust, shown as "1500?"
+    pub provisional: bool,
+}
+
+impl From<&Rating> for RatingResponse {
+    fn from(rating: &Rating) -> Self {
+        RatingResponse {
+            rating: rating.rating.round() as i32,
+            deviation: rating.deviation.round() as i32,
+            volatility: rating.volatility,
+            games: rating.games,
+            provisional: rating.is_provisional(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProfileResponse {
+    pub username: String,
+    pub created: u64,
+    // every pool, unplayed ones at the starting rating
+    pub ratings: BTreeMap<Pool, RatingResponse>,
+    // newest first
+    pub games: Vec<GameSummary>,
+}
+
+fn profile(store: &GameStore, accounts: &Accounts, username: &str) -> Result<ProfileResponse, ChessErrors> {
+    let user = accounts.user(username)?;
+    let games = user
+        .games
+        .iter()
+        .rev()
+        .filter_map(|id| store.get(id).ok())
+        .filter_map(|session| {
+            let color = [PLAYER::WHITE, PLAYER::BLACK].into_iter().find(|player| session.user(*player) == Some(user.username.as_str()))?;
+            Some(GameSummary {
+                id: session.id.clone(),
+                color: player_name(color),
+                opponent: session.user(opponent(color)).map(str::to_string),
+                status: session.status,
+                result: session.result.clone(),
+                time_control: session.time_control.clone(),
+                rated: session.rated,
+            })
+        })
+        .collect();
+    let ratings = Pool::ALL.into_iter().map(|pool| (pool, RatingResponse::from(&user.rating(pool)))).collect();
+    Ok(ProfileResponse { username: user.username, created: user.created, ratings, games })
+}
+
+#[post("/accounts/register")]
+async fn register(accounts: Data<Accounts>, req: Json<CredentialsRequest>) -> Result<HttpResponse, ApiError> {
+    let req = req.into_inner();
+    // hashing takes a while, keep it off the server's threads
+    let user = web::block(move || accounts.register(&req.username, &req.password))
+        .await
+        .map_err(|e| ChessErrors::AccountsError(e.to_string()))??;
+    Ok(HttpResponse::Created().json(serde_json::json!({ "username": user.username })))
+}
+
+#[post("/accounts/login")]
+async fn login(accounts: Data<Accounts>, req: Json<CredentialsRequest>) -> Result<HttpResponse, ApiError> {
+    let req = req.into_inner();
+    let (token, username) = web::block(move || accounts.login(&req.username, &req.password))
+        .await
+        .map_err(|e| ChessErrors::AccountsError(e.to_string()))??;
+    let cookie = Cookie::build(SESSION_COOKIE, token.clone()).path("/").http_only(true).finish();
+    Ok(HttpResponse::Ok().cookie(cookie).json(LoginResponse { username, token }))
+}
+
+#[post("/accounts/logout")]
+async fn logout(http: HttpRequest, accounts: Data<Accounts>) -> impl Responder {
+    if let Some(token) = session_token(&http) {
+        accounts.logout(&token);
+    }
+    let mut cookie = Cookie::build(SESSION_COOKIE, "").path("/").finish();
+    cookie.make_removal();
+    HttpResponse::NoContent().cookie(cookie).finish()
+}
+
+#[get("/me")]
+async fn me(store: Data<GameStore>, accounts: Data<Accounts>, user: Option<ReqData<CurrentUser>>) -> Result<HttpResponse, ApiError> {
+    let user = user.ok_or(ChessErrors::NotLoggedIn)?;
+    Ok(HttpResponse::Ok().json(profile(&store, &accounts, &user.0)?))
+}
+
+#[get("/users/{username}")]
+async fn user_profile(store: Data<GameStore>, accounts: Data<Accounts>, username: Path<String>) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(profile(&store, &accounts, &username)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RatingHistoryQuery {
+    // one pool's history, every pool's if not given
+    pub pool: Option<String>,
+}
+
+// a rating after one rated game
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RatingHistoryEntry {
+    pub pool: Pool,
+    pub game_id: String,
+    pub opponent: String,
+    pub score: f64,
+    pub rating: RatingResponse,
+    pub time: u64,
+}
+
+impl From<&RatingChange> for RatingHistoryEntry {
+    fn from(change: &RatingChange) -> Self {
+        RatingHistoryEntry {
+            pool: change.pool,
+            game_id: change.game_id.clone(),
+            opponent: change.opponent.clone(),
+            score: change.score,
+            rating: RatingResponse::from(&change.rating),
+            time: change.time,
+        }
+    }
+}
+
+// oldest first
+#[get("/users/{username}/ratings/history")]
+async fn rating_history(
+    accounts: Data<Accounts>,
+    username: Path<String>,
+    query: web::Query<RatingHistoryQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let pool: Option<Pool> = query.into_inner().pool.map(|pool| pool.parse()).transpose()?;
+    let user = accounts.user(&username)?;
+    let history: Vec<RatingHistoryEntry> = user
+        .rating_history
+        .iter()
+        .filter(|change| pool.is_none_or(|pool| change.pool == pool))
+        .map(RatingHistoryEntry::from)
+        .collect();
+    Ok(HttpResponse::Ok().json(history))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EngineMoveRequest {
+    pub fen: Option<String>,
+    // played from the fen, or from the start, e.g. ["e2-e4", "e7-e5"]
+    pub moves: Option<Vec<String>>,
+    // 0-20, or an Elo that picks the level
+    pub skill_level: Option<u32>,
+    pub elo: Option<u32>,
+    pub movetime_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EngineMoveResponse {
+    #[serde(rename = "move")]
+    pub chess_move: Option<String>,
+    pub score: String,
+}
+
+// the fen, or the start position, with the moves played on it
+fn build_game(fen: Option<&str>, moves: &[String]) -> Result<Game, ChessErrors> {
+    let mut game = Game::game_from_fen(fen.unwrap_or(START_FEN))?;
+    for chess_move in moves {
+        game.make_move(chess_move)?;
+    }
+    Ok(game)
+}
+
+// how long /engine_move thinks unless asked, and the most it will
+const DEFAULT_ENGINE_MOVE_MS: u64 = 1000;
+const MAX_ENGINE_MOVE_MS: u64 = 10_000;
+
+#[post("/engine_move")]
+async fn engine_move(req: Json<EngineMoveRequest>) -> impl Responder {
+    let req = req.into_inner();
+    let game = match build_game(req.fen.as_deref(), &req.moves.unwrap_or_default()) {
+        Ok(game) => game,
+        Err(e) => return ApiError(e).error_response(),
+    };
+    let skill = match (req.elo, req.skill_level) {
+        (Some(elo), _) => Skill::from_elo(elo),
+        (None, Some(level)) => Skill::new(level),
+        (None, None) => Skill::default(),
+    };
+    let limits = SearchLimits {
+        movetime: Some(Duration::from_millis(req.movetime_ms.unwrap_or(DEFAULT_ENGINE_MOVE_MS).min(MAX_ENGINE_MOVE_MS))),
+        ..SearchLimits::default()
+    };
+    // searching takes a while, keep it off the server's threads
+    let result = web::block(move || {
+        let mut engine = Engine::default();
+        engine.set_skill(skill);
+        engine.set_seed(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos() as u64));
+        engine.search(&game, &limits, &AtomicBool::new(false), |_| {})
+    })
+    .await;
+    match result {
+        Ok(result) => HttpResponse::Ok().json(EngineMoveResponse {
+            chess_move: result.best_move,
+            score: result.score.to_string(),
+        }),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// anyone can ask, so every analysis stops at whichever of these it reaches first
+const DEFAULT_ANALYSIS_DEPTH: u32 = 5;
+const MAX_ANALYSIS_DEPTH: u32 = 8;
+const DEFAULT_ANALYSIS_MS: u64 = 2000;
+const MAX_ANALYSIS_MS: u64 = 10_000;
+const MAX_ANALYSIS_LINES: usize = 10;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AnalyseRequest {
+    pub fen: Option<String>,
+    pub moves: Option<Vec<String>>,
+    pub depth: Option<u32>,
+    pub multipv: Option<usize>,
+    pub movetime_ms: Option<u64>,
+    // include the win/draw/loss estimate with every line
+    pub wdl: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AnalysisLineResponse {
+    pub score: String,
+    pub pv: Vec<String>,
+    pub wdl: Option<WdlEstimate>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AnalyseResponse {
+    pub depth: u32,
+    pub lines: Vec<AnalysisLineResponse>,
+}
+
+#[post("/analyse")]
+async fn analyse(req: Json<AnalyseRequest>) -> impl Responder {
+    let req = req.into_inner();
+    let game = match build_game(req.fen.as_deref(), &req.moves.unwrap_or_default()) {
+        Ok(game) => game,
+        Err(e) => return ApiError(e).error_response(),
+    };
+    let limits = SearchLimits {
+        depth: Some(req.depth.unwrap_or(DEFAULT_ANALYSIS_DEPTH).clamp(1, MAX_ANALYSIS_DEPTH)),
+        movetime: Some(Duration::from_millis(req.movetime_ms.unwrap_or(DEFAULT_ANALYSIS_MS).min(MAX_ANALYSIS_MS))),
+        multipv: req.multipv.unwrap_or(3).clamp(1, MAX_ANALYSIS_LINES),
+        ..SearchLimits::default()
+    };
+    let show_wdl = req.wdl.unwrap_or(false);
+    let lines = web::block(move || engine::analyse(&game, &limits)).await;
+    match lines {
+        Ok(lines) => HttpResponse::Ok().json(AnalyseResponse {
+            // the depth reached, which is less than asked for when the time ran out
+            depth: lines.first().map_or(0, |line| line.depth),
+            lines: lines
+                .into_iter()
+                .map(|line| AnalysisLineResponse {
+                    score: line.score.to_string(),
+                    pv: line.pv,
+                    wdl: Some(line.wdl).filter(|_| show_wdl),
+                })
+                .collect(),
+        }),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// the front end's settings, loaded before chess.js
+#[get("/config.js")]
+async fn front_end_config(config: Data<ServerConfig>) -> impl Responder {
+    let api_base = serde_json::to_string(&config.api_base).unwrap_or_default();
+    HttpResponse::Ok().content_type("text/javascript").body(format!("window.CHESS_API = {};\n", api_base))
+}
+
+// pages from the configured origins may call the api, with their cookies
+fn cors(origins: &[String]) -> Cors {
+    let cors = Cors::default().allow_any_method().allow_any_header().max_age(3600);
+    if origins.iter().any(|origin| origin == "*") {
+        return cors.allow_any_origin();
+    }
+    origins.iter().fold(cors.supports_credentials(), |cors, origin| cors.allowed_origin(origin))
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    HttpServer::new(|| {
+    // see ServerConfig for the flags, variables and config file keys
+    let args: Vec<String> = std::env::args().collect();
+    let config = ServerConfig::load(&args, |name| std::env::var(name).ok()).map_err(|e| std::io::Error::other(e.to_string()))?;
+    let accounts = Data::new(Accounts::open(&config.users).map_err(|e| std::io::Error::other(e.to_string()))?);
+    let repository =
+        SqliteGameRepository::open(std::path::Path::new(&config.games)).map_err(|e| std::io::Error::other(e.to_string()))?;
+    let clock: Data<dyn Clock> = Data::from(Arc::new(SystemClock::default()) as Arc<dyn Clock>);
+    let store =
+        Data::new(GameStore::open(Box::new(repository), clock.clone().into_inner()).map_err(|e| std::io::Error::other(e.to_string()))?);
+    let live = Data::new(LiveGames::default());
+    let lobby = Data::new(Lobby::default());
+    let feed = Data::new(LobbyFeed::default());
+    let (bind, port) = (config.bind.clone(), config.port);
+    let config = Data::new(config);
+
+    let (expiring, expiry_feed, expiry_clock) = (lobby.clone(), feed.clone(), clock.clone());
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(EXPIRY_INTERVAL);
+        loop {
+            interval.tick().await;
+            for seek in expiring.expire(expiry_clock.now()) {
+                expiry_feed.publish(&LobbyEvent::SeekRemoved { id: seek.id, reason: "expired".to_string(), game_id: None });
+            }
+        }
+    });
+    let (flagging, flag_live, flag_accounts, flag_clock) = (store.clone(), live.clone(), accounts.clone(), clock.clone());
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(FLAG_INTERVAL);
+        loop {
+            interval.tick().await;
+            for id in flagging.out_of_time() {
+                if let Err(e) = flag(&flagging, &flag_live, &flag_accounts, &**flag_clock, &id) {
+                    eprintln!("game {}: could not flag: {}", id, e);
+                }
+            }
+        }
+    });
+    HttpServer::new(move || {
         App::new()
-            .wrap(Cors::permissive() )
+            .wrap(from_fn(attach_user))
+            // same origin requests need no cors at all
+            .wrap(Condition::new(!config.cors_origins.is_empty(), cors(&config.cors_origins)))
+            .app_data(store.clone())
+            .app_data(live.clone())
+            .app_data(lobby.clone())
+            .app_data(feed.clone())
+            .app_data(clock.clone())
+            .app_data(accounts.clone())
+            .app_data(config.clone())
             .service(hello)
             .service(valid_moves)
+            .service(create_game)
+            .service(get_game)
+            .service(join_game)
+            .service(play_move)
+            .service(move_history)
+            .service(game_socket)
+            .service(post_seek)
+            .service(list_seeks)
+            .service(seek_status)
+            .service(cancel_seek)
+            .service(lobby_socket)
+            .service(register)
+            .service(login)
+            .service(logout)
+            .service(me)
+            .service(user_profile)
+            .service(rating_history)
+            .service(engine_move)
+            .service(analyse)
+            .service(front_end_config)
+            // last, so the api's routes come first
+            .service(Files::new("/", &config.static_dir).index_file("chess.html"))
     })
-    .bind(("127.0.0.1", 9090))?
+    .bind((bind, port))?
     .run()
     .await
 
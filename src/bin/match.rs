@@ -0,0 +1,542 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{self, Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use chess::chess_notation_utilities;
+use chess::engine::{Engine, SearchLimits, DEFAULT_HASH_MB};
+use chess::evaluation::EvalBackend;
+use chess::game::{Game, GameEnd};
+use chess::nnue::Network;
+use chess::pgn::{PgnGame, START_FEN};
+use chess::skill::Skill;
+use chess::visual::PLAYER;
+use chess::zobrist;
+
+const DEFAULT_PGN: &str = "match.pgn";
+const MAX_PLIES: usize = 600;
+// allowed past zero on the clock before a flag falls, for the pipe and process switches
+const TIME_MARGIN_MS: i64 = 50;
+// used when no --openings file is given, each one is played with both colours
+const DEFAULT_OPENINGS: [&str; 8] = [
+    "e2e4 e7e5 g1f3 b8c6 f1b5",
+    "e2e4 c7c5 g1f3 d7d6 d2d4",
+    "e2e4 e7e6 d2d4 d7d5",
+    "e2e4 c7c6 d2d4 d7d5",
+    "d2d4 d7d5 c2c4 e7e6",
+    "d2d4 g8f6 c2c4 g7g6 b1c3",
+    "c2c4 e7e5 b1c3",
+    "g1f3 d7d5 g2g3",
+];
+
+#[derive(Clone, Copy, Debug)]
+enum TimeControl {
+    // milliseconds on the clock and added after every move
+    Clock { base: u64, increment: u64 },
+    MoveTime(u64),
+    Nodes(u64),
+    Depth(u32),
+}
+
+// "40+0.4" is 40 seconds plus 0.4 a move, "60" has no increment
+fn parse_tc(tc: &str) -> Option<TimeControl> {
+    let mut parts = tc.splitn(2, '+');
+    let base = parts.next()?.parse::<f64>().ok()?;
+    let increment = match parts.next() {
+        Some(increment) => increment.parse::<f64>().ok()?,
+        None => 0.0,
+    };
+    Some(TimeControl::Clock { base: (base * 1000.0) as u64, increment: (increment * 1000.0) as u64 })
+}
+
+struct Opening {
+    fen: String,
+    moves: Vec<String>,
+}
+
+// a FEN, or moves from the start in UCI or our own notation
+fn parse_opening(line: &str) -> Result<Opening, String> {
+    if line.contains('/') {
+        Game::game_from_fen(line).map_err(|e| e.to_string())?;
+        return Ok(Opening { fen: line.to_string(), moves: Vec::new() });
+    }
+    let mut game = Game::default();
+    let mut moves = Vec::new();
+    for token in line.split_whitespace() {
+        let chess_move = if token.contains('-') {
+            token.to_string()
+        } else {
+            chess_notation_utilities::uci_to_move_notation(token).map_err(|e| e.to_string())?
+        };
+        game.make_move(&chess_move).map_err(|e| format!("{}: {}", token, e))?;
+        moves.push(chess_move);
+    }
+    Ok(Opening { fen: START_FEN.to_string(), moves })
+}
+
+fn load_openings(path: Option<&String>) -> Result<Vec<Opening>, String> {
+    let text = match path {
+        Some(path) => fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?,
+        None => DEFAULT_OPENINGS.join("\n"),
+    };
+    let openings: Vec<Opening> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| parse_opening(line).map_err(|e| format!("opening \"{}\": {}", line, e)))
+        .collect::<Result<_, _>>()?;
+    if openings.is_empty() {
+        return Err("no openings".to_string());
+    }
+    Ok(openings)
+}
+
+// an engine running as its own process, spoken to over UCI
+struct UciProcess {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl UciProcess {
+    fn start(path: &str, options: &[(String, String)]) -> io::Result<(UciProcess, Option<String>)> {
+        let mut child = Command::new(path).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut engine = UciProcess { child, stdin, stdout };
+        engine.send("uci")?;
+        let mut name = None;
+        let id_lines = engine.read_until("uciok")?;
+        for line in id_lines {
+            if let Some(id_name) = line.strip_prefix("id name ") {
+                name = Some(id_name.trim().to_string());
+            }
+        }
+        for (option, value) in options {
+            engine.send(&format!("setoption name {} value {}", option, value))?;
+        }
+        engine.sync()?;
+        Ok((engine, name))
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        writeln!(self.stdin, "{}", command)?;
+        self.stdin.flush()
+    }
+
+    // every line up to and including the one starting with prefix
+    fn read_until(&mut self, prefix: &str) -> io::Result<Vec<String>> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.stdout.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "engine exited"));
+            }
+            let done = line.starts_with(prefix);
+            lines.push(line.trim_end().to_string());
+            if done {
+                return Ok(lines);
+            }
+        }
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.send("isready")?;
+        self.read_until("readyok").map(|_| ())
+    }
+}
+
+impl Drop for UciProcess {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.child.wait();
+    }
+}
+
+enum Player {
+    Builtin(Box<Engine>),
+    External(UciProcess),
+}
+
+struct Contestant {
+    name: String,
+    player: Player,
+}
+
+// "builtin[,hash=MB,threads=N,skill=N,elo=N,evalfile=net.bin,name=X]" or
+// "uci:/path/to/engine[,Option=value...,name=X]"
+fn parse_contestant(spec: &str) -> Result<Contestant, String> {
+    let mut parts = spec.split(',');
+    let kind = parts.next().unwrap_or_default();
+    let mut options = Vec::new();
+    let mut name = None;
+    for part in parts {
+        let (key, value) = part.split_once('=').ok_or_else(|| format!("{}: expected key=value", part))?;
+        if key == "name" {
+            name = Some(value.to_string());
+        } else {
+            options.push((key.to_string(), value.to_string()));
+        }
+    }
+    if let Some(path) = kind.strip_prefix("uci:") {
+        let (process, id_name) = UciProcess::start(path, &options).map_err(|e| format!("{}: {}", path, e))?;
+        return Ok(Contestant {
+            name: name.or(id_name).unwrap_or_else(|| path.to_string()),
+            player: Player::External(process),
+        });
+    }
+    if kind != "builtin" {
+        return Err(format!("{}: an engine is \"builtin\" or \"uci:<path>\"", kind));
+    }
+    let mut engine = Engine::new(DEFAULT_HASH_MB);
+    for (key, value) in &options {
+        let number = || value.parse::<u32>().map_err(|_| format!("{}: not a number", value));
+        match key.as_str() {
+            "hash" => engine.resize_hash(number()? as usize),
+            "threads" => engine.set_threads(number()? as usize),
+            "skill" => engine.set_skill(Skill::new(number()?)),
+            "elo" => engine.set_skill(Skill::from_elo(number()?)),
+            "evalfile" => engine.set_eval_backend(EvalBackend::Nnue(Network::open(value).map_err(|e| e.to_string())?)),
+            _ => return Err(format!("unknown builtin option {}", key)),
+        }
+    }
+    Ok(Contestant {
+        name: name.unwrap_or_else(|| spec.to_string()),
+        player: Player::Builtin(Box::new(engine)),
+    })
+}
+
+impl Contestant {
+    fn new_game(&mut self) -> Result<(), String> {
+        match &mut self.player {
+            Player::Builtin(engine) => engine.new_game(),
+            Player::External(process) => {
+                process.send("ucinewgame").map_err(|e| e.to_string())?;
+                process.sync().map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(())
+    }
+
+    // clocks are white's and black's milliseconds left
+    fn best_move(&mut self, fen: &str, game: &Game, tc: TimeControl, clocks: [i64; 2]) -> Result<Option<String>, String> {
+        let (wtime, btime) = (clocks[0].max(1) as u64, clocks[1].max(1) as u64);
+        match &mut self.player {
+            Player::Builtin(engine) => {
+                let mut limits = SearchLimits::default();
+                match tc {
+                    TimeControl::Clock { increment, .. } => {
+                        limits.wtime = Some(Duration::from_millis(wtime));
+                        limits.btime = Some(Duration::from_millis(btime));
+                        limits.winc = Duration::from_millis(increment);
+                        limits.binc = Duration::from_millis(increment);
+                    }
+                    TimeControl::MoveTime(movetime) => limits.movetime = Some(Duration::from_millis(movetime)),
+                    TimeControl::Nodes(nodes) => limits.nodes = Some(nodes),
+                    TimeControl::Depth(depth) => limits.depth = Some(depth),
+                }
+                Ok(engine.search(game, &limits, &AtomicBool::new(false), |_| {}).best_move)
+            }
+            Player::External(process) => {
+                let moves: Vec<String> = game.turn_history.iter().map(|chess_move| chess_notation_utilities::move_notation_to_uci(chess_move)).collect();
+                let position = if moves.is_empty() {
+                    format!("position fen {}", fen)
+                } else {
+                    format!("position fen {} moves {}", fen, moves.join(" "))
+                };
+                let go = match tc {
+                    TimeControl::Clock { increment, .. } => format!("go wtime {} btime {} winc {} binc {}", wtime, btime, increment, increment),
+                    TimeControl::MoveTime(movetime) => format!("go movetime {}", movetime),
+                    TimeControl::Nodes(nodes) => format!("go nodes {}", nodes),
+                    TimeControl::Depth(depth) => format!("go depth {}", depth),
+                };
+                process.send(&position).and_then(|_| process.send(&go)).map_err(|e| e.to_string())?;
+                let lines = process.read_until("bestmove").map_err(|e| e.to_string())?;
+                let best_move = lines.last().and_then(|line| line.split_whitespace().nth(1)).unwrap_or("0000");
+                Ok(chess_notation_utilities::uci_to_move_notation(best_move).ok())
+            }
+        }
+    }
+}
+
+// the result for white, and why, if the rules end the game here
+fn game_over(game: &Game, keys: &[u64]) -> Option<(f64, &'static str)> {
+    match (game.game_end(keys), game.state.player_turn) {
+        (Some(GameEnd::Checkmate), PLAYER::WHITE) => Some((0.0, "Black mates")),
+        (Some(GameEnd::Checkmate), PLAYER::BLACK) => Some((1.0, "White mates")),
+        (Some(GameEnd::Stalemate), _) => Some((0.5, "Stalemate")),
+        (Some(GameEnd::Repetition), _) => Some((0.5, "Draw by repetition")),
+        (Some(GameEnd::FiftyMoves), _) => Some((0.5, "Draw by fifty move rule")),
+        (Some(GameEnd::InsufficientMaterial), _) => Some((0.5, "Draw by insufficient material")),
+        (None, _) if game.turn_history.len() >= MAX_PLIES => Some((0.5, "Draw by adjudication")),
+        (None, _) => None,
+    }
+}
+
+// plays one game and returns white's result, why it ended and the moves after the fen
+fn play_game(white: &mut Contestant, black: &mut Contestant, opening: &Opening, tc: TimeControl) -> (f64, String, Vec<String>) {
+    let mut game = Game::game_from_fen(&opening.fen).expect("opening was checked");
+    for chess_move in &opening.moves {
+        game.make_move(chess_move).expect("opening was checked");
+    }
+    let mut keys = vec![zobrist::position_key(&game.state)];
+    let mut clocks = match tc {
+        TimeControl::Clock { base, .. } => [base as i64, base as i64],
+        _ => [0, 0],
+    };
+    for contestant in [&mut *white, &mut *black] {
+        if let Err(e) = contestant.new_game() {
+            return (0.5, format!("{} failed: {}", contestant.name, e), game.turn_history);
+        }
+    }
+    // from here the loser is whoever is to move
+    let lost = |game: &Game| if game.state.player_turn == PLAYER::WHITE { 0.0 } else { 1.0 };
+    loop {
+        if let Some((result, reason)) = game_over(&game, &keys) {
+            return (result, reason.to_string(), game.turn_history);
+        }
+        let side = if game.state.player_turn == PLAYER::WHITE { 0 } else { 1 };
+        let contestant = if side == 0 { &mut *white } else { &mut *black };
+        let started = Instant::now();
+        let best_move = contestant.best_move(&opening.fen, &game, tc, clocks);
+        let elapsed = started.elapsed().as_millis() as i64;
+        let chess_move = match best_move {
+            Ok(Some(chess_move)) => chess_move,
+            Ok(None) => return (lost(&game), format!("{} returned no move", contestant.name), game.turn_history),
+            Err(e) => return (lost(&game), format!("{} failed: {}", contestant.name, e), game.turn_history),
+        };
+        if let TimeControl::Clock { increment, .. } = tc {
+            clocks[side] -= elapsed;
+            if clocks[side] < -TIME_MARGIN_MS {
+                return (lost(&game), format!("{} loses on time", contestant.name), game.turn_history);
+            }
+            clocks[side] += increment as i64;
+        }
+        if game.make_move(&chess_move).is_err() {
+            return (lost(&game), format!("{} played an illegal move {}", contestant.name, chess_move), game.turn_history);
+        }
+        keys.push(zobrist::position_key(&game.state));
+    }
+}
+
+#[derive(Default)]
+struct Score {
+    wins: u32,
+    draws: u32,
+    losses: u32,
+}
+
+impl Score {
+    fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    fn points(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    // the spread of a single game's result around the mean
+    fn variance(&self) -> f64 {
+        let mean = self.points();
+        let n = self.games() as f64;
+        (self.wins as f64 * (1.0 - mean).powi(2) + self.draws as f64 * (0.5 - mean).powi(2) + self.losses as f64 * mean.powi(2)) / n
+    }
+
+    // the Elo difference with its 95% interval
+    fn elo(&self) -> (f64, f64) {
+        let mean = self.points();
+        let margin = 1.96 * (self.variance() / self.games() as f64).sqrt();
+        let low = elo_from_score(mean - margin);
+        let high = elo_from_score(mean + margin);
+        (elo_from_score(mean), (high - low) / 2.0)
+    }
+
+    // the log likelihood ratio of elo1 over elo0, normal approximation
+    fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        let variance = self.variance();
+        if variance <= 0.0 {
+            return 0.0;
+        }
+        let (score0, score1) = (score_from_elo(elo0), score_from_elo(elo1));
+        self.games() as f64 * (score1 - score0) * (2.0 * self.points() - score0 - score1) / (2.0 * variance)
+    }
+}
+
+fn elo_from_score(score: f64) -> f64 {
+    let score = score.clamp(1e-6, 1.0 - 1e-6);
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+fn score_from_elo(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+// YYYY.MM.DD for the PGN Date tag
+fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs() / 86_400) as i64;
+    // days since 1970 to a civil date, after Howard Hinnant
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+fn usage() -> ! {
+    eprintln!("usage: match --engine1 <spec> --engine2 <spec> [--games N] [--openings file] [--pgn file]");
+    eprintln!("             [--tc seconds+increment | --movetime ms | --nodes N | --depth N] [--sprt elo0,elo1]");
+    eprintln!("  spec is builtin[,hash=MB,threads=N,skill=N,elo=N,evalfile=file,name=X]");
+    eprintln!("       or uci:/path/to/engine[,Option=value,...,name=X]");
+    process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let arg_value = |name: &str| args.iter().position(|arg| arg == name).and_then(|index| args.get(index + 1));
+    let (first, second) = match (arg_value("--engine1"), arg_value("--engine2")) {
+        (Some(first), Some(second)) => (first, second),
+        _ => usage(),
+    };
+    let tc = if let Some(movetime) = arg_value("--movetime") {
+        movetime.parse().ok().map(TimeControl::MoveTime)
+    } else if let Some(nodes) = arg_value("--nodes") {
+        nodes.parse().ok().map(TimeControl::Nodes)
+    } else if let Some(depth) = arg_value("--depth") {
+        depth.parse().ok().map(TimeControl::Depth)
+    } else {
+        parse_tc(arg_value("--tc").map_or("10+0.1", |tc| tc.as_str()))
+    };
+    let tc = tc.unwrap_or_else(|| usage());
+    let sprt = arg_value("--sprt").map(|bounds| {
+        let bounds: Vec<f64> = bounds.split(',').filter_map(|bound| bound.parse().ok()).collect();
+        match bounds[..] {
+            [elo0, elo1] => (elo0, elo1),
+            _ => usage(),
+        }
+    });
+    let openings = load_openings(arg_value("--openings")).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    let games = arg_value("--games").and_then(|games| games.parse().ok()).unwrap_or(2 * openings.len());
+    let mut contestants = [first, second].map(|spec| {
+        parse_contestant(spec).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        })
+    });
+    let pgn_path = arg_value("--pgn").map_or(DEFAULT_PGN, |path| path.as_str());
+    let mut pgn = File::create(pgn_path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", pgn_path, e);
+        process::exit(1);
+    });
+
+    // sprt at 5% for both error kinds
+    let (lower, upper) = ((0.05f64 / 0.95).ln(), (0.95f64 / 0.05).ln());
+    let date = today();
+    let mut score = Score::default();
+    for round in 0..games {
+        let opening = &openings[(round / 2) % openings.len()];
+        // engine1 takes white in even rounds, each opening is played from both sides
+        let first_is_white = round % 2 == 0;
+        let [first, second] = &mut contestants;
+        let (white, black) = if first_is_white { (first, second) } else { (second, first) };
+        let (result, termination, moves) = play_game(white, black, opening, tc);
+        let first_result = if first_is_white { result } else { 1.0 - result };
+        match first_result {
+            r if r > 0.5 => score.wins += 1,
+            r if r < 0.5 => score.losses += 1,
+            _ => score.draws += 1,
+        }
+        let result_tag = match result {
+            r if r > 0.5 => "1-0",
+            r if r < 0.5 => "0-1",
+            _ => "1/2-1/2",
+        };
+        let game = PgnGame {
+            event: "match".to_string(),
+            site: "?".to_string(),
+            date: date.clone(),
+            round: (round + 1).to_string(),
+            white: white.name.clone(),
+            black: black.name.clone(),
+            result: result_tag.to_string(),
+            start_fen: Some(opening.fen.clone()),
+            moves,
+            termination: Some(termination.clone()),
+            tags: vec![("TimeControl".to_string(), match tc {
+                TimeControl::Clock { base, increment } => format!("{}+{}", base as f64 / 1000.0, increment as f64 / 1000.0),
+                _ => "-".to_string(),
+            })],
+        };
+        if let Err(e) = pgn.write_all(game.to_pgn().as_bytes()) {
+            eprintln!("{}: {}", pgn_path, e);
+        }
+
+        let (elo, margin) = score.elo();
+        println!("game {}: {} - {} {} {{{}}}", round + 1, white.name, black.name, result_tag, termination);
+        println!("score of {} vs {}: {} - {} - {} [{:.3}] {}, elo {:.1} +/- {:.1}",
+            contestants[0].name, contestants[1].name, score.wins, score.losses, score.draws, score.points(), score.games(), elo, margin);
+        if let Some((elo0, elo1)) = sprt {
+            let llr = score.llr(elo0, elo1);
+            println!("sprt elo0 {} elo1 {}: llr {:.2} ({:.2}, {:.2})", elo0, elo1, llr, lower, upper);
+            if llr >= upper {
+                println!("sprt: H1 accepted, {} is stronger", contestants[0].name);
+                break;
+            }
+            if llr <= lower {
+                println!("sprt: H0 accepted");
+                break;
+            }
+        }
+    }
+    let (elo, margin) = score.elo();
+    println!("finished: {} wins, {} draws, {} losses for {}, elo difference {:.1} +/- {:.1}",
+        score.wins, score.draws, score.losses, contestants[0].name, elo, margin);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(actual: f64, expected: f64) -> bool {
+        (actual - expected).abs() < 1e-6
+    }
+
+    #[test]
+    fn elo_and_score_convert_both_ways() {
+        assert!(close(score_from_elo(0.0), 0.5));
+        assert!(close(score_from_elo(100.0), 0.6400649998));
+        assert!(close(elo_from_score(0.75), 190.8485018879));
+        assert!(close(elo_from_score(score_from_elo(-250.0)), -250.0));
+        // a clean sweep is big but not infinite
+        assert!(elo_from_score(1.0).is_finite());
+    }
+
+    #[test]
+    fn elo_comes_with_its_interval() {
+        let score = Score { wins: 60, draws: 20, losses: 20 };
+        assert!(close(score.points(), 0.7));
+        assert!(close(score.variance(), 0.16));
+        let (elo, margin) = score.elo();
+        assert!(close(elo, 147.1907141178));
+        assert!(close(margin, 66.0146386282));
+    }
+
+    #[test]
+    fn llr_leans_towards_the_better_hypothesis() {
+        let winning = Score { wins: 60, draws: 20, losses: 20 };
+        assert!(close(winning.llr(0.0, 5.0), 0.8832073384));
+        let losing = Score { wins: 100, draws: 300, losses: 110 };
+        assert!(close(losing.llr(0.0, 5.0), -0.4781615660));
+        // all draws say nothing either way
+        assert_eq!(Score { wins: 0, draws: 10, losses: 0 }.llr(0.0, 5.0), 0.0);
+    }
+}
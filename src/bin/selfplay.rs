@@ -0,0 +1,262 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::sync::atomic::AtomicBool;
+
+use serde::{Deserialize, Serialize};
+
+use chess::engine::{Engine, Score, SearchLimits, DEFAULT_HASH_MB, MATE_SCORE};
+use chess::game::{Game, GameEnd};
+use chess::prng::Prng;
+use chess::visual::PLAYER;
+use chess::zobrist;
+
+const DEFAULT_GAMES: usize = 100;
+const DEFAULT_NODES: u64 = 5000;
+const DEFAULT_RANDOM_PLIES: usize = 8;
+const DEFAULT_OUT: &str = "selfplay.jsonl";
+
+// a game is given up for lost once both sides agree on this much for RESIGN_PLIES in a row
+const RESIGN_SCORE: i32 = 1000;
+const RESIGN_PLIES: usize = 6;
+// and called a draw past DRAW_MOVE when the score stays this close to 0 for DRAW_PLIES
+const DRAW_SCORE: i32 = 10;
+const DRAW_PLIES: usize = 12;
+const DRAW_MOVE: u32 = 40;
+const MAX_PLIES: usize = 400;
+
+// one searched position, a line of the output
+#[derive(Debug, Deserialize, Serialize)]
+struct Record {
+    game: usize,
+    ply: usize,
+    fen: String,
+    // centipawns for the side to move, mates as +-MATE_SCORE less the plies to it
+    score: i32,
+    best_move: String,
+    // for white: 1 won, 0.5 drawn, 0 lost
+    result: f32,
+    // how many records the game has, so a game cut off half way can be told apart
+    game_positions: usize,
+}
+
+fn centipawns(score: Score) -> i32 {
+    match score {
+        Score::Centipawns(cp) => cp,
+        Score::Mate(moves) if moves > 0 => MATE_SCORE - 2 * moves + 1,
+        Score::Mate(moves) => -MATE_SCORE - 2 * moves,
+    }
+}
+
+// the result for white if the game is over by the rules
+fn game_over(game: &Game, keys: &[u64]) -> Option<f32> {
+    match (game.game_end(keys)?, game.state.player_turn) {
+        (GameEnd::Checkmate, PLAYER::WHITE) => Some(0.0),
+        (GameEnd::Checkmate, PLAYER::BLACK) => Some(1.0),
+        _ => Some(0.5),
+    }
+}
+
+// Drops whatever a crash left of an unfinished game at the end of the file and returns
+// the game to carry on from.
+fn resume(path: &str) -> io::Result<usize> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut next_game = 0;
+    let mut valid_len = 0;
+    let mut offset = 0;
+    let mut count = 0;
+    let mut current = None;
+    for line in contents.split_inclusive('\n') {
+        offset += line.len();
+        let record = match serde_json::from_str::<Record>(line) {
+            Ok(record) if line.ends_with('\n') => record,
+            _ => break,
+        };
+        if current != Some(record.game) {
+            current = Some(record.game);
+            count = 0;
+        }
+        count += 1;
+        if count == record.game_positions {
+            next_game = record.game + 1;
+            valid_len = offset;
+        }
+    }
+    if valid_len < contents.len() {
+        OpenOptions::new().write(true).open(path)?.set_len(valid_len as u64)?;
+    }
+    Ok(next_game)
+}
+
+fn play_game(engine: &mut Engine, index: usize, seed: u64, nodes: u64, random_plies: usize) -> Vec<Record> {
+    // every game has its own generator so it comes out the same whether or not the run was resumed
+    let mut prng = Prng::new(seed ^ (index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    engine.new_game();
+    engine.set_seed(prng.next_u64());
+    let mut game = Game::default();
+    let mut keys = vec![zobrist::position_key(&game.state)];
+    for _ in 0..random_plies {
+        if game_over(&game, &keys).is_some() {
+            break;
+        }
+        let moves = game.get_validated_moves(game.state.player_turn);
+        let chess_move = &moves[prng.below(moves.len() as u64) as usize];
+        game.make_move(chess_move).expect("generated move is legal");
+        keys.push(zobrist::position_key(&game.state));
+    }
+
+    let limits = SearchLimits { nodes: Some(nodes), ..SearchLimits::default() };
+    let stop = AtomicBool::new(false);
+    let mut records = Vec::new();
+    let mut winning: i32 = 0;
+    let mut drawish = 0;
+    let result = loop {
+        if let Some(result) = game_over(&game, &keys) {
+            break result;
+        }
+        if game.turn_history.len() >= MAX_PLIES {
+            break 0.5;
+        }
+        let search = engine.search(&game, &limits, &stop, |_| {});
+        let best_move = match search.best_move {
+            Some(best_move) => best_move,
+            None => break 0.5,
+        };
+        let score = centipawns(search.score);
+        records.push(Record {
+            game: index,
+            ply: game.turn_history.len(),
+            fen: game.state.to_fen(),
+            score,
+            best_move: best_move.clone(),
+            result: 0.5,
+            game_positions: 0,
+        });
+
+        let white_score = if game.state.player_turn == PLAYER::WHITE { score } else { -score };
+        winning = if white_score.abs() < RESIGN_SCORE {
+            0
+        } else if winning.signum() == white_score.signum() {
+            winning + white_score.signum()
+        } else {
+            white_score.signum()
+        };
+        if winning.unsigned_abs() as usize >= RESIGN_PLIES {
+            break if winning > 0 { 1.0 } else { 0.0 };
+        }
+        drawish = if game.state.fullmove_number >= DRAW_MOVE && score.abs() <= DRAW_SCORE { drawish + 1 } else { 0 };
+        if drawish >= DRAW_PLIES {
+            break 0.5;
+        }
+
+        game.make_move(&best_move).expect("search move is legal");
+        keys.push(zobrist::position_key(&game.state));
+    };
+    let game_positions = records.len();
+    for record in records.iter_mut() {
+        record.result = result;
+        record.game_positions = game_positions;
+    }
+    records
+}
+
+fn main() -> io::Result<()> {
+    // selfplay [--games N] [--nodes N] [--seed N] [--random-plies N] [--hash MB] [--out file.jsonl]
+    let args: Vec<String> = env::args().collect();
+    let arg_value = |name: &str| args.iter().position(|arg| arg == name).and_then(|index| args.get(index + 1));
+    let games = arg_value("--games").and_then(|x| x.parse().ok()).unwrap_or(DEFAULT_GAMES);
+    let nodes = arg_value("--nodes").and_then(|x| x.parse().ok()).unwrap_or(DEFAULT_NODES);
+    let seed = arg_value("--seed").and_then(|x| x.parse().ok()).unwrap_or(0);
+    let random_plies = arg_value("--random-plies").and_then(|x| x.parse().ok()).unwrap_or(DEFAULT_RANDOM_PLIES);
+    let hash_mb = arg_value("--hash").and_then(|x| x.parse().ok()).unwrap_or(DEFAULT_HASH_MB);
+    let out = arg_value("--out").map_or(DEFAULT_OUT, |out| out.as_str());
+
+    let first_game = resume(out)?;
+    if first_game > 0 {
+        eprintln!("resuming {} at game {}", out, first_game + 1);
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(out)?;
+    // one thread, so the same seed and node budget always play the same games
+    let mut engine = Engine::new(hash_mb);
+    let mut scores = [0.0, 0.0];
+    for index in first_game..games {
+        let records = play_game(&mut engine, index, seed, nodes, random_plies);
+        let mut lines = String::new();
+        for record in &records {
+            lines.push_str(&serde_json::to_string(record)?);
+            lines.push('\n');
+        }
+        file.write_all(lines.as_bytes())?;
+        file.sync_data()?;
+        let result = records.first().map_or(0.5, |record| record.result);
+        scores[0] += result;
+        scores[1] += 1.0 - result;
+        eprintln!("game {}/{}: {} positions, {} (white {} - black {})", index + 1, games,
+            records.len(), match result { r if r > 0.5 => "1-0", r if r < 0.5 => "0-1", _ => "1/2-1/2" }, scores[0], scores[1]);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(game: usize, ply: usize, game_positions: usize) -> String {
+        let record = Record {
+            game,
+            ply,
+            fen: Game::default().state.to_fen(),
+            score: 0,
+            best_move: "e2-e4".to_string(),
+            result: 0.5,
+            game_positions,
+        };
+        serde_json::to_string(&record).unwrap() + "\n"
+    }
+
+    fn temp_path(name: &str) -> String {
+        env::temp_dir().join(format!("selfplay-{}-{}.jsonl", name, std::process::id())).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn resume_drops_an_unfinished_game() {
+        let path = temp_path("unfinished");
+        let finished = record(0, 0, 2) + &record(0, 1, 2) + &record(1, 0, 3) + &record(1, 1, 3) + &record(1, 2, 3);
+        // game 2 got one of its two lines out, then the crash tore the next one in half
+        let torn = record(2, 1, 2);
+        let contents = finished.clone() + &record(2, 0, 2) + &torn[..torn.len() / 2];
+        fs::write(&path, contents).unwrap();
+        assert_eq!(resume(&path).unwrap(), 2);
+        assert_eq!(fs::read_to_string(&path).unwrap(), finished);
+        // a second resume finds nothing to drop
+        assert_eq!(resume(&path).unwrap(), 2);
+        assert_eq!(fs::read_to_string(&path).unwrap(), finished);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn resume_keeps_a_complete_file() {
+        let path = temp_path("complete");
+        let finished = record(0, 0, 1) + &record(1, 0, 2) + &record(1, 1, 2);
+        fs::write(&path, &finished).unwrap();
+        assert_eq!(resume(&path).unwrap(), 2);
+        assert_eq!(fs::read_to_string(&path).unwrap(), finished);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(resume(&path).unwrap(), 0);
+    }
+
+    #[test]
+    fn a_resumed_game_is_played_the_same() {
+        let lines = |records: Vec<Record>| records.iter().map(|record| serde_json::to_string(record).unwrap()).collect::<Vec<_>>();
+        let mut engine = Engine::new(1);
+        play_game(&mut engine, 0, 7, 300, DEFAULT_RANDOM_PLIES);
+        let after_another = lines(play_game(&mut engine, 1, 7, 300, DEFAULT_RANDOM_PLIES));
+        let from_scratch = lines(play_game(&mut Engine::new(1), 1, 7, 300, DEFAULT_RANDOM_PLIES));
+        assert!(!from_scratch.is_empty());
+        assert_eq!(after_another, from_scratch);
+    }
+}
@@ -0,0 +1,287 @@
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::process;
+
+use chess::chess_notation_utilities;
+use chess::evaluation::{self, Weights, NUM_WEIGHTS, WEIGHTS_ENV};
+use chess::visual::GameState;
+
+const DEFAULT_OUT: &str = "weights.txt";
+const DEFAULT_ITERATIONS: usize = 1000;
+const DEFAULT_RATE: f64 = 1.0;
+
+// one training position: the evaluation's coefficients and how the game ended for white
+struct Sample {
+    features: Vec<(usize, f64)>,
+    result: f64,
+}
+
+fn parse_result(line: &str) -> Option<f64> {
+    [("1/2-1/2", 0.5), ("1-0", 1.0), ("0-1", 0.0), ("[0.5]", 0.5), ("[1.0]", 1.0), ("[0.0]", 0.0)]
+        .iter()
+        .find(|(result, _)| line.contains(result))
+        .map(|(_, value)| *value)
+}
+
+// an EPD line with the result as a c9 opcode or in brackets, or a line of selfplay's JSONL
+fn parse_line(line: &str) -> Option<(GameState, f64, Option<String>)> {
+    if line.starts_with('{') {
+        let value: serde_json::Value = serde_json::from_str(line).ok()?;
+        let state = GameState::from_fen(value.get("fen")?.as_str()?).ok()?;
+        let result = match value.get("result")? {
+            serde_json::Value::Number(number) => number.as_f64()?,
+            serde_json::Value::String(result) => parse_result(result)?,
+            _ => return None,
+        };
+        let best_move = value.get("best_move").and_then(|best_move| best_move.as_str()).map(str::to_string);
+        return Some((state, result, best_move));
+    }
+    let fen: Vec<&str> = line.split_whitespace().take(4).collect();
+    let state = GameState::from_fen(&fen.join(" ")).ok()?;
+    Some((state, parse_result(line)?, None))
+}
+
+// positions in check, or where the best move takes something, would need a search to score
+fn is_quiet(state: &GameState, best_move: Option<&str>) -> bool {
+    if state.is_in_check(state.player_turn) {
+        return false;
+    }
+    match best_move.and_then(|best_move| chess_notation_utilities::move_notation_to_indexes(best_move).ok()) {
+        Some((_, to)) => state.state[to].is_none(),
+        None => true,
+    }
+}
+
+fn load_samples(path: &str) -> Result<Vec<Sample>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut samples = Vec::new();
+    let mut skipped = 0;
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| format!("{}: {}", path, e))?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match parse_line(line) {
+            Some((state, result, best_move)) if is_quiet(&state, best_move.as_deref()) => samples.push(Sample {
+                features: evaluation::features(&state),
+                result,
+            }),
+            _ => skipped += 1,
+        }
+    }
+    eprintln!("{} positions, {} skipped as unreadable or not quiet", samples.len(), skipped);
+    Ok(samples)
+}
+
+fn score(sample: &Sample, weights: &[f64]) -> f64 {
+    sample.features.iter().map(|(index, coefficient)| weights[*index] * coefficient).sum()
+}
+
+// the expected result for white at a score in centipawns
+fn sigmoid(score: f64, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * score / 400.0))
+}
+
+fn mean_error(samples: &[Sample], scores: &[f64], k: f64) -> f64 {
+    let total: f64 = samples.iter().zip(scores).map(|(sample, score)| (sample.result - sigmoid(*score, k)).powi(2)).sum();
+    total / samples.len() as f64
+}
+
+// the scaling constant that fits the untuned evaluation best, found by ternary search
+fn fit_k(samples: &[Sample], scores: &[f64]) -> f64 {
+    let (mut low, mut high) = (0.01, 5.0);
+    for _ in 0..60 {
+        let third = (high - low) / 3.0;
+        if mean_error(samples, scores, low + third) < mean_error(samples, scores, high - third) {
+            high -= third;
+        } else {
+            low += third;
+        }
+    }
+    (low + high) / 2.0
+}
+
+// Adam over the whole set each step
+fn gradient_descent(samples: &[Sample], weights: &mut [f64], k: f64, iterations: usize, rate: f64) {
+    let (beta1, beta2, epsilon) = (0.9, 0.999, 1e-8);
+    let mut m = vec![0.0; NUM_WEIGHTS];
+    let mut v = vec![0.0; NUM_WEIGHTS];
+    let slope = k * 10f64.ln() / 400.0;
+    for iteration in 1..=iterations {
+        let mut gradient = vec![0.0; NUM_WEIGHTS];
+        let mut error = 0.0;
+        for sample in samples {
+            let expected = sigmoid(score(sample, weights), k);
+            let difference = sample.result - expected;
+            error += difference * difference;
+            let step = -2.0 * difference * expected * (1.0 - expected) * slope;
+            for (index, coefficient) in &sample.features {
+                gradient[*index] += step * coefficient;
+            }
+        }
+        for index in 0..NUM_WEIGHTS {
+            let g = gradient[index] / samples.len() as f64;
+            m[index] = beta1 * m[index] + (1.0 - beta1) * g;
+            v[index] = beta2 * v[index] + (1.0 - beta2) * g * g;
+            let m_hat = m[index] / (1.0 - beta1.powi(iteration as i32));
+            let v_hat = v[index] / (1.0 - beta2.powi(iteration as i32));
+            weights[index] -= rate * m_hat / (v_hat.sqrt() + epsilon);
+        }
+        if iteration % 100 == 0 || iteration == iterations {
+            eprintln!("iteration {}: error {:.6}", iteration, error / samples.len() as f64);
+        }
+    }
+}
+
+// The classic Texel tuner: nudge each weight by one either way and keep what helps,
+// until a whole pass changes nothing. Only positions a weight appears in are rescored.
+fn local_search(samples: &[Sample], weights: &mut [f64], k: f64, passes: usize) {
+    let mut uses: Vec<Vec<(usize, f64)>> = vec![Vec::new(); NUM_WEIGHTS];
+    for (position, sample) in samples.iter().enumerate() {
+        for (index, coefficient) in &sample.features {
+            uses[*index].push((position, *coefficient));
+        }
+    }
+    let mut scores: Vec<f64> = samples.iter().map(|sample| score(sample, weights)).collect();
+    let error_of = |position: usize, score: f64| (samples[position].result - sigmoid(score, k)).powi(2);
+    for pass in 1..=passes {
+        let mut improved = false;
+        for index in 0..NUM_WEIGHTS {
+            for delta in [1.0, -1.0] {
+                let change: f64 = uses[index]
+                    .iter()
+                    .map(|(position, coefficient)| {
+                        error_of(*position, scores[*position] + delta * coefficient) - error_of(*position, scores[*position])
+                    })
+                    .sum();
+                if change < -1e-12 {
+                    weights[index] += delta;
+                    for (position, coefficient) in &uses[index] {
+                        scores[*position] += delta * coefficient;
+                    }
+                    improved = true;
+                    break;
+                }
+            }
+        }
+        eprintln!("pass {}: error {:.6}", pass, mean_error(samples, &scores, k));
+        if !improved {
+            break;
+        }
+    }
+}
+
+fn main() {
+    // tune <positions.epd|positions.jsonl> [--out weights.txt] [--weights start.txt]
+    //      [--method gd|local] [--iterations N] [--rate R]
+    let args: Vec<String> = env::args().collect();
+    let arg_value = |name: &str| args.iter().position(|arg| arg == name).and_then(|index| args.get(index + 1));
+    let path = match args.get(1).filter(|path| !path.starts_with("--")) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: tune <positions.epd|positions.jsonl> [--out weights.txt] [--weights start.txt] [--method gd|local] [--iterations N] [--rate R]");
+            process::exit(1);
+        }
+    };
+    let out = arg_value("--out").map_or(DEFAULT_OUT, |out| out.as_str());
+    let iterations = arg_value("--iterations").and_then(|x| x.parse().ok()).unwrap_or(DEFAULT_ITERATIONS);
+    let rate = arg_value("--rate").and_then(|x| x.parse().ok()).unwrap_or(DEFAULT_RATE);
+    let start = match arg_value("--weights").map(|path| Weights::load(path)) {
+        Some(Ok(weights)) => weights,
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        None => Weights::default(),
+    };
+    let samples = match load_samples(path) {
+        Ok(samples) if !samples.is_empty() => samples,
+        Ok(_) => {
+            eprintln!("no positions to tune on");
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let mut weights: Vec<f64> = start.to_vec().into_iter().map(f64::from).collect();
+    let scores: Vec<f64> = samples.iter().map(|sample| score(sample, &weights)).collect();
+    let k = fit_k(&samples, &scores);
+    eprintln!("k {:.4}, starting error {:.6}", k, mean_error(&samples, &scores, k));
+    match arg_value("--method").map(|method| method.as_str()) {
+        Some("local") => local_search(&samples, &mut weights, k, iterations),
+        _ => gradient_descent(&samples, &mut weights, k, iterations, rate),
+    }
+
+    let tuned: Vec<i32> = weights.iter().map(|weight| weight.round() as i32).collect();
+    let tuned = Weights::from_slice(&tuned);
+    if let Err(e) = tuned.save(out) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    eprintln!("wrote {}, set {}={} to play with them", out, WEIGHTS_ENV, out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -";
+
+    #[test]
+    fn reads_epd_results() {
+        for (line, expected) in [
+            (format!("{} c9 \"1-0\";", START), 1.0),
+            (format!("{} c9 \"0-1\";", START), 0.0),
+            (format!("{} c9 \"1/2-1/2\";", START), 0.5),
+            (format!("{} [0.5]", START), 0.5),
+            (format!("{} [1.0]", START), 1.0),
+        ] {
+            let (state, result, best_move) = parse_line(&line).unwrap();
+            assert_eq!(result, expected, "{}", line);
+            assert_eq!(state.to_fen(), GameState::from_fen(START).unwrap().to_fen());
+            assert_eq!(best_move, None);
+        }
+    }
+
+    #[test]
+    fn reads_selfplay_lines() {
+        let line = r#"{"game":0,"ply":0,"fen":"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1","score":25,"best_move":"e2-e4","result":0.0,"game_positions":60}"#;
+        let (_, result, best_move) = parse_line(line).unwrap();
+        assert_eq!(result, 0.0);
+        assert_eq!(best_move.as_deref(), Some("e2-e4"));
+        let (_, result, best_move) = parse_line(r#"{"fen":"4k3/8/8/8/8/8/8/4K3 w - - 0 1","result":"1/2-1/2"}"#).unwrap();
+        assert_eq!(result, 0.5);
+        assert_eq!(best_move, None);
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        for line in [
+            START.to_string(),
+            format!("{} c9 \"*\";", START),
+            "not a position [1.0]".to_string(),
+            "8/8/8 w - - [1.0]".to_string(),
+            r#"{"fen":"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"}"#.to_string(),
+            r#"{"fen":"rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1","result":true}"#.to_string(),
+            r#"{"fen":"nonsense","result":1.0}"#.to_string(),
+            r#"{"fen":"#.to_string(),
+        ] {
+            assert!(parse_line(&line).is_none(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn only_quiet_positions_are_kept() {
+        let state = GameState::from_fen("rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2").unwrap();
+        assert!(is_quiet(&state, Some("g1-f3")));
+        assert!(is_quiet(&state, None));
+        assert!(!is_quiet(&state, Some("e4-d5")));
+        let check = GameState::from_fen("rnbqkbnr/ppp1pppp/8/1B1p4/4P3/8/PPPP1PPP/RNBQK1NR b KQkq - 1 2").unwrap();
+        assert!(!is_quiet(&check, Some("c7-c6")));
+    }
+}
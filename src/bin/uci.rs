@@ -0,0 +1,373 @@
+use std::io::{stdin, BufRead};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chess::chess_notation_utilities;
+use chess::engine::{Engine, SearchLimits, DEFAULT_HASH_MB};
+use chess::evaluation::EvalBackend;
+use chess::game::Game;
+use chess::nnue::Network;
+use chess::polyglot::{Book, BookPick};
+use chess::prng::Prng;
+use chess::skill::{Skill, MAX_ELO, MAX_SKILL_LEVEL, MIN_ELO};
+use chess::syzygy::Tablebase;
+
+const DEFAULT_ELO: u32 = 1500;
+const MAX_MULTIPV: usize = 256;
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+struct Search {
+    handle: JoinHandle<Engine>,
+    stop: Arc<AtomicBool>,
+    // only a stop ends it
+    infinite: bool,
+}
+
+struct Uci {
+    // the engine is handed to the search thread while it thinks
+    engine: Option<Engine>,
+    search: Option<Search>,
+    position_fen: String,
+    position_moves: Vec<String>,
+    book: Option<Book>,
+    own_book: bool,
+    book_pick: BookPick,
+    prng: Prng,
+    skill_level: u32,
+    limit_strength: bool,
+    elo: u32,
+    multipv: usize,
+    show_wdl: bool,
+    network: Option<Arc<Network>>,
+    use_nnue: bool,
+}
+
+impl Uci {
+    fn new() -> Self {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos() as u64);
+        let mut engine = Engine::new(DEFAULT_HASH_MB);
+        engine.set_seed(seed);
+        Uci {
+            engine: Some(engine),
+            search: None,
+            position_fen: START_FEN.to_string(),
+            position_moves: Vec::new(),
+            book: None,
+            own_book: false,
+            book_pick: BookPick::Weighted,
+            prng: Prng::new(seed),
+            skill_level: MAX_SKILL_LEVEL,
+            limit_strength: false,
+            elo: DEFAULT_ELO,
+            multipv: 1,
+            show_wdl: false,
+            network: None,
+            use_nnue: false,
+        }
+    }
+
+    // UCI_LimitStrength picks the level from UCI_Elo, otherwise Skill Level counts
+    fn update_skill(&mut self) {
+        let skill = if self.limit_strength { Skill::from_elo(self.elo) } else { Skill::new(self.skill_level) };
+        self.engine().set_skill(skill);
+    }
+
+    // the network only counts with Use NNUE on, and falls back to classical without a file
+    fn update_eval(&mut self) {
+        let backend = match (&self.network, self.use_nnue) {
+            (Some(network), true) => EvalBackend::Nnue(network.clone()),
+            (None, true) => {
+                println!("info string no EvalFile loaded, using the classical evaluation");
+                EvalBackend::Classical
+            }
+            _ => EvalBackend::Classical,
+        };
+        self.engine().set_eval_backend(backend);
+    }
+
+    fn wait_for_search(&mut self, stop: bool) {
+        if let Some(search) = self.search.take() {
+            if stop {
+                search.stop.store(true, Ordering::Relaxed);
+            }
+            self.engine = Some(search.handle.join().expect("search thread panicked"));
+        }
+    }
+
+    fn engine(&mut self) -> &mut Engine {
+        self.wait_for_search(true);
+        self.engine.as_mut().unwrap()
+    }
+
+    fn build_game(fen: &str, moves: &[String]) -> Result<Game, String> {
+        let mut game = Game::game_from_fen(fen).map_err(|e| e.to_string())?;
+        for chess_move in moves {
+            game.make_move(chess_move).map_err(|e| format!("{}: {}", chess_move, e))?;
+        }
+        Ok(game)
+    }
+
+    fn uci(&self) {
+        println!("id name {} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        println!("id author eddiechristian");
+        println!("option name Hash type spin default {} min 1 max 4096", DEFAULT_HASH_MB);
+        println!("option name Clear Hash type button");
+        println!("option name Threads type spin default 1 min 1 max 256");
+        println!("option name MultiPV type spin default 1 min 1 max {}", MAX_MULTIPV);
+        println!("option name UCI_ShowWDL type check default false");
+        println!("option name Skill Level type spin default {} min 0 max {}", MAX_SKILL_LEVEL, MAX_SKILL_LEVEL);
+        println!("option name UCI_LimitStrength type check default false");
+        println!("option name UCI_Elo type spin default {} min {} max {}", DEFAULT_ELO, MIN_ELO, MAX_ELO);
+        println!("option name OwnBook type check default false");
+        println!("option name BookFile type string default <empty>");
+        println!("option name BookBestMove type check default false");
+        println!("option name Use NNUE type check default false");
+        println!("option name EvalFile type string default <empty>");
+        println!("option name SyzygyPath type string default <empty>");
+        println!("uciok");
+    }
+
+    fn set_option(&mut self, args: &[&str]) {
+        // setoption name <id> [value <x>], names may contain spaces
+        let rest = match (args.first(), args.get(1..)) {
+            (Some(&"name"), Some(rest)) => rest,
+            _ => {
+                println!("info string setoption needs a name");
+                return;
+            }
+        };
+        let value_at = rest.iter().position(|arg| *arg == "value");
+        let name = rest[..value_at.unwrap_or(rest.len())].join(" ");
+        let value = value_at.map(|index| rest[index + 1..].join(" ")).unwrap_or_default();
+        if name.is_empty() {
+            println!("info string setoption needs a name");
+            return;
+        }
+        match name.to_lowercase().as_str() {
+            "hash" => match value.parse::<usize>() {
+                Ok(hash_mb) => self.engine().resize_hash(hash_mb),
+                Err(_) => println!("info string invalid Hash value {}", value),
+            },
+            "clear hash" => self.engine().new_game(),
+            "skill level" => match value.parse::<u32>() {
+                Ok(level) => {
+                    self.skill_level = level.min(MAX_SKILL_LEVEL);
+                    self.update_skill();
+                }
+                Err(_) => println!("info string invalid Skill Level value {}", value),
+            },
+            "uci_limitstrength" => {
+                self.limit_strength = value == "true";
+                self.update_skill();
+            }
+            "uci_elo" => match value.parse::<u32>() {
+                Ok(elo) => {
+                    self.elo = elo.clamp(MIN_ELO, MAX_ELO);
+                    self.update_skill();
+                }
+                Err(_) => println!("info string invalid UCI_Elo value {}", value),
+            },
+            "multipv" => match value.parse::<usize>() {
+                Ok(multipv) => self.multipv = multipv.clamp(1, MAX_MULTIPV),
+                Err(_) => println!("info string invalid MultiPV value {}", value),
+            },
+            "uci_showwdl" => self.show_wdl = value == "true",
+            "threads" => match value.parse::<usize>() {
+                Ok(threads) => self.engine().set_threads(threads),
+                Err(_) => println!("info string invalid Threads value {}", value),
+            },
+            "ownbook" => self.own_book = value == "true",
+            "bookfile" => {
+                self.book = None;
+                if !value.is_empty() && value != "<empty>" {
+                    match Book::open(&value) {
+                        Ok(book) => self.book = Some(book),
+                        Err(e) => println!("info string {}", e),
+                    }
+                }
+            }
+            "use nnue" => {
+                self.use_nnue = value == "true";
+                self.update_eval();
+            }
+            "evalfile" => {
+                self.network = None;
+                if !value.is_empty() && value != "<empty>" {
+                    match Network::open(&value) {
+                        Ok(network) => {
+                            println!("info string loaded network with {} hidden neurons", network.hidden_size());
+                            self.network = Some(network);
+                        }
+                        Err(e) => println!("info string {}", e),
+                    }
+                }
+                self.update_eval();
+            }
+            "syzygypath" => {
+                let tablebase = if value.is_empty() || value == "<empty>" {
+                    None
+                } else {
+                    match Tablebase::open(&value) {
+                        Ok(tablebase) => {
+                            println!("info string found {}-piece tablebases", tablebase.max_pieces());
+                            Some(Arc::new(tablebase))
+                        }
+                        Err(e) => {
+                            println!("info string {}", e);
+                            None
+                        }
+                    }
+                };
+                self.engine().set_tablebase(tablebase);
+            }
+            "bookbestmove" => self.book_pick = if value == "true" { BookPick::Best } else { BookPick::Weighted },
+            _ => println!("info string unknown option {}", name),
+        }
+    }
+
+    fn position(&mut self, args: &[&str]) {
+        let moves_at = args.iter().position(|arg| *arg == "moves").unwrap_or(args.len());
+        let fen = match args.first() {
+            Some(&"startpos") => START_FEN.to_string(),
+            Some(&"fen") => args[1..moves_at].join(" "),
+            _ => {
+                println!("info string position needs startpos or fen");
+                return;
+            }
+        };
+        let mut moves = Vec::new();
+        for uci_move in args.iter().skip(moves_at + 1) {
+            match chess_notation_utilities::uci_to_move_notation(uci_move) {
+                Ok(chess_move) => moves.push(chess_move),
+                Err(e) => {
+                    println!("info string {}", e);
+                    return;
+                }
+            }
+        }
+        // check it all now so a bad position is reported here rather than at "go"
+        if let Err(e) = Uci::build_game(&fen, &moves) {
+            println!("info string invalid position: {}", e);
+            return;
+        }
+        self.position_fen = fen;
+        self.position_moves = moves;
+    }
+
+    fn go(&mut self, args: &[&str]) {
+        // a search that already printed its bestmove only has to hand the engine back
+        if self.search.as_ref().is_some_and(|search| search.handle.is_finished()) {
+            self.wait_for_search(false);
+        }
+        let mut engine = match self.engine.take() {
+            Some(engine) => engine,
+            None => {
+                println!("info string already searching");
+                return;
+            }
+        };
+        let game = Uci::build_game(&self.position_fen, &self.position_moves).unwrap_or_default();
+        let mut limits = parse_go(args);
+        limits.multipv = self.multipv;
+        let show_wdl = self.show_wdl;
+        let infinite = limits.infinite;
+        if self.own_book && !limits.infinite {
+            let book_move = match &self.book {
+                Some(book) => book.pick(&game, self.book_pick, &mut self.prng),
+                None => None,
+            };
+            if let Some(book_move) = book_move {
+                self.engine = Some(engine);
+                println!("info string book move");
+                println!("bestmove {}", chess_notation_utilities::move_notation_to_uci(&book_move));
+                return;
+            }
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let handle = thread::spawn(move || {
+            let result = engine.search(&game, &limits, &thread_stop, |info| {
+                let millis = info.time.as_millis().max(1) as u64;
+                let pv: Vec<String> = info.pv.iter().map(|chess_move| chess_notation_utilities::move_notation_to_uci(chess_move)).collect();
+                let wdl = if show_wdl {
+                    let wdl = info.score.wdl();
+                    format!(" wdl {} {} {}", wdl.win, wdl.draw, wdl.loss)
+                } else {
+                    String::new()
+                };
+                println!("info depth {} seldepth {} multipv {} score {}{} nodes {} nps {} time {} pv {}",
+                    info.depth, info.seldepth, info.multipv, info.score, wdl, info.nodes, info.nodes * 1000 / millis, millis, pv.join(" "));
+            });
+            match (result.best_move, result.ponder_move) {
+                (Some(best_move), Some(ponder_move)) => println!("bestmove {} ponder {}",
+                    chess_notation_utilities::move_notation_to_uci(&best_move),
+                    chess_notation_utilities::move_notation_to_uci(&ponder_move)),
+                (Some(best_move), None) => println!("bestmove {}", chess_notation_utilities::move_notation_to_uci(&best_move)),
+                _ => println!("bestmove 0000"),
+            }
+            engine
+        });
+        self.search = Some(Search { handle, stop, infinite });
+    }
+}
+
+fn parse_go(args: &[&str]) -> SearchLimits {
+    let mut limits = SearchLimits::default();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().and_then(|x| x.parse::<u64>().ok());
+        match *arg {
+            "depth" => limits.depth = value().map(|depth| depth as u32),
+            "nodes" => limits.nodes = value(),
+            "movetime" => limits.movetime = value().map(Duration::from_millis),
+            "wtime" => limits.wtime = value().map(Duration::from_millis),
+            "btime" => limits.btime = value().map(Duration::from_millis),
+            "winc" => limits.winc = Duration::from_millis(value().unwrap_or(0)),
+            "binc" => limits.binc = Duration::from_millis(value().unwrap_or(0)),
+            "movestogo" => limits.movestogo = value().map(|moves| moves as u32),
+            "infinite" => limits.infinite = true,
+            _ => {}
+        }
+    }
+    limits
+}
+
+fn main() {
+    let mut uci = Uci::new();
+    for line in stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match args.first() {
+            Some(&"uci") => uci.uci(),
+            Some(&"isready") => println!("readyok"),
+            Some(&"ucinewgame") => uci.engine().new_game(),
+            Some(&"setoption") => uci.set_option(&args[1..]),
+            Some(&"position") => uci.position(&args[1..]),
+            Some(&"go") => uci.go(&args[1..]),
+            Some(&"stop") => uci.wait_for_search(true),
+            Some(&"quit") => {
+                uci.wait_for_search(true);
+                return;
+            }
+            // not UCI, but handy when typing at it
+            Some(&"d") => match Uci::build_game(&uci.position_fen, &uci.position_moves) {
+                Ok(game) => {
+                    println!("{}", game.state);
+                    println!("Fen: {}", game.state.to_fen());
+                }
+                Err(e) => println!("info string {}", e),
+            },
+            Some(other) => println!("info string unknown command {}", other),
+            None => {}
+        }
+    }
+    // input ran out (a piped script), let a running search finish on its own unless it never would
+    let infinite = uci.search.as_ref().is_some_and(|search| search.infinite);
+    uci.wait_for_search(infinite);
+}
@@ -0,0 +1,378 @@
+use std::io::{stdin, stdout, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use chess::chess_notation_utilities;
+use chess::engine::{Engine, Score, SearchLimits, DEFAULT_HASH_MB};
+use chess::game::{Game, GameEnd};
+use chess::syzygy::Tablebase;
+use chess::visual::PLAYER;
+use chess::zobrist;
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+// xboard reports mate in N as 100000 + N
+const XBOARD_MATE: i32 = 100_000;
+
+struct Search {
+    handle: JoinHandle<(Engine, Option<String>)>,
+    stop: Arc<AtomicBool>,
+    // set when the move must not be played, e.g. after force or result
+    discard: Arc<AtomicBool>,
+}
+
+enum TimeControl {
+    // level MPS BASE INC, times in milliseconds
+    Level { moves_per_session: u32, base: u64, increment: u64 },
+    // st, a fixed time for every move
+    PerMove(Duration),
+}
+
+struct XBoard {
+    engine: Option<Engine>,
+    search: Option<Search>,
+    start_fen: String,
+    moves: Vec<String>,
+    game: Game,
+    // the zobrist key of every position in the game, for repetitions
+    keys: Vec<u64>,
+    // None while in force mode
+    engine_side: Option<PLAYER>,
+    game_over: bool,
+    post: bool,
+    max_depth: Option<u32>,
+    time_control: TimeControl,
+    engine_time: Option<u64>,
+}
+
+impl XBoard {
+    fn new() -> Self {
+        let game = Game::game_from_fen(START_FEN).unwrap_or_default();
+        XBoard {
+            engine: Some(Engine::new(DEFAULT_HASH_MB)),
+            search: None,
+            start_fen: START_FEN.to_string(),
+            moves: Vec::new(),
+            keys: vec![zobrist::position_key(&game.state)],
+            game,
+            engine_side: Some(PLAYER::BLACK),
+            game_over: false,
+            post: false,
+            max_depth: None,
+            time_control: TimeControl::Level { moves_per_session: 40, base: 5 * 60 * 1000, increment: 0 },
+            engine_time: None,
+        }
+    }
+
+    // collects a running search; the move it printed is played on our board too
+    fn wait_for_search(&mut self, stop: bool, discard: bool) {
+        if let Some(search) = self.search.take() {
+            if discard {
+                search.discard.store(true, Ordering::Relaxed);
+            }
+            if stop || discard {
+                search.stop.store(true, Ordering::Relaxed);
+            }
+            let (engine, played) = search.handle.join().expect("search thread panicked");
+            self.engine = Some(engine);
+            if let Some(chess_move) = played {
+                self.play(&chess_move, false);
+            }
+        }
+    }
+
+    fn search_finished(&self) -> bool {
+        self.search.as_ref().is_some_and(|search| search.handle.is_finished())
+    }
+
+    fn engine(&mut self) -> &mut Engine {
+        self.wait_for_search(true, true);
+        self.engine.as_mut().unwrap()
+    }
+
+    fn set_position(&mut self, fen: &str) -> Result<(), String> {
+        let game = Game::game_from_fen(fen).map_err(|e| e.to_string())?;
+        self.start_fen = fen.to_string();
+        self.moves.clear();
+        self.keys = vec![zobrist::position_key(&game.state)];
+        self.game = game;
+        self.game_over = false;
+        Ok(())
+    }
+
+    // replays the game from its start, used after undo and remove
+    fn rebuild(&mut self) {
+        let mut game = Game::game_from_fen(&self.start_fen).unwrap_or_default();
+        let mut keys = vec![zobrist::position_key(&game.state)];
+        for chess_move in &self.moves {
+            if game.make_move(chess_move).is_err() {
+                break;
+            }
+            keys.push(zobrist::position_key(&game.state));
+        }
+        self.game = game;
+        self.keys = keys;
+        self.game_over = false;
+    }
+
+    // the search thread announces the end of the game after its own moves
+    fn play(&mut self, chess_move: &str, announce: bool) -> bool {
+        if self.game.make_move(chess_move).is_err() {
+            return false;
+        }
+        self.moves.push(chess_move.to_string());
+        self.keys.push(zobrist::position_key(&self.game.state));
+        if let Some(result) = game_result(&self.game, &self.keys) {
+            if announce {
+                println!("{}", result);
+            }
+            self.game_over = true;
+        }
+        true
+    }
+
+    fn protover(&self) {
+        println!("feature myname=\"{} {}\" ping=1 setboard=1 usermove=1 playother=1 colors=0 \
+                  sigint=0 sigterm=0 reuse=1 analyze=0 draw=0 name=0 smp=1 egt=\"syzygy\" done=1",
+                 env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    }
+
+    fn new_game(&mut self) {
+        self.engine().new_game();
+        let _ = self.set_position(START_FEN);
+        self.engine_side = Some(PLAYER::BLACK);
+        self.max_depth = None;
+        self.engine_time = None;
+    }
+
+    fn egtpath(&mut self, args: &[&str]) {
+        if args.first() != Some(&"syzygy") || args.len() < 2 {
+            println!("Error (unsupported tablebases): egtpath");
+            return;
+        }
+        match Tablebase::open(&args[1..].join(" ")) {
+            Ok(tablebase) => self.engine().set_tablebase(Some(Arc::new(tablebase))),
+            Err(e) => println!("tellusererror {}", e),
+        }
+    }
+
+    fn usermove(&mut self, args: &[&str]) {
+        self.wait_for_search(false, false);
+        let uci_move = match args.first() {
+            Some(uci_move) => *uci_move,
+            None => {
+                println!("Error (no move): usermove");
+                return;
+            }
+        };
+        let legal = !self.game_over
+            && chess_notation_utilities::uci_to_move_notation(uci_move).is_ok_and(|chess_move| self.play(&chess_move, true));
+        if !legal {
+            println!("Illegal move: {}", uci_move);
+            return;
+        }
+        self.think_if_our_turn();
+    }
+
+    fn set_board(&mut self, args: &[&str]) {
+        self.wait_for_search(true, true);
+        if let Err(e) = self.set_position(&args.join(" ")) {
+            println!("tellusererror Illegal position: {}", e);
+        }
+    }
+
+    fn take_back(&mut self, count: usize) {
+        self.wait_for_search(true, true);
+        if self.moves.len() < count {
+            println!("Error (no moves to take back): {}", if count == 1 { "undo" } else { "remove" });
+            return;
+        }
+        self.moves.truncate(self.moves.len() - count);
+        self.rebuild();
+    }
+
+    fn level(&mut self, args: &[&str]) {
+        // level 40 5 0, level 0 2:30 12
+        if args.len() < 3 {
+            println!("Error (bad arguments): level");
+            return;
+        }
+        let moves_per_session = args[0].parse::<u32>().unwrap_or(0);
+        let base = parse_base_time(args[1]).unwrap_or(5 * 60 * 1000);
+        let increment = args[2].parse::<f64>().map(|seconds| (seconds * 1000.0) as u64).unwrap_or(0);
+        self.time_control = TimeControl::Level { moves_per_session, base, increment };
+    }
+
+    fn limits(&self) -> SearchLimits {
+        let mut limits = SearchLimits {
+            depth: self.max_depth,
+            ..SearchLimits::default()
+        };
+        match self.time_control {
+            TimeControl::PerMove(movetime) => limits.movetime = Some(movetime),
+            TimeControl::Level { moves_per_session, base, increment } => {
+                // without a "time" command all we know is the starting clock
+                let time_left = Some(Duration::from_millis(self.engine_time.unwrap_or(base)));
+                let increment = Duration::from_millis(increment);
+                match self.game.state.player_turn {
+                    PLAYER::WHITE => (limits.wtime, limits.winc) = (time_left, increment),
+                    PLAYER::BLACK => (limits.btime, limits.binc) = (time_left, increment),
+                }
+                if moves_per_session > 0 {
+                    let played = self.game.state.fullmove_number.saturating_sub(1);
+                    limits.movestogo = Some(moves_per_session - played % moves_per_session);
+                }
+            }
+        }
+        limits
+    }
+
+    fn think_if_our_turn(&mut self) {
+        if !self.game_over && self.search.is_none() && self.engine_side == Some(self.game.state.player_turn) {
+            self.think();
+        }
+    }
+
+    fn think(&mut self) {
+        let mut engine = match self.engine.take() {
+            Some(engine) => engine,
+            None => return,
+        };
+        let mut game = self.game.clone();
+        let mut keys = self.keys.clone();
+        let limits = self.limits();
+        let post = self.post;
+        let stop = Arc::new(AtomicBool::new(false));
+        let discard = Arc::new(AtomicBool::new(false));
+        let (thread_stop, thread_discard) = (stop.clone(), discard.clone());
+        let handle = thread::spawn(move || {
+            let result = engine.search(&game, &limits, &thread_stop, |info| {
+                if post {
+                    let score = match info.score {
+                        Score::Centipawns(cp) => cp,
+                        Score::Mate(moves) if moves > 0 => XBOARD_MATE + moves,
+                        Score::Mate(moves) => -XBOARD_MATE + moves,
+                    };
+                    let pv: Vec<String> = info.pv.iter().map(|chess_move| chess_notation_utilities::move_notation_to_uci(chess_move)).collect();
+                    println!("{} {} {} {} {}", info.depth, score, info.time.as_millis() / 10, info.nodes, pv.join(" "));
+                    let _ = stdout().flush();
+                }
+            });
+            if thread_discard.load(Ordering::Relaxed) {
+                return (engine, None);
+            }
+            if let Some(best_move) = &result.best_move {
+                println!("move {}", chess_notation_utilities::move_notation_to_uci(best_move));
+                if game.make_move(best_move).is_ok() {
+                    keys.push(zobrist::position_key(&game.state));
+                    if let Some(result) = game_result(&game, &keys) {
+                        println!("{}", result);
+                    }
+                }
+                let _ = stdout().flush();
+            }
+            (engine, result.best_move)
+        });
+        self.search = Some(Search { handle, stop, discard });
+    }
+}
+
+fn game_result(game: &Game, keys: &[u64]) -> Option<&'static str> {
+    match (game.game_end(keys)?, game.state.player_turn) {
+        (GameEnd::Checkmate, PLAYER::WHITE) => Some("0-1 {Black mates}"),
+        (GameEnd::Checkmate, PLAYER::BLACK) => Some("1-0 {White mates}"),
+        (GameEnd::Stalemate, _) => Some("1/2-1/2 {Stalemate}"),
+        (GameEnd::Repetition, _) => Some("1/2-1/2 {Draw by repetition}"),
+        (GameEnd::FiftyMoves, _) => Some("1/2-1/2 {50 move rule}"),
+        (GameEnd::InsufficientMaterial, _) => Some("1/2-1/2 {Insufficient material}"),
+    }
+}
+
+// "5" is minutes, "2:30" minutes and seconds
+fn parse_base_time(base: &str) -> Option<u64> {
+    let mut parts = base.splitn(2, ':');
+    let minutes = parts.next()?.parse::<u64>().ok()?;
+    let seconds = match parts.next() {
+        Some(seconds) => seconds.parse::<u64>().ok()?,
+        None => 0,
+    };
+    Some((minutes * 60 + seconds) * 1000)
+}
+
+fn main() {
+    let mut xboard = XBoard::new();
+    for line in stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        // pick up the engine's own move before reading the board
+        if xboard.search_finished() {
+            xboard.wait_for_search(false, false);
+        }
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match args.first() {
+            Some(&"xboard") => {}
+            Some(&"protover") => xboard.protover(),
+            Some(&"accepted") | Some(&"rejected") => {}
+            Some(&"ping") => println!("pong {}", args.get(1).unwrap_or(&"")),
+            Some(&"new") => xboard.new_game(),
+            Some(&"usermove") => xboard.usermove(&args[1..]),
+            Some(&"go") => {
+                xboard.wait_for_search(false, false);
+                xboard.engine_side = Some(xboard.game.state.player_turn);
+                xboard.think_if_our_turn();
+            }
+            Some(&"playother") => {
+                xboard.wait_for_search(true, true);
+                xboard.engine_side = Some(match xboard.game.state.player_turn {
+                    PLAYER::WHITE => PLAYER::BLACK,
+                    PLAYER::BLACK => PLAYER::WHITE,
+                });
+            }
+            Some(&"force") => {
+                xboard.wait_for_search(true, true);
+                xboard.engine_side = None;
+            }
+            Some(&"?") => xboard.wait_for_search(true, false),
+            Some(&"undo") => xboard.take_back(1),
+            Some(&"remove") => xboard.take_back(2),
+            Some(&"setboard") => xboard.set_board(&args[1..]),
+            Some(&"level") => xboard.level(&args[1..]),
+            Some(&"st") => match args.get(1).and_then(|seconds| seconds.parse::<f64>().ok()) {
+                Some(seconds) => xboard.time_control = TimeControl::PerMove(Duration::from_secs_f64(seconds)),
+                None => println!("Error (bad arguments): st"),
+            },
+            Some(&"sd") => match args.get(1).and_then(|depth| depth.parse::<u32>().ok()) {
+                Some(depth) => xboard.max_depth = Some(depth),
+                None => println!("Error (bad arguments): sd"),
+            },
+            // our clock and the opponent's, in centiseconds
+            Some(&"time") => xboard.engine_time = args.get(1).and_then(|time| time.parse::<u64>().ok()).map(|centis| centis * 10),
+            Some(&"otim") => {}
+            Some(&"result") => {
+                xboard.wait_for_search(true, true);
+                xboard.game_over = true;
+                xboard.engine_side = None;
+            }
+            Some(&"cores") => match args.get(1).and_then(|cores| cores.parse::<usize>().ok()) {
+                Some(cores) => xboard.engine().set_threads(cores),
+                None => println!("Error (bad arguments): cores"),
+            },
+            Some(&"egtpath") => xboard.egtpath(&args[1..]),
+            Some(&"post") => xboard.post = true,
+            Some(&"nopost") => xboard.post = false,
+            Some(&"random") | Some(&"hard") | Some(&"easy") | Some(&"computer") | Some(&"name") | Some(&"rating") => {}
+            Some(&"quit") => {
+                xboard.wait_for_search(true, true);
+                return;
+            }
+            Some(other) => println!("Error (unknown command): {}", other),
+            None => {}
+        }
+        let _ = stdout().flush();
+    }
+    xboard.wait_for_search(false, false);
+}
@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use crate::time_control::{Bonus, TimeControl};
+use crate::visual::PLAYER;
+
+fn seat(player: PLAYER) -> usize {
+    match player {
+        PLAYER::WHITE => 0,
+        PLAYER::BLACK => 1,
+    }
+}
+
+fn other(player: PLAYER) -> PLAYER {
+    match player {
+        PLAYER::WHITE => PLAYER::BLACK,
+        PLAYER::BLACK => PLAYER::WHITE,
+    }
+}
+
+// A game's pair of clocks, on the server's monotonic time (what a time_manager::Clock says).
+// Neither runs before the first move; after that only the side to move's does.
+#[derive(Clone, Debug)]
+pub struct ChessClock {
+    pub time_control: TimeControl,
+    // what each side had when its clock last stopped
+    banked: [Duration; 2],
+    // moves each side has made
+    moves: [u32; 2],
+    // the side whose clock is running, and since when
+    running: Option<(PLAYER, Duration)>,
+}
+
+impl ChessClock {
+    pub fn new(time_control: TimeControl) -> ChessClock {
+        let time = time_control.stages[0].time;
+        ChessClock {
+            time_control,
+            banked: [time, time],
+            moves: [0, 0],
+            running: None,
+        }
+    }
+
+    // a clock picked up where it was stopped, with running's time going from now
+    pub fn resume(time_control: TimeControl, banked: [Duration; 2], moves: [u32; 2], running: Option<PLAYER>, now: Duration) -> ChessClock {
+        ChessClock {
+            time_control,
+            banked,
+            moves,
+            running: running.map(|player| (player, now)),
+        }
+    }
+
+    pub fn running(&self) -> Option<PLAYER> {
+        self.running.map(|(player, _)| player)
+    }
+
+    pub fn banked(&self, player: PLAYER) -> Duration {
+        self.banked[seat(player)]
+    }
+
+    // how much of the time player has spent on this move comes off their clock
+    fn charged(&self, player: PLAYER, spent: Duration) -> Duration {
+        let stage = self.time_control.stage_for(self.moves[seat(player)] + 1);
+        match stage.bonus {
+            Bonus::Delay => spent.saturating_sub(stage.seconds),
+            Bonus::Increment | Bonus::Bronstein => spent,
+        }
+    }
+
+    fn spent(&self, player: PLAYER, now: Duration) -> Duration {
+        match self.running {
+            Some((side, since)) if side == player => now.saturating_sub(since),
+            _ => Duration::ZERO,
+        }
+    }
+
+    pub fn remaining(&self, player: PLAYER, now: Duration) -> Duration {
+        self.banked(player).saturating_sub(self.charged(player, self.spent(player, now)))
+    }
+
+    // the side whose time has run out
+    pub fn flagged(&self, now: Duration) -> Option<PLAYER> {
+        let player = self.running()?;
+        self.remaining(player, now).is_zero().then_some(player)
+    }
+
+    // player has moved: their clock stops and gets its bonus, and the other one starts
+    pub fn press(&mut self, player: PLAYER, now: Duration) {
+        let spent = self.spent(player, now);
+        let stage = *self.time_control.stage_for(self.moves[seat(player)] + 1);
+        let mut left = self.remaining(player, now);
+        left += match stage.bonus {
+            Bonus::Increment => stage.seconds,
+            Bonus::Bronstein => spent.min(stage.seconds),
+            Bonus::Delay => Duration::ZERO,
+        };
+        self.moves[seat(player)] += 1;
+        left += self.time_control.added_after(self.moves[seat(player)]).unwrap_or_default();
+        self.banked[seat(player)] = left;
+        self.running = Some((other(player), now));
+    }
+
+    // once the game is over
+    pub fn stop(&mut self, now: Duration) {
+        if let Some(player) = self.running() {
+            self.banked[seat(player)] = self.remaining(player, now);
+            self.running = None;
+        }
+    }
+}
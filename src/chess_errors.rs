@@ -14,6 +14,33 @@ pub enum ChessErrors {
     InvalidMove(String),
     InvalidPromotion(String),
     PieceBetween(String),
+    KingInCheck(String),
+    InvalidFen(String),
+    BookError(String),
+    TablebaseError(String),
+    WeightsError(String),
+    NetworkError(String),
+    GameNotFound(String),
+    GameOver(String),
+    NoDrawOffer(String),
+    NotYourTurn(String),
+    NotAPlayer(String),
+    SeatTaken(String),
+    AlreadySeated(String),
+    InvalidInvite(String),
+    RandomError(String),
+    InvalidTimeControl(String),
+    SeekNotFound(String),
+    InvalidUsername(String),
+    WeakPassword(String),
+    UsernameTaken(String),
+    InvalidCredentials,
+    NotLoggedIn,
+    UserNotFound(String),
+    AccountsError(String),
+    InvalidPool(String),
+    StorageError(String),
+    InvalidConfig(String),
     Utf8Error
 }
 
@@ -25,6 +52,51 @@ impl From<Utf8Error> for ChessErrors {
 
 //Utf8Error
 
+impl ChessErrors {
+    // a stable name for the error, for clients that want to tell them apart
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ChessErrors::InvalidNotation(_) => "invalid_notation",
+            ChessErrors::WrongPlayer(_) => "wrong_player",
+            ChessErrors::NoPiece(_) => "no_piece",
+            ChessErrors::PlayerPieceAlreadyThere(_) => "player_piece_already_there",
+            ChessErrors::PawnCantAttackForward(_) => "pawn_cant_attack_forward",
+            ChessErrors::PawnCanOnlyAttackDiagonal(_) => "pawn_can_only_attack_diagonal",
+            ChessErrors::InvalidMove(_) => "invalid_move",
+            ChessErrors::InvalidPromotion(_) => "invalid_promotion",
+            ChessErrors::PieceBetween(_) => "piece_between",
+            ChessErrors::KingInCheck(_) => "king_in_check",
+            ChessErrors::InvalidFen(_) => "invalid_fen",
+            ChessErrors::BookError(_) => "book_error",
+            ChessErrors::TablebaseError(_) => "tablebase_error",
+            ChessErrors::WeightsError(_) => "weights_error",
+            ChessErrors::NetworkError(_) => "network_error",
+            ChessErrors::GameNotFound(_) => "game_not_found",
+            ChessErrors::GameOver(_) => "game_over",
+            ChessErrors::NoDrawOffer(_) => "no_draw_offer",
+            ChessErrors::NotYourTurn(_) => "not_your_turn",
+            ChessErrors::NotAPlayer(_) => "not_a_player",
+            ChessErrors::SeatTaken(_) => "seat_taken",
+            ChessErrors::AlreadySeated(_) => "already_seated",
+            ChessErrors::InvalidInvite(_) => "invalid_invite",
+            ChessErrors::RandomError(_) => "random_error",
+            ChessErrors::InvalidTimeControl(_) => "invalid_time_control",
+            ChessErrors::SeekNotFound(_) => "seek_not_found",
+            ChessErrors::InvalidUsername(_) => "invalid_username",
+            ChessErrors::WeakPassword(_) => "weak_password",
+            ChessErrors::UsernameTaken(_) => "username_taken",
+            ChessErrors::InvalidCredentials => "invalid_credentials",
+            ChessErrors::NotLoggedIn => "not_logged_in",
+            ChessErrors::UserNotFound(_) => "user_not_found",
+            ChessErrors::AccountsError(_) => "accounts_error",
+            ChessErrors::InvalidPool(_) => "invalid_pool",
+            ChessErrors::StorageError(_) => "storage_error",
+            ChessErrors::InvalidConfig(_) => "invalid_config",
+            ChessErrors::Utf8Error => "utf8_error",
+        }
+    }
+}
+
 impl Error for ChessErrors {}
 
 impl fmt::Display for ChessErrors {
@@ -55,7 +127,88 @@ impl fmt::Display for ChessErrors {
                 write!(f, "piece cannot be promoted to {}", x)
             }
             ChessErrors::PieceBetween(x) => {
-                write!(f, "piece cannot move  because one of your pieces is at {}", x)
+                write!(f, "piece cannot move  because a piece is at {}", x)
+            }
+            ChessErrors::KingInCheck(x) => {
+                write!(f, "{} leaves your king in check", x)
+            }
+            ChessErrors::InvalidFen(x) => {
+                write!(f, "{} is not a valid FEN", x)
+            }
+            ChessErrors::BookError(x) => {
+                write!(f, "could not read opening book {}", x)
+            }
+            ChessErrors::NetworkError(x) => {
+                write!(f, "could not read network {}", x)
+            }
+            ChessErrors::WeightsError(x) => {
+                write!(f, "could not read evaluation weights {}", x)
+            }
+            ChessErrors::TablebaseError(x) => {
+                write!(f, "could not read tablebases in {}", x)
+            }
+            ChessErrors::GameNotFound(x) => {
+                write!(f, "there is no game {}", x)
+            }
+            ChessErrors::GameOver(x) => {
+                write!(f, "game {} is already over", x)
+            }
+            ChessErrors::NoDrawOffer(x) => {
+                write!(f, "there is no draw offer to answer in game {}", x)
+            }
+            ChessErrors::NotYourTurn(x) => {
+                write!(f, "it is not your turn in game {}", x)
+            }
+            ChessErrors::NotAPlayer(x) => {
+                write!(f, "you are not playing in game {}", x)
+            }
+            ChessErrors::SeatTaken(x) => {
+                write!(f, "that seat in game {} is taken", x)
+            }
+            ChessErrors::AlreadySeated(x) => {
+                write!(f, "you already play the other side in game {}", x)
+            }
+            ChessErrors::InvalidInvite(x) => {
+                write!(f, "game {} needs a valid invite to join", x)
+            }
+            ChessErrors::RandomError(x) => {
+                write!(f, "could not get random bytes {}", x)
+            }
+            ChessErrors::InvalidTimeControl(x) => {
+                write!(f, "{} is not a time control like 5+3", x)
+            }
+            ChessErrors::SeekNotFound(x) => {
+                write!(f, "there is no seek {}", x)
+            }
+            ChessErrors::InvalidUsername(x) => {
+                write!(f, "{} is not a valid username, use up to 20 letters, digits, _ or -", x)
+            }
+            ChessErrors::WeakPassword(x) => {
+                write!(f, "passwords need at least {} characters", x)
+            }
+            ChessErrors::UsernameTaken(x) => {
+                write!(f, "the username {} is taken", x)
+            }
+            ChessErrors::InvalidCredentials => {
+                write!(f, "wrong username or password")
+            }
+            ChessErrors::NotLoggedIn => {
+                write!(f, "you need to log in")
+            }
+            ChessErrors::UserNotFound(x) => {
+                write!(f, "there is no user {}", x)
+            }
+            ChessErrors::AccountsError(x) => {
+                write!(f, "could not store accounts {}", x)
+            }
+            ChessErrors::InvalidPool(x) => {
+                write!(f, "{} is not a rating pool, use bullet, blitz, rapid or classical", x)
+            }
+            ChessErrors::StorageError(x) => {
+                write!(f, "could not store games {}", x)
+            }
+            ChessErrors::InvalidConfig(x) => {
+                write!(f, "bad server config {}", x)
             }
             _ => {
                 write!(f, "ddddd")
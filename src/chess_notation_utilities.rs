@@ -572,3 +572,46 @@ pub fn convert_move_notation_to_xy(
     let to_point = Point{x: to_col,y: to_row};
     Ok((from_point, to_point))
 }
+
+pub fn move_notation_to_indexes(chess_move: &str) -> Result<(usize, usize), chess_errors::ChessErrors> {
+    // moves look like "a2-a4" or "a7-a8pq"
+    if let Some(index_of_dash) = chess_move.find('-') {
+        let from_spot = &chess_move[0..index_of_dash];
+        if let Some(to_spot) = chess_move.get(index_of_dash + 1..index_of_dash + 3) {
+            return convert_move_notation_to_indexes(from_spot, to_spot);
+        }
+    }
+    Err(chess_errors::ChessErrors::InvalidNotation(chess_move.to_string()))
+}
+
+// "e2e4" or "e7e8q" (what UCI and most GUIs send) to "e2-e4" or "e7-e8pq"
+pub fn uci_to_move_notation(uci_move: &str) -> Result<String, chess_errors::ChessErrors> {
+    let invalid = || chess_errors::ChessErrors::InvalidNotation(uci_move.to_string());
+    if uci_move.len() != 4 && uci_move.len() != 5 {
+        return Err(invalid());
+    }
+    let from_spot = uci_move.get(0..2).ok_or_else(invalid)?;
+    let to_spot = uci_move.get(2..4).ok_or_else(invalid)?;
+    check_for_valid_notation(from_spot)?;
+    check_for_valid_notation(to_spot)?;
+    match uci_move.get(4..5) {
+        None => Ok(format!("{}-{}", from_spot, to_spot)),
+        // knights are "k" in this crate's promotion notation
+        Some("n") => Ok(format!("{}-{}pk", from_spot, to_spot)),
+        Some(promotion @ ("q" | "r" | "b")) => Ok(format!("{}-{}p{}", from_spot, to_spot, promotion)),
+        Some(_) => Err(invalid()),
+    }
+}
+
+pub fn move_notation_to_uci(chess_move: &str) -> String {
+    let mut uci_move = chess_move.replace('-', "");
+    if let Some(index_of_p) = uci_move.find('p') {
+        let promotion = match &uci_move[index_of_p + 1..] {
+            "k" => "n".to_string(),
+            other => other.to_string(),
+        };
+        uci_move.truncate(index_of_p);
+        uci_move.push_str(&promotion);
+    }
+    uci_move
+}
@@ -0,0 +1,390 @@
+// The AlphaZero input and policy encoding, so positions and search results can be fed to a
+// network trained elsewhere. Everything is seen from the side to move: its pieces come
+// first and the board is flipped vertically when black is to move.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::chess_errors::ChessErrors;
+use crate::chess_notation_utilities;
+use crate::game::Game;
+use crate::mcts::MoveStats;
+use crate::visual::{GameState, PLAYER};
+use crate::zobrist;
+
+// positions of history in the input, the current one included
+pub const HISTORY: usize = 8;
+// 6 for each side's pieces and 2 for repetitions
+pub const PLANES_PER_POSITION: usize = 14;
+// colour, move count, 4 castling rights and the 50 move count
+pub const CONSTANT_PLANES: usize = 7;
+pub const NUM_PLANES: usize = HISTORY * PLANES_PER_POSITION + CONSTANT_PLANES;
+
+// 56 queen-like moves, 8 knight moves and 9 underpromotions from every square
+pub const MOVE_PLANES: usize = 73;
+pub const POLICY_SIZE: usize = MOVE_PLANES * 64;
+
+// (file, rank) steps, north first and then clockwise
+const QUEEN_DIRECTIONS: [(i32, i32); 8] = [(0, 1), (1, 1), (1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1)];
+const KNIGHT_JUMPS: [(i32, i32); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+// in the order the underpromotion planes use, with their letter in our move notation
+const UNDERPROMOTIONS: [char; 3] = ['k', 'b', 'r'];
+
+// the board index (0 is a8) as (file, rank) with rank 0 the mover's own back rank
+fn oriented(index: usize, player: PLAYER) -> (i32, i32) {
+    let (row, col) = ((index / 8) as i32, (index % 8) as i32);
+    match player {
+        PLAYER::WHITE => (col, 7 - row),
+        PLAYER::BLACK => (col, row),
+    }
+}
+
+fn board_index(file: i32, rank: i32, player: PLAYER) -> usize {
+    let row = match player {
+        PLAYER::WHITE => 7 - rank,
+        PLAYER::BLACK => rank,
+    };
+    (row * 8 + file) as usize
+}
+
+// the positions the game went through, from start to the one after the last move
+pub fn positions(start: &GameState, moves: &[String]) -> Result<Vec<GameState>, ChessErrors> {
+    let mut game = Game {
+        state: start.clone(),
+        turn_history: Vec::new(),
+    };
+    let mut positions = vec![start.clone()];
+    for chess_move in moves {
+        game.make_move(chess_move)?;
+        positions.push(game.state.clone());
+    }
+    Ok(positions)
+}
+
+// NUM_PLANES planes of 8x8, plane by plane and rank by rank from the mover's back rank.
+// history runs oldest first and ends with the position to encode; anything older than
+// HISTORY positions only counts towards repetitions.
+pub fn encode_planes(history: &[GameState]) -> Vec<f32> {
+    let mut planes = vec![0.0; NUM_PLANES * 64];
+    let current = match history.last() {
+        Some(current) => current,
+        None => return planes,
+    };
+    let player = current.player_turn;
+    let keys: Vec<u64> = history.iter().map(zobrist::position_key).collect();
+    for step in 0..HISTORY.min(history.len()) {
+        let at = history.len() - 1 - step;
+        let state = &history[at];
+        let base = step * PLANES_PER_POSITION;
+        for (index, piece_opt) in state.state.iter().enumerate() {
+            let piece = match piece_opt {
+                Some(piece) => piece,
+                None => continue,
+            };
+            let piece_index = match zobrist::piece_index(piece.get_unicode_val()) {
+                Some(piece_index) => piece_index % 6,
+                None => continue,
+            };
+            let side = if piece.get_player() == player { 0 } else { 6 };
+            let (file, rank) = oriented(index, player);
+            planes[(base + side + piece_index) * 64 + (rank * 8 + file) as usize] = 1.0;
+        }
+        let repetitions = keys[..at].iter().filter(|key| **key == keys[at]).count();
+        for plane in 0..repetitions.min(2) {
+            planes[(base + 12 + plane) * 64..(base + 13 + plane) * 64].fill(1.0);
+        }
+    }
+    let rights = current.castling_rights();
+    let (own, other) = match player {
+        PLAYER::WHITE => (['K', 'Q'], ['k', 'q']),
+        PLAYER::BLACK => (['k', 'q'], ['K', 'Q']),
+    };
+    let constants = [
+        if player == PLAYER::BLACK { 1.0 } else { 0.0 },
+        current.fullmove_number as f32,
+        if rights.contains(own[0]) { 1.0 } else { 0.0 },
+        if rights.contains(own[1]) { 1.0 } else { 0.0 },
+        if rights.contains(other[0]) { 1.0 } else { 0.0 },
+        if rights.contains(other[1]) { 1.0 } else { 0.0 },
+        current.halfmove_clock as f32,
+    ];
+    let base = HISTORY * PLANES_PER_POSITION;
+    for (plane, value) in constants.iter().enumerate() {
+        planes[(base + plane) * 64..(base + plane + 1) * 64].fill(*value);
+    }
+    planes
+}
+
+// The index of a move in the 73x8x8 policy, plane * 64 + from square, both from the
+// point of view of player who makes it. Promoting to a queen is a plain queen-like move.
+pub fn move_to_policy_index(chess_move: &str, player: PLAYER) -> Option<usize> {
+    let (from, to) = chess_notation_utilities::move_notation_to_indexes(chess_move).ok()?;
+    let (from_file, from_rank) = oriented(from, player);
+    let (to_file, to_rank) = oriented(to, player);
+    let (df, dr) = (to_file - from_file, to_rank - from_rank);
+    let square = (from_rank * 8 + from_file) as usize;
+    let promotion = chess_move.get(6..7).and_then(|piece| piece.chars().next());
+    let plane = if let Some(piece) = promotion.filter(|piece| *piece != 'q') {
+        let kind = UNDERPROMOTIONS.iter().position(|p| *p == piece)?;
+        if dr != 1 || df.abs() > 1 {
+            return None;
+        }
+        64 + kind * 3 + (df + 1) as usize
+    } else if let Some(jump) = KNIGHT_JUMPS.iter().position(|jump| *jump == (df, dr)) {
+        56 + jump
+    } else {
+        let distance = df.abs().max(dr.abs());
+        if distance == 0 || (df != 0 && dr != 0 && df.abs() != dr.abs()) {
+            return None;
+        }
+        let direction = QUEEN_DIRECTIONS.iter().position(|dir| *dir == (df.signum(), dr.signum()))?;
+        direction * 7 + (distance - 1) as usize
+    };
+    Some(plane * 64 + square)
+}
+
+// The move a policy index stands for in this position. Needs the position to tell a
+// pawn reaching the last rank, which promotes to a queen, from any other piece.
+pub fn policy_index_to_move(index: usize, state: &GameState) -> Option<String> {
+    if index >= POLICY_SIZE {
+        return None;
+    }
+    let player = state.player_turn;
+    let (plane, square) = (index / 64, index % 64);
+    let (file, rank) = ((square % 8) as i32, (square / 8) as i32);
+    let (df, dr, promotion) = if plane < 56 {
+        let (x, y) = QUEEN_DIRECTIONS[plane / 7];
+        let distance = (plane % 7 + 1) as i32;
+        (x * distance, y * distance, None)
+    } else if plane < 64 {
+        let (x, y) = KNIGHT_JUMPS[plane - 56];
+        (x, y, None)
+    } else {
+        let kind = (plane - 64) / 3;
+        ((plane - 64) as i32 % 3 - 1, 1, Some(UNDERPROMOTIONS[kind]))
+    };
+    let (to_file, to_rank) = (file + df, rank + dr);
+    if !(0..8).contains(&to_file) || !(0..8).contains(&to_rank) {
+        return None;
+    }
+    let from = board_index(file, rank, player);
+    let to = board_index(to_file, to_rank, player);
+    let mut chess_move = format!(
+        "{}-{}",
+        chess_notation_utilities::index_to_spot(from),
+        chess_notation_utilities::index_to_spot(to)
+    );
+    let pawn = state.state[from].as_ref().is_some_and(|piece| matches!(zobrist::piece_index(piece.get_unicode_val()), Some(0) | Some(6)));
+    match promotion {
+        Some(piece) => chess_move.push_str(&format!("p{}", piece)),
+        None if pawn && to_rank == 7 => chess_move.push_str("pq"),
+        None => {}
+    }
+    Some(chess_move)
+}
+
+// every legal move in the game with its policy index
+pub fn legal_policy_moves(game: &Game) -> Vec<(String, usize)> {
+    let player = game.state.player_turn;
+    game.get_validated_moves(player)
+        .into_iter()
+        .filter_map(|chess_move| move_to_policy_index(&chess_move, player).map(|index| (chess_move, index)))
+        .collect()
+}
+
+// the search's visit counts as a policy target over all POLICY_SIZE moves
+pub fn policy_from_visits(stats: &[MoveStats], player: PLAYER) -> Vec<f32> {
+    let mut policy = vec![0.0; POLICY_SIZE];
+    let total: u32 = stats.iter().map(|stat| stat.visits).sum();
+    if total == 0 {
+        return policy;
+    }
+    for stat in stats {
+        if let Some(index) = move_to_policy_index(&stat.chess_move, player) {
+            policy[index] = stat.visits as f32 / total as f32;
+        }
+    }
+    policy
+}
+
+// Writes data as a little endian float32 .npy file of the given shape, which
+// numpy.load reads back as is.
+pub fn write_npy<P: AsRef<Path>>(path: P, shape: &[usize], data: &[f32]) -> io::Result<()> {
+    if shape.iter().product::<usize>() != data.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "shape does not match the data"));
+    }
+    let dims: Vec<String> = shape.iter().map(|dim| dim.to_string()).collect();
+    let shape = if dims.len() == 1 { format!("({},)", dims[0]) } else { format!("({})", dims.join(", ")) };
+    let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}", shape);
+    // magic, version and length take 10 bytes, the whole header is padded to 64
+    let padding = 64 - (10 + header.len() + 1) % 64;
+    header.push_str(&" ".repeat(padding % 64));
+    header.push('\n');
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(b"\x93NUMPY\x01\x00")?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for value in data {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // castling both ways for both sides, underpromotions with and without a capture, en passant
+    const POSITIONS: [&str; 5] = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+        "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R w KQ - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 b kq - 0 1",
+        "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+    ];
+
+    #[test]
+    fn every_legal_move_survives_a_round_trip() {
+        let mut seen = Vec::new();
+        for fen in POSITIONS {
+            let game = Game::game_from_fen(fen).unwrap();
+            let moves = game.get_validated_moves(game.state.player_turn);
+            let encoded = legal_policy_moves(&game);
+            assert_eq!(encoded.len(), moves.len(), "{}", fen);
+            let mut indexes: Vec<usize> = encoded.iter().map(|(_, index)| *index).collect();
+            indexes.sort();
+            indexes.dedup();
+            assert_eq!(indexes.len(), moves.len(), "two moves share an index in {}", fen);
+            for (chess_move, index) in encoded {
+                assert!(index < POLICY_SIZE);
+                assert_eq!(policy_index_to_move(index, &game.state).as_deref(), Some(chess_move.as_str()), "{}", fen);
+                seen.push(chess_move);
+            }
+        }
+        // the special moves really were in there
+        for chess_move in ["e1-g1", "e1-c1", "e8-g8", "e8-c8", "b7-a8pk", "b7-b8pb", "b2-a1pr", "b2-b1pq", "e5-f6"] {
+            assert!(seen.iter().any(|seen| seen == chess_move), "{} not tried", chess_move);
+        }
+    }
+
+    #[test]
+    fn black_moves_are_seen_from_blacks_side() {
+        // e7-e5 for black is e2-e4 for white: two squares north from the same file
+        assert_eq!(move_to_policy_index("e7-e5", PLAYER::BLACK), move_to_policy_index("e2-e4", PLAYER::WHITE));
+        assert_eq!(move_to_policy_index("g8-f6", PLAYER::BLACK), move_to_policy_index("g1-f3", PLAYER::WHITE));
+        assert_eq!(move_to_policy_index("e2-e2", PLAYER::WHITE), None);
+        assert_eq!(move_to_policy_index("a1-b3pk", PLAYER::WHITE), None);
+        let start = GameState::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        assert_eq!(policy_index_to_move(POLICY_SIZE, &start), None);
+    }
+
+    fn history(fen: &str, moves: &[&str]) -> Vec<GameState> {
+        let moves: Vec<String> = moves.iter().map(|chess_move| chess_move.to_string()).collect();
+        positions(&GameState::from_fen(fen).unwrap(), &moves).unwrap()
+    }
+
+    // the squares set on a plane, rank by rank from the mover's back rank
+    fn squares(planes: &[f32], plane: usize) -> Vec<usize> {
+        (0..64).filter(|square| planes[plane * 64 + square] == 1.0).collect()
+    }
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    #[test]
+    fn pieces_land_on_their_plane_and_square() {
+        let planes = encode_planes(&history(START, &[]));
+        assert_eq!(planes.len(), NUM_PLANES * 64);
+        // the mover's king, pawns and knights, then the other side's king
+        assert_eq!(squares(&planes, 5), [4]);
+        assert_eq!(squares(&planes, 0), (8..16).collect::<Vec<_>>());
+        assert_eq!(squares(&planes, 1), [1, 6]);
+        assert_eq!(squares(&planes, 6 + 5), [60]);
+
+        // black to move sees the board flipped vertically, its own pieces first
+        let planes = encode_planes(&history(START, &["e2-e4"]));
+        assert_eq!(squares(&planes, 5), [4]);
+        assert_eq!(squares(&planes, 6 + 5), [60]);
+        // e4 is the fourth rank for white and the fifth for black
+        assert!(squares(&planes, 6).contains(&36));
+        assert!(!squares(&planes, 6).contains(&28));
+        assert!(squares(&encode_planes(&history(START, &["e2-e4", "a7-a6"])), 0).contains(&28));
+    }
+
+    #[test]
+    fn older_positions_follow_the_current_one() {
+        let planes = encode_planes(&history(START, &["e2-e4"]));
+        // the start position, one back, still from black's side
+        let base = PLANES_PER_POSITION;
+        assert_eq!(squares(&planes, base + 5), [4]);
+        assert!(squares(&planes, base + 6).contains(&52));
+        assert!(!squares(&planes, base + 6).contains(&36));
+        assert!(!squares(&planes, 6).contains(&52));
+        // nothing further back than the game goes
+        assert!(planes[2 * PLANES_PER_POSITION * 64..HISTORY * PLANES_PER_POSITION * 64].iter().all(|value| *value == 0.0));
+    }
+
+    #[test]
+    fn repetitions_fill_their_planes() {
+        let knights = ["g1-f3", "g8-f6", "f3-g1", "f6-g8"];
+        let planes = encode_planes(&history(START, &knights));
+        assert_eq!(squares(&planes, 12).len(), 64);
+        assert!(squares(&planes, 13).is_empty());
+        // the position before had not been seen yet
+        assert!(squares(&planes, PLANES_PER_POSITION + 12).is_empty());
+
+        let twice: Vec<&str> = knights.iter().chain(knights.iter()).copied().collect();
+        let planes = encode_planes(&history(START, &twice));
+        assert_eq!((squares(&planes, 12).len(), squares(&planes, 13).len()), (64, 64));
+        // four plies back is the first repetition
+        assert_eq!(squares(&planes, 4 * PLANES_PER_POSITION + 12).len(), 64);
+        assert!(squares(&planes, 4 * PLANES_PER_POSITION + 13).is_empty());
+    }
+
+    #[test]
+    fn constant_planes_are_seen_from_the_mover() {
+        let base = HISTORY * PLANES_PER_POSITION;
+        let constants = |fen: &str| -> Vec<f32> {
+            let planes = encode_planes(&history(fen, &[]));
+            (0..CONSTANT_PLANES)
+                .map(|plane| {
+                    let values = &planes[(base + plane) * 64..(base + plane + 1) * 64];
+                    assert!(values.iter().all(|value| *value == values[0]));
+                    values[0]
+                })
+                .collect()
+        };
+        // colour, move number, own king and queen side, the other's, then the 50 move count
+        assert_eq!(constants("r3k2r/8/8/8/8/8/8/R3K2R w Kq - 7 12"), [0.0, 12.0, 1.0, 0.0, 0.0, 1.0, 7.0]);
+        assert_eq!(constants("r3k2r/8/8/8/8/8/8/R3K2R b Kq - 7 12"), [1.0, 12.0, 0.0, 1.0, 1.0, 0.0, 7.0]);
+    }
+
+    // the header and the values of an .npy file
+    fn read_npy(path: &Path) -> (String, Vec<f32>) {
+        let bytes = std::fs::read(path).unwrap();
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap().to_string();
+        let values = bytes[10 + header_len..].chunks_exact(4).map(|value| f32::from_le_bytes(value.try_into().unwrap())).collect();
+        (header, values)
+    }
+
+    #[test]
+    fn npy_files_have_the_header_numpy_expects() {
+        let path = std::env::temp_dir().join(format!("chess-encoding-{}.npy", std::process::id()));
+        let data: Vec<f32> = (0..6).map(|value| value as f32 * 0.5).collect();
+        write_npy(&path, &[2, 3], &data).unwrap();
+        let (header, values) = read_npy(&path);
+        assert_eq!(header.trim_end(), "{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3), }");
+        assert!(header.ends_with('\n'));
+        assert_eq!(values, data);
+
+        // a single dimension is written as a one element tuple
+        write_npy(&path, &[6], &data).unwrap();
+        assert_eq!(read_npy(&path).0.trim_end(), "{'descr': '<f4', 'fortran_order': False, 'shape': (6,), }");
+        assert!(write_npy(&path, &[4], &data).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,827 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chess_notation_utilities;
+use crate::evaluation::{self, EvalBackend};
+use crate::game::Game;
+use crate::move_picker;
+use crate::nnue::{Accumulator, Network};
+use crate::prng::Prng;
+use crate::skill::Skill;
+use crate::syzygy::{Tablebase, Wdl};
+use crate::time_manager::{Clock, SystemClock, TimeManager};
+use crate::zobrist;
+
+use move_picker::{HistoryTable, KillerMoves, MovePicker, MAX_PLY};
+
+pub const MATE_SCORE: i32 = 30_000;
+const INFINITY: i32 = 32_000;
+// any score past this is a forced mate
+const MATE_BOUND: i32 = MATE_SCORE - MAX_PLY as i32;
+
+pub const DEFAULT_HASH_MB: usize = 16;
+
+// how often a search thread adds its nodes to the shared count
+const NODE_BATCH: u64 = 1024;
+
+// a tablebase win, less the distance to zeroing, so it sorts below any found mate
+pub const TB_WIN_SCORE: i32 = 20_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Score {
+    Centipawns(i32),
+    // moves until mate, negative when the side to move is getting mated
+    Mate(i32),
+}
+
+impl Score {
+    pub fn from_search(value: i32) -> Self {
+        if value >= MATE_BOUND {
+            Score::Mate((MATE_SCORE - value + 1) / 2)
+        } else if value <= -MATE_BOUND {
+            Score::Mate(-(MATE_SCORE + value) / 2)
+        } else {
+            Score::Centipawns(value)
+        }
+    }
+
+    // How likely a win, draw or loss is for the side to move, in per mille. Ahead by
+    // WDL_EVEN_MARGIN the game is as likely won as drawn.
+    pub fn wdl(&self) -> WdlEstimate {
+        let cp = match *self {
+            Score::Mate(moves) if moves > 0 => return WdlEstimate { win: 1000, draw: 0, loss: 0 },
+            Score::Mate(_) => return WdlEstimate { win: 0, draw: 0, loss: 1000 },
+            Score::Centipawns(cp) => cp as f64,
+        };
+        let rate = |cp: f64| (1000.0 / (1.0 + ((WDL_EVEN_MARGIN - cp) / WDL_SPREAD).exp())).round() as u32;
+        let (win, loss) = (rate(cp), rate(-cp));
+        WdlEstimate { win, draw: 1000 - win - loss, loss }
+    }
+}
+
+const WDL_EVEN_MARGIN: f64 = 250.0;
+const WDL_SPREAD: f64 = 70.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct WdlEstimate {
+    pub win: u32,
+    pub draw: u32,
+    pub loss: u32,
+}
+
+// the way UCI wants it after "score"
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Score::Centipawns(cp) => write!(f, "cp {}", cp),
+            Score::Mate(moves) => write!(f, "mate {}", moves),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchLimits {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+    pub infinite: bool,
+    // what is left on the clocks, the time manager works out the rest
+    pub wtime: Option<Duration>,
+    pub btime: Option<Duration>,
+    pub winc: Duration,
+    pub binc: Duration,
+    pub movestogo: Option<u32>,
+    // how many of the best root moves to search fully, 0 is the same as 1
+    pub multipv: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchInfo {
+    pub depth: u32,
+    pub seldepth: u32,
+    pub score: Score,
+    pub nodes: u64,
+    pub time: Duration,
+    pub pv: Vec<String>,
+    // which of the multipv lines this is, from 1
+    pub multipv: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct PvLine {
+    pub score: Score,
+    pub pv: Vec<String>,
+    value: i32,
+}
+
+// one of the ranked lines analyse returns
+#[derive(Clone, Debug)]
+pub struct AnalysisLine {
+    // how deep the search got before it ran out of depth or time
+    pub depth: u32,
+    pub score: Score,
+    pub pv: Vec<String>,
+    pub wdl: WdlEstimate,
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub best_move: Option<String>,
+    pub ponder_move: Option<String>,
+    pub score: Score,
+    pub depth: u32,
+    pub nodes: u64,
+    pub pv: Vec<String>,
+    // best first, as many as multipv asked for
+    pub lines: Vec<PvLine>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Clone, Debug)]
+struct TtEntry {
+    depth: u32,
+    score: i32,
+    bound: Bound,
+    best_move: Option<String>,
+}
+
+// one slot of the table. The key is stored xor'd with the data so a slot torn by two
+// threads writing at once no longer matches its key and is just a miss.
+struct TtSlot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+// shared by every search thread without locks
+pub struct TranspositionTable {
+    slots: Vec<TtSlot>,
+}
+
+// moves packed into 16 bits: from, to, promotion piece and a bit saying there is a move at all
+fn encode_move(chess_move: &str) -> u64 {
+    let (from, to) = match chess_notation_utilities::move_notation_to_indexes(chess_move) {
+        Ok(indexes) => indexes,
+        Err(_) => return 0,
+    };
+    let promotion = match chess_move.find('p').map(|index_of_p| &chess_move[index_of_p + 1..]) {
+        Some("q") => 1,
+        Some("r") => 2,
+        Some("b") => 3,
+        Some("k") => 4,
+        _ => 0,
+    };
+    (1 << 15 | promotion << 12 | from << 6 | to) as u64
+}
+
+fn decode_move(packed: u64) -> Option<String> {
+    if packed & (1 << 15) == 0 {
+        return None;
+    }
+    let from = chess_notation_utilities::index_to_spot(((packed >> 6) & 63) as usize);
+    let to = chess_notation_utilities::index_to_spot((packed & 63) as usize);
+    let promotion = match (packed >> 12) & 7 {
+        1 => "pq",
+        2 => "pr",
+        3 => "pb",
+        4 => "pk",
+        _ => "",
+    };
+    Some(format!("{}-{}{}", from, to, promotion))
+}
+
+fn encode_bound(bound: Bound) -> u64 {
+    match bound {
+        Bound::Exact => 0,
+        Bound::Lower => 1,
+        Bound::Upper => 2,
+    }
+}
+
+fn decode_bound(bits: u64) -> Bound {
+    match bits {
+        1 => Bound::Lower,
+        2 => Bound::Upper,
+        _ => Bound::Exact,
+    }
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> Self {
+        let slot_size = std::mem::size_of::<TtSlot>();
+        let count = (size_mb.max(1) * 1024 * 1024 / slot_size).max(1);
+        TranspositionTable {
+            slots: (0..count).map(|_| TtSlot { key: AtomicU64::new(0), data: AtomicU64::new(0) }).collect(),
+        }
+    }
+
+    fn probe(&self, key: u64) -> Option<TtEntry> {
+        let slot = &self.slots[(key % self.slots.len() as u64) as usize];
+        let data = slot.data.load(Ordering::Relaxed);
+        if data == 0 || slot.key.load(Ordering::Relaxed) ^ data != key {
+            return None;
+        }
+        // score in the low 16 bits, then depth, bound and move
+        Some(TtEntry {
+            score: data as u16 as i16 as i32,
+            depth: ((data >> 16) & 0xFF) as u32,
+            bound: decode_bound((data >> 24) & 3),
+            best_move: decode_move(data >> 32),
+        })
+    }
+
+    fn store(&self, key: u64, depth: u32, score: i32, bound: Bound, best_move: Option<&str>) {
+        let slot = &self.slots[(key % self.slots.len() as u64) as usize];
+        let old_data = slot.data.load(Ordering::Relaxed);
+        // keep the deeper result for the same position
+        if old_data != 0 && slot.key.load(Ordering::Relaxed) ^ old_data == key
+            && ((old_data >> 16) & 0xFF) as u32 > depth && bound != Bound::Exact {
+            return;
+        }
+        let data = (score as i16 as u16 as u64)
+            | (depth.min(255) as u64) << 16
+            | encode_bound(bound) << 24
+            | best_move.map_or(0, encode_move) << 32;
+        slot.key.store(key ^ data, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        for slot in self.slots.iter() {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+// mate scores are stored relative to the node, not the root
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score + ply as i32
+    } else if score <= -MATE_BOUND {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score - ply as i32
+    } else if score <= -MATE_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+fn play(game: &Game, chess_move: &str) -> Option<Game> {
+    let mut child = Game {
+        state: game.state.clone(),
+        turn_history: Vec::new(),
+    };
+    child.make_move(chess_move).ok()?;
+    Some(child)
+}
+
+pub struct Engine {
+    tt: Arc<TranspositionTable>,
+    history: HistoryTable,
+    threads: usize,
+    tablebase: Option<Arc<Tablebase>>,
+    clock: Arc<dyn Clock>,
+    skill: Skill,
+    prng: Prng,
+    eval_backend: EvalBackend,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new(DEFAULT_HASH_MB)
+    }
+}
+
+// everything one search thread needs for itself; the transposition table is what they share
+struct Worker<'a> {
+    tt: &'a TranspositionTable,
+    killers: KillerMoves,
+    history: HistoryTable,
+    limits: SearchLimits,
+    clock: &'a dyn Clock,
+    // clock reading at the hard time limit
+    deadline: Option<Duration>,
+    stop: &'a AtomicBool,
+    // every thread's nodes, added up in batches
+    total_nodes: &'a AtomicU64,
+    nodes: u64,
+    seldepth: u32,
+    stopped: bool,
+    pv_table: Vec<Vec<String>>,
+    path_keys: Vec<u64>,
+    // root moves already reported as better lines in a multipv search
+    excluded: Vec<String>,
+    eval_noise: i32,
+    noise_seed: u64,
+    // with a network, one accumulator per ply, each made from the one before it
+    network: Option<&'a Network>,
+    accumulators: Vec<Accumulator>,
+}
+
+impl Engine {
+    pub fn new(hash_mb: usize) -> Self {
+        Engine {
+            tt: Arc::new(TranspositionTable::new(hash_mb)),
+            history: HistoryTable::default(),
+            threads: 1,
+            tablebase: None,
+            clock: Arc::new(SystemClock::default()),
+            skill: Skill::default(),
+            prng: Prng::new(0),
+            eval_backend: EvalBackend::Classical,
+        }
+    }
+
+    pub fn set_skill(&mut self, skill: Skill) {
+        self.skill = skill;
+    }
+
+    pub fn skill(&self) -> Skill {
+        self.skill
+    }
+
+    // the weakened engine's choices and noise come from this, the same seed plays the same moves
+    pub fn set_seed(&mut self, seed: u64) {
+        self.prng = Prng::new(seed);
+    }
+
+    // where the search reads the time from
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn set_eval_backend(&mut self, eval_backend: EvalBackend) {
+        self.eval_backend = eval_backend;
+    }
+
+    pub fn eval_backend(&self) -> &EvalBackend {
+        &self.eval_backend
+    }
+
+    pub fn set_tablebase(&mut self, tablebase: Option<Arc<Tablebase>>) {
+        self.tablebase = tablebase;
+    }
+
+    pub fn resize_hash(&mut self, hash_mb: usize) {
+        self.tt = Arc::new(TranspositionTable::new(hash_mb));
+    }
+
+    // how many threads search together, the calling one included
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn new_game(&mut self) {
+        self.tt.clear();
+        self.history.clear();
+    }
+
+    // iterative deepening from the position in game, reporting every finished depth to on_info.
+    // the search ends at the limits or as soon as stop is set. Extra threads search the same
+    // position alongside and only help through the transposition table.
+    pub fn search<F: FnMut(&SearchInfo)>(&mut self, game: &Game, limits: &SearchLimits, stop: &AtomicBool, mut on_info: F) -> SearchResult {
+        self.history.age();
+        let root_moves = game.get_validated_moves(game.state.player_turn);
+        let mut result = SearchResult {
+            best_move: root_moves.first().cloned(),
+            ponder_move: None,
+            score: Score::Centipawns(0),
+            depth: 0,
+            nodes: 0,
+            pv: Vec::new(),
+            lines: Vec::new(),
+        };
+        if root_moves.is_empty() {
+            if game.state.is_in_check(game.state.player_turn) {
+                result.score = Score::Mate(0);
+            }
+            return result;
+        }
+
+        let mut limits = limits.clone();
+        self.skill.restrict(&mut limits);
+        let limits = &limits;
+        let multipv = limits.multipv.clamp(1, root_moves.len());
+        let mut time = TimeManager::new(self.clock.clone(), limits, game.state.player_turn);
+        let clock = &*self.clock;
+        let mut max_depth = limits.depth.unwrap_or(MAX_PLY as u32 - 1).clamp(1, MAX_PLY as u32 - 1);
+        // with few enough pieces the tablebase already knows the answer, no need to search
+        let tablebase = self.tablebase.as_ref().filter(|_| self.skill.is_full_strength());
+        if let Some((best_move, probe)) = tablebase.and_then(|tablebase| tablebase.best_move(game)) {
+            let score = match probe.wdl {
+                Wdl::Win => TB_WIN_SCORE - probe.dtz.abs(),
+                Wdl::Loss => -TB_WIN_SCORE + probe.dtz.abs(),
+                _ => 0,
+            };
+            result.best_move = Some(best_move.clone());
+            result.pv = vec![best_move];
+            result.score = Score::Centipawns(score);
+            result.depth = 1;
+            on_info(&SearchInfo {
+                depth: 1,
+                seldepth: 1,
+                score: result.score,
+                nodes: 0,
+                time: time.elapsed(),
+                pv: result.pv.clone(),
+                multipv: 1,
+            });
+            max_depth = 0;
+        }
+
+        let helpers_stop = AtomicBool::new(false);
+        let total_nodes = AtomicU64::new(0);
+        let tt = &*self.tt;
+        let deadline = time.deadline();
+        let mut main = Worker::new(tt, limits.clone(), clock, deadline, stop, &total_nodes, std::mem::take(&mut self.history));
+        let network = match &self.eval_backend {
+            EvalBackend::Nnue(network) => Some(&**network),
+            EvalBackend::Classical => None,
+        };
+        main.set_network(network);
+        main.eval_noise = self.skill.eval_noise();
+        main.noise_seed = self.prng.next_u64();
+        std::thread::scope(|scope| {
+            let helpers: Vec<_> = (1..self.threads)
+                .filter(|_| max_depth > 0)
+                .map(|id| {
+                    let (helpers_stop, total_nodes) = (&helpers_stop, &total_nodes);
+                    scope.spawn(move || {
+                        // helpers only stop when the main thread is done
+                        let limits = SearchLimits { depth: Some(max_depth), ..SearchLimits::default() };
+                        let mut helper = Worker::new(tt, limits, clock, None, helpers_stop, total_nodes, HistoryTable::default());
+                        helper.set_network(network);
+                        helper.iterate(game, id, max_depth);
+                    })
+                })
+                .collect();
+
+            for depth in 1..=max_depth {
+                let mut lines = Vec::new();
+                main.excluded.clear();
+                for pv_index in 0..multipv {
+                    main.seldepth = 0;
+                    let value = main.negamax(game, depth as i32, 0, -INFINITY, INFINITY);
+                    // a line that was cut short is only trusted if it is all we have
+                    if main.stopped && (result.depth > 0 || pv_index > 0) {
+                        break;
+                    }
+                    let pv = main.pv_table[0].clone();
+                    let first_move = match pv.first() {
+                        Some(first_move) => first_move.clone(),
+                        None => break,
+                    };
+                    main.excluded.push(first_move);
+                    let line = PvLine { score: Score::from_search(value), pv, value };
+                    on_info(&SearchInfo {
+                        depth,
+                        seldepth: main.seldepth,
+                        score: line.score,
+                        nodes: main.nodes_searched(),
+                        time: time.elapsed(),
+                        pv: line.pv.clone(),
+                        multipv: pv_index + 1,
+                    });
+                    lines.push(line);
+                    if main.stopped {
+                        break;
+                    }
+                }
+                if lines.is_empty() || (main.stopped && result.depth > 0 && lines.len() < multipv) {
+                    break;
+                }
+                lines.sort_by_key(|line| std::cmp::Reverse(line.value));
+                result.best_move = lines[0].pv.first().cloned();
+                result.ponder_move = lines[0].pv.get(1).cloned();
+                result.pv = lines[0].pv.clone();
+                result.score = lines[0].score;
+                result.depth = depth;
+                let best_value = lines[0].value;
+                result.lines = lines;
+                if main.stopped {
+                    break;
+                }
+                if let Some(best_move) = &result.best_move {
+                    time.on_iteration(best_move, best_value);
+                }
+                if !time.should_start_iteration() {
+                    break;
+                }
+            }
+            // "go infinite" must not return before it's told to
+            while limits.infinite && !stop.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            helpers_stop.store(true, Ordering::Relaxed);
+            for helper in helpers {
+                let _ = helper.join();
+            }
+        });
+        result.nodes = main.nodes_searched();
+        self.history = main.history;
+        if !self.skill.is_full_strength() && result.lines.len() > 1 {
+            let scores: Vec<i32> = result.lines.iter().map(|line| line.value).collect();
+            let line = &result.lines[self.skill.pick(&scores, &mut self.prng)];
+            result.best_move = line.pv.first().cloned();
+            result.ponder_move = line.pv.get(1).cloned();
+            result.pv = line.pv.clone();
+            result.score = line.score;
+        }
+        result
+    }
+
+    // The limits.multipv best lines, best first, at full strength whatever the skill, searched
+    // until the depth, the nodes or the movetime runs out. on_info sees every line of every
+    // depth as it finishes.
+    pub fn analyse<F: FnMut(&SearchInfo)>(&mut self, game: &Game, limits: &SearchLimits, stop: &AtomicBool, on_info: F) -> Vec<AnalysisLine> {
+        let skill = std::mem::take(&mut self.skill);
+        let result = self.search(game, limits, stop, on_info);
+        self.skill = skill;
+        let lines = if result.lines.is_empty() && !result.pv.is_empty() {
+            // the tablebase answered without a search
+            vec![PvLine { score: result.score, pv: result.pv, value: 0 }]
+        } else {
+            result.lines
+        };
+        lines
+            .into_iter()
+            .map(|line| AnalysisLine { depth: result.depth, wdl: line.score.wdl(), score: line.score, pv: line.pv })
+            .collect()
+    }
+}
+
+// analyse with a fresh engine, for when there is no engine around to reuse
+pub fn analyse(game: &Game, limits: &SearchLimits) -> Vec<AnalysisLine> {
+    Engine::default().analyse(game, limits, &AtomicBool::new(false), |_| {})
+}
+
+impl<'a> Worker<'a> {
+    fn new(tt: &'a TranspositionTable, limits: SearchLimits, clock: &'a dyn Clock, deadline: Option<Duration>,
+           stop: &'a AtomicBool, total_nodes: &'a AtomicU64, history: HistoryTable) -> Self {
+        Worker {
+            tt,
+            killers: KillerMoves::default(),
+            history,
+            limits,
+            clock,
+            deadline,
+            stop,
+            total_nodes,
+            nodes: 0,
+            seldepth: 0,
+            stopped: false,
+            pv_table: vec![Vec::new(); MAX_PLY + 1],
+            path_keys: Vec::new(),
+            excluded: Vec::new(),
+            eval_noise: 0,
+            noise_seed: 0,
+            network: None,
+            accumulators: Vec::new(),
+        }
+    }
+
+    // a helper thread's own iterative deepening. Odd helpers start a depth ahead so the
+    // threads are not all working on the same iteration at the same time.
+    fn iterate(&mut self, game: &Game, id: usize, max_depth: u32) {
+        let mut depth = 1 + (id % 2) as u32;
+        while depth <= max_depth && !self.stopped {
+            self.negamax(game, depth as i32, 0, -INFINITY, INFINITY);
+            depth += 1;
+        }
+        self.total_nodes.fetch_add(self.nodes % NODE_BATCH, Ordering::Relaxed);
+    }
+
+    fn set_network(&mut self, network: Option<&'a Network>) {
+        self.network = network;
+        self.accumulators = match network {
+            Some(_) => vec![Accumulator::default(); MAX_PLY + 1],
+            None => Vec::new(),
+        };
+    }
+
+    fn evaluate(&self, game: &Game, ply: usize) -> i32 {
+        match self.network {
+            Some(network) => network.evaluate(&self.accumulators[ply], game.state.player_turn),
+            None => evaluation::evaluate(&game.state),
+        }
+    }
+
+    // the child's accumulator is its parent's with the move's few changes applied
+    fn update_accumulator(&mut self, ply: usize, parent: &Game, child: &Game) {
+        if let Some(network) = self.network {
+            let (parents, children) = self.accumulators.split_at_mut(ply + 1);
+            network.update(&parents[ply], &parent.state, &child.state, &mut children[0]);
+        }
+    }
+
+    fn count_node(&mut self, ply: usize) {
+        self.nodes += 1;
+        self.seldepth = self.seldepth.max(ply as u32);
+        if self.nodes.is_multiple_of(NODE_BATCH) {
+            self.total_nodes.fetch_add(NODE_BATCH, Ordering::Relaxed);
+        }
+    }
+
+    // all threads together, as far as the main thread can tell
+    fn nodes_searched(&self) -> u64 {
+        self.total_nodes.load(Ordering::Relaxed) + self.nodes % NODE_BATCH
+    }
+
+    // the same small error for a position all through one search, so the tree stays consistent
+    fn noise(&self, game: &Game) -> i32 {
+        if self.eval_noise == 0 {
+            return 0;
+        }
+        let mut prng = Prng::new(zobrist::position_key(&game.state) ^ self.noise_seed);
+        prng.below(2 * self.eval_noise as u64 + 1) as i32 - self.eval_noise
+    }
+
+    fn should_stop(&mut self) -> bool {
+        if self.stopped {
+            return true;
+        }
+        if let Some(max_nodes) = self.limits.nodes {
+            if self.nodes >= max_nodes {
+                self.stopped = true;
+            }
+        }
+        if self.nodes.is_multiple_of(256) {
+            if self.stop.load(Ordering::Relaxed) {
+                self.stopped = true;
+            }
+            // past the hard limit the stop flag goes up, as if the GUI had sent stop
+            if let Some(deadline) = self.deadline {
+                if self.clock.now() >= deadline {
+                    self.stop.store(true, Ordering::Relaxed);
+                    self.stopped = true;
+                }
+            }
+        }
+        self.stopped
+    }
+
+    fn negamax(&mut self, game: &Game, depth: i32, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        self.pv_table[ply].clear();
+        if ply == 0 {
+            if let Some(network) = self.network {
+                network.refresh(&game.state, &mut self.accumulators[0]);
+            }
+        }
+        // the root always gets searched so there is a move to play
+        if ply > 0 && self.should_stop() {
+            return 0;
+        }
+        self.count_node(ply);
+
+        let key = zobrist::position_key(&game.state);
+        if ply > 0 && (game.state.halfmove_clock >= 100 || self.path_keys.contains(&key)) {
+            return 0;
+        }
+        let in_check = game.state.is_in_check(game.state.player_turn);
+        // look one move further when in check
+        let depth = if in_check { depth + 1 } else { depth };
+        if depth <= 0 || ply >= MAX_PLY - 1 {
+            return self.quiesce(game, ply, alpha, beta);
+        }
+
+        let mut hash_move = None;
+        if let Some(entry) = self.tt.probe(key) {
+            hash_move = entry.best_move;
+            if ply > 0 && entry.depth >= depth as u32 {
+                let score = score_from_tt(entry.score, ply);
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => {}
+                }
+            }
+        }
+
+        let moves = game.get_validated_moves(game.state.player_turn);
+        if moves.is_empty() {
+            return if in_check { -MATE_SCORE + ply as i32 } else { 0 };
+        }
+
+        let picker = MovePicker::new(&game.state, moves, hash_move.as_deref(), ply, &self.killers, &self.history);
+        let original_alpha = alpha;
+        let mut best_score = -INFINITY;
+        let mut best_move = None;
+        self.path_keys.push(key);
+        for chess_move in picker {
+            if ply == 0 && self.excluded.contains(&chess_move) {
+                continue;
+            }
+            let is_capture = move_picker::is_capture(&game.state, &chess_move);
+            let child = match play(game, &chess_move) {
+                Some(child) => child,
+                None => continue,
+            };
+            self.update_accumulator(ply, game, &child);
+            let score = -self.negamax(&child, depth - 1, ply + 1, -beta, -alpha);
+            if self.stopped {
+                if ply > 0 {
+                    self.path_keys.pop();
+                    return 0;
+                }
+                break;
+            }
+            if score > best_score {
+                best_score = score;
+                best_move = Some(chess_move.clone());
+                if score > alpha {
+                    alpha = score;
+                    let mut pv = vec![chess_move.clone()];
+                    pv.extend(self.pv_table[ply + 1].iter().cloned());
+                    self.pv_table[ply] = pv;
+                }
+            }
+            if alpha >= beta {
+                if !is_capture {
+                    self.killers.store(ply, &chess_move);
+                    self.history.update(&chess_move, depth as u32);
+                }
+                break;
+            }
+        }
+        self.path_keys.pop();
+
+        // a root searched without some of its moves is not the real position
+        if !self.stopped && (ply != 0 || self.excluded.is_empty()) {
+            let bound = if best_score >= beta {
+                Bound::Lower
+            } else if best_score > original_alpha {
+                Bound::Exact
+            } else {
+                Bound::Upper
+            };
+            self.tt.store(key, depth as u32, score_to_tt(best_score, ply), bound, best_move.as_deref());
+        }
+        best_score
+    }
+
+    // only captures and queen promotions, until the position is quiet
+    fn quiesce(&mut self, game: &Game, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        self.pv_table[ply].clear();
+        if self.should_stop() {
+            return 0;
+        }
+        self.count_node(ply);
+
+        let stand_pat = self.evaluate(game, ply) + self.noise(game);
+        if stand_pat >= beta || ply >= MAX_PLY - 1 {
+            return stand_pat;
+        }
+        if stand_pat > alpha {
+            alpha = stand_pat;
+        }
+
+        let moves: Vec<String> = game.get_validated_moves(game.state.player_turn).into_iter()
+            .filter(|chess_move| move_picker::is_capture(&game.state, chess_move) || chess_move.ends_with("pq"))
+            .collect();
+        let picker = MovePicker::new(&game.state, moves, None, ply, &self.killers, &self.history);
+        for chess_move in picker {
+            let child = match play(game, &chess_move) {
+                Some(child) => child,
+                None => continue,
+            };
+            self.update_accumulator(ply, game, &child);
+            let score = -self.quiesce(&child, ply + 1, -beta, -alpha);
+            if self.stopped {
+                return 0;
+            }
+            if score > alpha {
+                alpha = score;
+                let mut pv = vec![chess_move.clone()];
+                pv.extend(self.pv_table[ply + 1].iter().cloned());
+                self.pv_table[ply] = pv;
+                if alpha >= beta {
+                    break;
+                }
+            }
+        }
+        alpha
+    }
+}
@@ -0,0 +1,380 @@
+use std::fmt;
+use std::fs;
+use std::sync::{Arc, OnceLock};
+
+use crate::chess_errors::ChessErrors;
+use crate::move_picker;
+use crate::nnue::Network;
+use crate::visual;
+
+use visual::{GameState, PLAYER};
+
+// piece-square tables from white's side, laid out like the board prints (a8 first).
+// black pieces look them up mirrored.
+const PAWN_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+const KNIGHT_TABLE: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+
+const BISHOP_TABLE: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+
+const ROOK_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+
+const QUEEN_TABLE: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+
+const KING_MIDDLE_GAME_TABLE: [i32; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
+
+const KING_END_GAME_TABLE: [i32; 64] = [
+    -50,-40,-30,-20,-20,-30,-40,-50,
+    -30,-20,-10,  0,  0,-10,-20,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-30,  0,  0,  0,  0,-30,-30,
+    -50,-30,-30,-30,-30,-30,-30,-50,
+];
+
+// non-pawn material (both sides) of the starting position, used to blend the king tables
+const OPENING_PHASE: i32 = 2 * (2 * 320 + 2 * 330 + 2 * 500 + 900);
+
+// which evaluation the search uses, switchable between searches
+#[derive(Clone, Default)]
+pub enum EvalBackend {
+    #[default]
+    Classical,
+    Nnue(Arc<Network>),
+}
+
+// pawn, knight, bishop, rook and queen
+pub const PIECE_KINDS: usize = 5;
+// one per piece kind, then the king's middle and end game tables
+pub const TABLES: usize = PIECE_KINDS + 2;
+// every tunable number: the piece values followed by the tables
+pub const NUM_WEIGHTS: usize = PIECE_KINDS + TABLES * 64;
+
+const VALUE_NAMES: [&str; PIECE_KINDS] = ["pawn_value", "knight_value", "bishop_value", "rook_value", "queen_value"];
+const TABLE_NAMES: [&str; TABLES] = [
+    "pawn_table", "knight_table", "bishop_table", "rook_table", "queen_table", "king_middle_game_table", "king_end_game_table",
+];
+const KING_MIDDLE_GAME: usize = 5;
+const KING_END_GAME: usize = 6;
+
+// read at startup instead of the built in weights when set
+pub const WEIGHTS_ENV: &str = "CHESS_EVAL_WEIGHTS";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Weights {
+    pub values: [i32; PIECE_KINDS],
+    pub tables: [[i32; 64]; TABLES],
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        let value = |unicode_val| move_picker::piece_value(unicode_val);
+        Weights {
+            values: [
+                value(visual::WHITE_PAWN),
+                value(visual::WHITE_KNIGHT),
+                value(visual::WHITE_BISHOP),
+                value(visual::WHITE_ROOK),
+                value(visual::WHITE_QUEEN),
+            ],
+            tables: [PAWN_TABLE, KNIGHT_TABLE, BISHOP_TABLE, ROOK_TABLE, QUEEN_TABLE, KING_MIDDLE_GAME_TABLE, KING_END_GAME_TABLE],
+        }
+    }
+}
+
+// one "name numbers..." line per value and table, tables in board order
+impl fmt::Display for Weights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in VALUE_NAMES.iter().zip(self.values.iter()) {
+            writeln!(f, "{} {}", name, value)?;
+        }
+        for (name, table) in TABLE_NAMES.iter().zip(self.tables.iter()) {
+            let numbers: Vec<String> = table.iter().map(|value| value.to_string()).collect();
+            writeln!(f, "{} {}", name, numbers.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Weights {
+    // anything the text leaves out keeps its built in value
+    pub fn from_text(text: &str) -> Result<Weights, ChessErrors> {
+        let mut weights = Weights::default();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let mut fields = line.split_whitespace();
+            let name = fields.next().unwrap_or_default();
+            let numbers = fields
+                .map(|field| field.parse::<i32>())
+                .collect::<Result<Vec<i32>, _>>()
+                .map_err(|_| ChessErrors::WeightsError(format!("bad number in {}", name)))?;
+            if let Some(kind) = VALUE_NAMES.iter().position(|value_name| *value_name == name) {
+                match numbers[..] {
+                    [value] => weights.values[kind] = value,
+                    _ => return Err(ChessErrors::WeightsError(format!("{} needs one number", name))),
+                }
+            } else if let Some(table) = TABLE_NAMES.iter().position(|table_name| *table_name == name) {
+                weights.tables[table] = numbers
+                    .try_into()
+                    .map_err(|_| ChessErrors::WeightsError(format!("{} needs 64 numbers", name)))?;
+            } else {
+                return Err(ChessErrors::WeightsError(format!("unknown weight {}", name)));
+            }
+        }
+        Ok(weights)
+    }
+
+    pub fn load(path: &str) -> Result<Weights, ChessErrors> {
+        let text = fs::read_to_string(path).map_err(|e| ChessErrors::WeightsError(format!("{}: {}", path, e)))?;
+        Weights::from_text(&text)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), ChessErrors> {
+        fs::write(path, self.to_string()).map_err(|e| ChessErrors::WeightsError(format!("{}: {}", path, e)))
+    }
+
+    // all NUM_WEIGHTS numbers in a row, the order features() indexes them in
+    pub fn to_vec(&self) -> Vec<i32> {
+        self.values.iter().chain(self.tables.iter().flatten()).copied().collect()
+    }
+
+    pub fn from_slice(weights: &[i32]) -> Weights {
+        let mut result = Weights::default();
+        result.values.copy_from_slice(&weights[..PIECE_KINDS]);
+        for (table, values) in result.tables.iter_mut().zip(weights[PIECE_KINDS..].chunks(64)) {
+            table.copy_from_slice(values);
+        }
+        result
+    }
+}
+
+static WEIGHTS: OnceLock<Weights> = OnceLock::new();
+
+// the weights evaluate uses, from the file named by WEIGHTS_ENV if there is one
+pub fn weights() -> &'static Weights {
+    WEIGHTS.get_or_init(|| match std::env::var(WEIGHTS_ENV) {
+        Ok(path) => Weights::load(&path).unwrap_or_else(|e| {
+            eprintln!("{}, using the built in weights", e);
+            Weights::default()
+        }),
+        Err(_) => Weights::default(),
+    })
+}
+
+// only works before the first evaluation, the weights never change after that
+pub fn load_weights(path: &str) -> Result<(), ChessErrors> {
+    WEIGHTS
+        .set(Weights::load(path)?)
+        .map_err(|_| ChessErrors::WeightsError(format!("{}: weights are already in use", path)))
+}
+
+fn table_for(unicode_val: char) -> Option<usize> {
+    match unicode_val {
+        visual::WHITE_PAWN | visual::BLACK_PAWN => Some(0),
+        visual::WHITE_KNIGHT | visual::BLACK_KNIGHT => Some(1),
+        visual::WHITE_BISHOP | visual::BLACK_BISHOP => Some(2),
+        visual::WHITE_ROOK | visual::BLACK_ROOK => Some(3),
+        visual::WHITE_QUEEN | visual::BLACK_QUEEN => Some(4),
+        _ => None,
+    }
+}
+
+// static evaluation in centipawns from the side to move's point of view
+pub fn evaluate(state: &GameState) -> i32 {
+    evaluate_with(state, weights())
+}
+
+pub fn evaluate_with(state: &GameState, weights: &Weights) -> i32 {
+    let mut score = 0;
+    let mut phase = 0;
+    let mut kings = [None, None];
+    for (index, piece_opt) in state.state.iter().enumerate() {
+        if let Some(piece) = piece_opt {
+            let unicode_val = piece.get_unicode_val();
+            let (sign, square) = match piece.get_player() {
+                PLAYER::WHITE => (1, index),
+                PLAYER::BLACK => (-1, index ^ 56),
+            };
+            let kind = match table_for(unicode_val) {
+                Some(kind) => kind,
+                None => {
+                    kings[(sign < 0) as usize] = Some(square);
+                    continue;
+                }
+            };
+            phase += phase_weight(unicode_val);
+            score += sign * (weights.values[kind] + weights.tables[kind][square]);
+        }
+    }
+    // the king hides in the middle game and walks to the centre in the end game
+    let phase = phase.min(OPENING_PHASE);
+    for (side, king) in kings.iter().enumerate() {
+        if let Some(square) = king {
+            let sign = if side == 0 { 1 } else { -1 };
+            let king_score = (weights.tables[KING_MIDDLE_GAME][*square] * phase
+                + weights.tables[KING_END_GAME][*square] * (OPENING_PHASE - phase)) / OPENING_PHASE;
+            score += sign * king_score;
+        }
+    }
+    match state.player_turn {
+        PLAYER::WHITE => score,
+        PLAYER::BLACK => -score,
+    }
+}
+
+// how far from the end game a piece counts, independent of the tuned values
+fn phase_weight(unicode_val: char) -> i32 {
+    let value = move_picker::piece_value(unicode_val);
+    if value > move_picker::piece_value(visual::WHITE_PAWN) && table_for(unicode_val).is_some() {
+        value
+    } else {
+        0
+    }
+}
+
+// The evaluation is linear in the weights: white's score is the sum of each weight times
+// its coefficient here (the king tables are blended by game phase). Tuning works on this.
+pub fn features(state: &GameState) -> Vec<(usize, f64)> {
+    let mut features = Vec::new();
+    let mut phase = 0;
+    let mut kings = [None, None];
+    for (index, piece_opt) in state.state.iter().enumerate() {
+        if let Some(piece) = piece_opt {
+            let unicode_val = piece.get_unicode_val();
+            let (sign, square) = match piece.get_player() {
+                PLAYER::WHITE => (1.0, index),
+                PLAYER::BLACK => (-1.0, index ^ 56),
+            };
+            match table_for(unicode_val) {
+                Some(kind) => {
+                    phase += phase_weight(unicode_val);
+                    features.push((kind, sign));
+                    features.push((PIECE_KINDS + kind * 64 + square, sign));
+                }
+                None => kings[(sign < 0.0) as usize] = Some(square),
+            }
+        }
+    }
+    let middle_game = phase.min(OPENING_PHASE) as f64 / OPENING_PHASE as f64;
+    for (side, king) in kings.iter().enumerate() {
+        if let Some(square) = king {
+            let sign = if side == 0 { 1.0 } else { -1.0 };
+            features.push((PIECE_KINDS + KING_MIDDLE_GAME * 64 + square, sign * middle_game));
+            features.push((PIECE_KINDS + KING_END_GAME * 64 + square, sign * (1.0 - middle_game)));
+        }
+    }
+    features
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prng::Prng;
+
+    const POSITIONS: [&str; 5] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "4k3/8/8/8/8/8/8/4K3 b - - 0 1",
+    ];
+
+    fn white_score(state: &GameState, weights: &Weights) -> i32 {
+        match state.player_turn {
+            PLAYER::WHITE => evaluate_with(state, weights),
+            PLAYER::BLACK => -evaluate_with(state, weights),
+        }
+    }
+
+    #[test]
+    fn evaluation_is_features_times_weights() {
+        let mut prng = Prng::new(3);
+        let random = Weights::from_slice(&(0..NUM_WEIGHTS).map(|_| prng.below(2001) as i32 - 1000).collect::<Vec<_>>());
+        for weights in [Weights::default(), random] {
+            let numbers = weights.to_vec();
+            for fen in POSITIONS {
+                let state = GameState::from_fen(fen).unwrap();
+                let dot: f64 = features(&state).iter().map(|(index, coefficient)| numbers[*index] as f64 * coefficient).sum();
+                // the evaluation rounds each king's blend of its two tables towards zero
+                let score = white_score(&state, &weights) as f64;
+                assert!((score - dot).abs() < 2.0, "{}: {} against {}", fen, score, dot);
+            }
+        }
+    }
+
+    #[test]
+    fn the_start_position_is_level() {
+        let state = GameState::from_fen(POSITIONS[0]).unwrap();
+        assert_eq!(evaluate_with(&state, &Weights::default()), 0);
+    }
+
+    #[test]
+    fn weights_read_back_what_they_write() {
+        let mut prng = Prng::new(5);
+        let weights = Weights::from_slice(&(0..NUM_WEIGHTS).map(|_| prng.below(1000) as i32 - 500).collect::<Vec<_>>());
+        assert_eq!(weights.to_vec().len(), NUM_WEIGHTS);
+        assert_eq!(Weights::from_text(&weights.to_string()).unwrap(), weights);
+        // what the text leaves out keeps its built in value
+        let knight = Weights::from_text("# comment\nknight_value 300\n").unwrap();
+        assert_eq!(knight.values[1], 300);
+        assert_eq!(knight.tables, Weights::default().tables);
+        for bad in ["queen_value", "pawn_value 1 2", "pawn_table 1 2 3", "rook_value x", "king_value 1"] {
+            assert!(matches!(Weights::from_text(bad), Err(ChessErrors::WeightsError(_))), "{}", bad);
+        }
+    }
+}
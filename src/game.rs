@@ -23,6 +23,18 @@ pub enum Direction {
 
 
 
+// the ways the rules end a game, with nobody resigning or running out of time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameEnd {
+    // the side to move is mated
+    Checkmate,
+    Stalemate,
+    Repetition,
+    FiftyMoves,
+    InsufficientMaterial,
+}
+
+#[derive(Clone)]
 pub struct Game {
     pub state: GameState,
     pub turn_history: Vec<String>,
@@ -55,6 +67,42 @@ impl Game {
         }
         chess_game
     }
+    pub fn game_from_fen(fen: &str) -> Result<Self, chess_errors::ChessErrors> {
+        Ok(Game {
+            state: GameState::from_fen(fen)?,
+            turn_history: Vec::new(),
+        })
+    }
+    pub fn make_move(&mut self, chess_move: &str) -> Result<(), chess_errors::ChessErrors> {
+        // moves come straight from clients, so anything not shaped like "e2-e4" stops here
+        if chess_notation_utilities::move_notation_to_indexes(&chess_move.to_lowercase()).is_err() {
+            return Err(chess_errors::ChessErrors::InvalidMove(chess_move.to_string()));
+        }
+        self.move_piece(chess_move, self.state.player_turn)?;
+        self.turn_history.push(chess_move.to_string());
+        self.state.player_turn = match self.state.player_turn {
+            visual::PLAYER::WHITE => visual::PLAYER::BLACK,
+            visual::PLAYER::BLACK => visual::PLAYER::WHITE,
+        };
+        Ok(())
+    }
+    // keys holds the zobrist key of every position so far, the current one last
+    pub fn game_end(&self, keys: &[u64]) -> Option<GameEnd> {
+        let player = self.state.player_turn;
+        let current = keys.last().copied();
+        if self.get_validated_moves(player).is_empty() {
+            Some(if self.state.is_in_check(player) { GameEnd::Checkmate } else { GameEnd::Stalemate })
+        } else if keys.iter().filter(|key| Some(**key) == current).count() >= 3 {
+            Some(GameEnd::Repetition)
+        } else if self.state.halfmove_clock >= 100 {
+            Some(GameEnd::FiftyMoves)
+        } else if self.state.is_insufficient_material() {
+            Some(GameEnd::InsufficientMaterial)
+        } else {
+            None
+        }
+    }
+    //walks back from to_spot to from_spot, a piece of either colour on the way blocks the move
     pub fn check_pieces_between(&self, from_spot: &str, to_spot: &str, dir: Direction)-> Result<(), chess_errors::ChessErrors>{
         let mut pos:String = to_spot.to_string();
         loop{
@@ -92,19 +140,14 @@ impl Game {
                         break;
                     }
                     if let Ok(index) = chess_notation_utilities::notation_to_index(&pos) {
-                        if let Some(piece) = self.state.get_piece_at(index) {
-                            if piece.get_player() == self.state.player_turn{
-                                return Err(chess_errors::ChessErrors::PieceBetween(pos));
-                            }
+                        if self.state.get_piece_at(index).is_some() {
+                            return Err(chess_errors::ChessErrors::PieceBetween(pos));
                         }
                     }
                 }else {
-                    println!("oops1");
                     return Err(chess_errors::ChessErrors::InvalidNotation(pos.to_string()));
                 }
             } else {
-
-                println!("oops2");
                 return Err(chess_errors::ChessErrors::InvalidNotation(pos.to_string()));
             }
            
@@ -116,15 +159,44 @@ impl Game {
         let mut unvalidated_moves = self.state.get_unvalidated_moves(player);
         for (index, piece_move) in unvalidated_moves.iter().enumerate(){
             let move_spots: Vec<&str> = piece_move.split("-").collect();
-            if self.is_move_valid(move_spots[0],move_spots[1], player, None).is_ok(){
-                validated_moves.push(piece_move.clone());
+            match self.is_move_valid(move_spots[0],move_spots[1], player, None) {
+                Ok(visual::MoveType::Promotion(_)) => {
+                    //list every piece the pawn can become
+                    for promotion in ["q", "r", "b", "k"] {
+                        validated_moves.push(format!("{}p{}", piece_move, promotion));
+                    }
+                },
+                Ok(_) => validated_moves.push(piece_move.clone()),
+                Err(_) => {},
             }
         }
-        println!("validated_moves: {:?}", validated_moves);
         validated_moves
     }
 
-    pub fn is_move_valid(&self, from_spot: &str, to_spot: &str, whos_turn: visual::PLAYER, promotion_opt: Option<&str>)->Result<(visual::MoveType), chess_errors::ChessErrors> {
+    pub fn is_move_valid(&self, from_spot: &str, to_spot: &str, whos_turn: visual::PLAYER, promotion_opt: Option<&str>)->Result<visual::MoveType, chess_errors::ChessErrors> {
+        let move_type = self.check_move_rules(from_spot, to_spot, whos_turn, promotion_opt)?;
+        let (from, to) = chess_notation_utilities::convert_move_notation_to_indexes(from_spot, to_spot)?;
+        let opponent = match whos_turn {
+            PLAYER::WHITE => PLAYER::BLACK,
+            PLAYER::BLACK => PLAYER::WHITE,
+        };
+        if move_type == visual::MoveType::Castling {
+            //cannot castle out of or through check
+            if self.state.is_in_check(whos_turn) || self.state.is_square_attacked((from + to) / 2, opponent) {
+                let msg = format!("{}-{}",from_spot,to_spot);
+                return Err(chess_errors::ChessErrors::KingInCheck(msg));
+            }
+        }
+        let mut next_state = self.state.clone();
+        next_state.move_piece(from, to, promotion_opt, move_type);
+        if next_state.is_in_check(whos_turn) {
+            let msg = format!("{}-{}",from_spot,to_spot);
+            return Err(chess_errors::ChessErrors::KingInCheck(msg));
+        }
+        Ok(move_type)
+    }
+
+    fn check_move_rules(&self, from_spot: &str, to_spot: &str, whos_turn: visual::PLAYER, promotion_opt: Option<&str>)->Result<visual::MoveType, chess_errors::ChessErrors> {
         // first determine if piece at from is correct player.
         if let Ok(index) = chess_notation_utilities::notation_to_index(&from_spot) {
             if let Some(piece) = self.state.get_piece_at(index) {
@@ -150,21 +222,19 @@ impl Game {
             //promotions are only valid from 8th rank for pawn
             let from_row = chess_notation_utilities::convert_row(from_spot)?;
             let to_row = chess_notation_utilities::convert_row(to_spot)?;
-            if whos_turn == PLAYER::WHITE && to_row !=  0 &&  from_row != 1 {
+            if whos_turn == PLAYER::WHITE && (to_row !=  0 ||  from_row != 1) {
                 let msg = format!("{}",to_spot);
                 return Err(chess_errors::ChessErrors::InvalidPromotion(msg));
             }
-            if whos_turn == PLAYER::BLACK && to_row != 8 && from_row!= 7 {
+            if whos_turn == PLAYER::BLACK && (to_row != 7 || from_row!= 6) {
                 let msg = format!("{}",to_spot);
                 return Err(chess_errors::ChessErrors::InvalidPromotion(msg));
             }
         }
         //check move against en-passant_moves
         if let Some(en_passant_enabled_vec) = &self.state.en_passant_enabled {
-            println!("en_passant_enabled_vec: {:?}",en_passant_enabled_vec);
             for en_passant_move in en_passant_enabled_vec {
                 let en_passant_notation_move = &en_passant_move[0..5];
-                println!("en_passant_notation_move: {:?}",en_passant_notation_move);
                 let notation_move = format!("{}-{}",from_spot,to_spot);
                 if en_passant_notation_move == notation_move {
                     let attacked_piece = &en_passant_move[5..];
@@ -195,9 +265,8 @@ impl Game {
             }
             if let Ok(index) = chess_notation_utilities::notation_to_index(&from_spot) {
                 if let Some(piece) = self.state.get_piece_at(index) {
-                    if let (_, visual::MoveType::Promotion(new_piece)) = piece.move_vertical(to_spot, &self.state, delta_y, promotion_opt)?{
-                        return Ok(visual::MoveType::Promotion(new_piece));
-                    }
+                    let (_, move_type) = piece.move_vertical(to_spot, &self.state, delta_y, promotion_opt)?;
+                    return Ok(move_type);
                 }
             }
         } else if delta_y == 0{
@@ -214,7 +283,8 @@ impl Game {
             }
             if let Ok(index) = chess_notation_utilities::notation_to_index(&from_spot) {
                 if let Some(piece) = self.state.get_piece_at(index) {
-                    piece.move_horizontal(to_spot, &self.state, delta_x, promotion_opt)?;
+                    let (_, move_type) = piece.move_horizontal(to_spot, &self.state, delta_x, promotion_opt)?;
+                    return Ok(move_type);
                 }
             }
         }else if delta_x.abs() == delta_y.abs(){
@@ -237,7 +307,8 @@ impl Game {
             }
             if let Ok(index) = chess_notation_utilities::notation_to_index(&from_spot) {
                 if let Some(piece) = self.state.get_piece_at(index) {
-                    piece.move_diagonal(to_spot, &self.state, delta_y, promotion_opt)?;
+                    let (_, move_type) = piece.move_diagonal(to_spot, &self.state, delta_y, promotion_opt)?;
+                    return Ok(move_type);
                 }
             }
             // if diagonal deltas must be equal, except for Knight
@@ -246,12 +317,14 @@ impl Game {
             if let Ok(index) = chess_notation_utilities::notation_to_index(&from_spot) {
                 if let Some(piece) = self.state.get_piece_at(index) {
                     piece.move_knight(to_spot, &self.state, promotion_opt)?;
+                    return Ok(visual::MoveType::Regular);
                 }
             }
         }
 
-        //check for current player in check
-        Ok(visual::MoveType::Regular)
+        //no piece moves like this
+        let msg = format!("{}",to_spot);
+        Err(chess_errors::ChessErrors::InvalidMove(msg))
     }
 
     pub fn check_en_passant (&mut self ,from_spot: &str, to_spot: &str, whos_turn: visual::PLAYER)->Result<(), chess_errors::ChessErrors> {
@@ -261,7 +334,6 @@ impl Game {
             if let Some(piece) = self.state.get_piece_at(index) {
                 let from_row = chess_notation_utilities::convert_row(from_spot)?;
                 let to_row = chess_notation_utilities::convert_row(to_spot)?;
-                println!("from_row {:?} to_row: {:?}", from_row, to_row);
                  //determine if its a pawn
                 let bounds = chess_notation_utilities::get_bounds(to_spot)?;
                 let (left_spot_opt, right_spot_opt,
@@ -353,7 +425,7 @@ impl Game {
         let the_move = chess_move.to_lowercase();
         if let Some(index_of_dash) = the_move.find("-") {
             let from_spot = &the_move[0..index_of_dash];
-            let to_spot = &the_move[index_of_dash+1..index_of_dash+3];
+            let to_spot = the_move.get(index_of_dash+1..index_of_dash+3).unwrap_or_default();
             if let Ok((from, to)) = chess_notation_utilities::convert_move_notation_to_indexes(from_spot,to_spot) {
                 let promotion = if let Some(index_of_p) = the_move.find("p") {
                     Some(&the_move[index_of_p+1 ..])
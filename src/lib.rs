@@ -1,4 +1,25 @@
 pub mod visual;
 pub mod game;
 pub mod chess_notation_utilities;
-pub mod chess_errors;
\ No newline at end of file
+pub mod chess_errors;
+pub mod move_picker;
+pub mod prng;
+pub mod zobrist;
+pub mod evaluation;
+pub mod engine;
+pub mod polyglot;
+pub mod syzygy;
+pub mod time_manager;
+pub mod skill;
+pub mod mcts;
+pub mod encoding;
+pub mod nnue;
+pub mod pgn;
+pub mod session;
+pub mod time_control;
+pub mod lobby;
+pub mod accounts;
+pub mod rating;
+pub mod repository;
+pub mod server_config;
+pub mod chess_clock;
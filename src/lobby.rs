@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::chess_errors::ChessErrors;
+use crate::session::secret_token;
+use crate::time_control::TimeControl;
+use crate::visual::PLAYER;
+
+// a seek nobody has asked after for this long is dropped
+pub const SEEK_TIMEOUT: Duration = Duration::from_secs(60);
+// and a pairing its seeker never collected
+const PAIRING_TIMEOUT: Duration = Duration::from_secs(300);
+
+// what a player is looking for
+#[derive(Clone, Debug)]
+pub struct SeekRequest {
+    pub time_control: TimeControl,
+    // None takes either colour
+    pub color: Option<PLAYER>,
+    pub rated: bool,
+    pub rating: i32,
+    // a rating from too few games to trust yet
+    pub provisional: bool,
+    // the opponents' ratings they will play
+    pub min_rating: Option<i32>,
+    pub max_rating: Option<i32>,
+    // the account seeking, if logged in
+    pub user: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Seek {
+    pub id: String,
+    // lets only the seeker look up or cancel it
+    pub token: String,
+    pub request: SeekRequest,
+    // when the seeker was last heard from, on the lobby's clock
+    pub last_seen: Duration,
+}
+
+impl Seek {
+    fn accepts_rating(&self, rating: i32) -> bool {
+        self.request.min_rating.is_none_or(|min| rating >= min) && self.request.max_rating.is_none_or(|max| rating <= max)
+    }
+
+    // the two can play each other: same game wanted, colours that fit, ratings in range
+    pub fn matches(&self, other: &SeekRequest) -> bool {
+        // nobody plays themselves
+        (self.request.user.is_none() || self.request.user != other.user)
+            && self.request.time_control == other.time_control
+            && self.request.rated == other.rated
+            && (self.request.color.is_none() || self.request.color != other.color)
+            && self.accepts_rating(other.rating)
+            && (other.min_rating.is_none_or(|min| self.request.rating >= min)
+                && other.max_rating.is_none_or(|max| self.request.rating <= max))
+    }
+}
+
+// where a paired seeker's game is, kept until they come for it
+#[derive(Clone, Debug)]
+pub struct Pairing {
+    pub game_id: String,
+    pub color: PLAYER,
+    // the seat's token in the game
+    pub token: String,
+    seek_token: String,
+    made: Duration,
+}
+
+pub enum SeekOutcome {
+    // nobody fits yet, the seek waits in the lobby
+    Posted(Seek),
+    // this waiting seek fitted, and its seeker's pairing is waiting to be collected
+    Matched(Seek),
+}
+
+pub enum SeekStatus {
+    Open,
+    Paired(Pairing),
+}
+
+#[derive(Default)]
+pub struct Lobby {
+    seeks: Mutex<Vec<Seek>>,
+    pairings: Mutex<HashMap<String, Pairing>>,
+    next_id: AtomicU64,
+}
+
+impl Lobby {
+    // Pairs the request with the longest waiting seek that fits, or leaves it in the lobby.
+    // pair sets up the game for a match and returns the waiting seeker's game id, colour
+    // and seat token; the lobby stays locked meanwhile so the seeker never finds neither.
+    pub fn post(
+        &self,
+        request: SeekRequest,
+        now: Duration,
+        pair: impl FnOnce(&Seek) -> Result<(String, PLAYER, String), ChessErrors>,
+    ) -> Result<SeekOutcome, ChessErrors> {
+        let mut seeks = self.seeks.lock().unwrap();
+        if let Some(index) = seeks.iter().position(|seek| seek.matches(&request)) {
+            let (game_id, color, token) = pair(&seeks[index])?;
+            let seek = seeks.remove(index);
+            self.record_pairing(&seek, &game_id, color, &token, now);
+            return Ok(SeekOutcome::Matched(seek));
+        }
+        let seek = Seek {
+            id: (self.next_id.fetch_add(1, Ordering::Relaxed) + 1).to_string(),
+            token: secret_token()?,
+            request,
+            last_seen: now,
+        };
+        seeks.push(seek.clone());
+        Ok(SeekOutcome::Posted(seek))
+    }
+
+    pub fn open_seeks(&self) -> Vec<Seek> {
+        self.seeks.lock().unwrap().clone()
+    }
+
+    fn record_pairing(&self, seek: &Seek, game_id: &str, color: PLAYER, token: &str, now: Duration) {
+        let pairing = Pairing {
+            game_id: game_id.to_string(),
+            color,
+            token: token.to_string(),
+            seek_token: seek.token.clone(),
+            made: now,
+        };
+        self.pairings.lock().unwrap().insert(seek.id.clone(), pairing);
+    }
+
+    // Asking keeps a seek alive. A pairing is handed over once and then forgotten.
+    pub fn status(&self, seek_id: &str, token: &str, now: Duration) -> Result<SeekStatus, ChessErrors> {
+        let mut seeks = self.seeks.lock().unwrap();
+        if let Some(seek) = seeks.iter_mut().find(|seek| seek.id == seek_id) {
+            if seek.token != token {
+                return Err(ChessErrors::SeekNotFound(seek_id.to_string()));
+            }
+            seek.last_seen = now;
+            return Ok(SeekStatus::Open);
+        }
+        let mut pairings = self.pairings.lock().unwrap();
+        match pairings.get(seek_id) {
+            Some(pairing) if pairing.seek_token == token => Ok(SeekStatus::Paired(pairings.remove(seek_id).unwrap())),
+            _ => Err(ChessErrors::SeekNotFound(seek_id.to_string())),
+        }
+    }
+
+    pub fn cancel(&self, seek_id: &str, token: &str) -> Result<Seek, ChessErrors> {
+        let mut seeks = self.seeks.lock().unwrap();
+        let index = seeks
+            .iter()
+            .position(|seek| seek.id == seek_id && seek.token == token)
+            .ok_or_else(|| ChessErrors::SeekNotFound(seek_id.to_string()))?;
+        Ok(seeks.remove(index))
+    }
+
+    // drops what has gone stale and returns the seeks that were
+    pub fn expire(&self, now: Duration) -> Vec<Seek> {
+        let mut seeks = self.seeks.lock().unwrap();
+        let (stale, fresh) = seeks.drain(..).partition(|seek| now.saturating_sub(seek.last_seen) > SEEK_TIMEOUT);
+        *seeks = fresh;
+        self.pairings.lock().unwrap().retain(|_, pairing| now.saturating_sub(pairing.made) <= PAIRING_TIMEOUT);
+        stale
+    }
+}
@@ -0,0 +1,395 @@
+use crate::chess_errors::ChessErrors;
+use crate::evaluation;
+use crate::game::Game;
+use crate::prng::Prng;
+
+// Whatever guides the tree: how promising each move looks before it has been tried and
+// what the position is worth. A learned model implements this; RolloutEvaluator is
+// the stand-in until there is one.
+pub trait PolicyValue {
+    // priors for moves (in the same order, they need not add up to 1) and the value
+    // of the position for the side to move, from -1 lost to 1 won
+    fn evaluate(&mut self, game: &Game, moves: &[String]) -> (Vec<f32>, f32);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rollout {
+    // play random moves for at most this many plies, then fall back on the evaluation
+    Random(u32),
+    // the static evaluation of the leaf itself
+    Evaluation,
+}
+
+// centipawns that count as about three quarters of a win
+const VALUE_SCALE: f32 = 400.0;
+
+pub fn value_from_centipawns(cp: i32) -> f32 {
+    (cp as f32 / VALUE_SCALE).tanh()
+}
+
+// uniform priors with the value from a rollout
+pub struct RolloutEvaluator {
+    rollout: Rollout,
+    prng: Prng,
+}
+
+impl RolloutEvaluator {
+    pub fn new(rollout: Rollout, seed: u64) -> Self {
+        RolloutEvaluator { rollout, prng: Prng::new(seed) }
+    }
+}
+
+impl PolicyValue for RolloutEvaluator {
+    fn evaluate(&mut self, game: &Game, moves: &[String]) -> (Vec<f32>, f32) {
+        let priors = vec![1.0; moves.len()];
+        let max_plies = match self.rollout {
+            Rollout::Random(max_plies) => max_plies,
+            Rollout::Evaluation => return (priors, value_from_centipawns(evaluation::evaluate(&game.state))),
+        };
+        let mut game = Game {
+            state: game.state.clone(),
+            turn_history: Vec::new(),
+        };
+        let mut sign = 1.0;
+        for _ in 0..max_plies {
+            if let Some(value) = terminal_value(&game) {
+                return (priors, sign * value);
+            }
+            let moves = game.get_validated_moves(game.state.player_turn);
+            let chess_move = &moves[self.prng.below(moves.len() as u64) as usize];
+            if game.make_move(chess_move).is_err() {
+                break;
+            }
+            sign = -sign;
+        }
+        (priors, sign * value_from_centipawns(evaluation::evaluate(&game.state)))
+    }
+}
+
+// the value for the side to move if the game is over
+fn terminal_value(game: &Game) -> Option<f32> {
+    if game.state.halfmove_clock >= 100 {
+        return Some(0.0);
+    }
+    if !game.get_validated_moves(game.state.player_turn).is_empty() {
+        return None;
+    }
+    Some(if game.state.is_in_check(game.state.player_turn) { -1.0 } else { 0.0 })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Selection {
+    // UCB1, tries every move once before trusting any of them; ignores the priors
+    Uct,
+    // AlphaZero's, leans on the priors
+    Puct,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MctsConfig {
+    pub selection: Selection,
+    pub exploration: f64,
+    pub seed: u64,
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        MctsConfig { selection: Selection::Puct, exploration: 1.5, seed: 0 }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    chess_move: Option<String>,
+    children: Vec<usize>,
+    prior: f32,
+    visits: u32,
+    // from the point of view of the side that played chess_move
+    value_sum: f64,
+    expanded: bool,
+    // the value for the side to move once the game is over here
+    terminal: Option<f32>,
+}
+
+impl Node {
+    fn new(chess_move: Option<String>, prior: f32) -> Self {
+        Node {
+            chess_move,
+            children: Vec::new(),
+            prior,
+            visits: 0,
+            value_sum: 0.0,
+            expanded: false,
+            terminal: None,
+        }
+    }
+
+    fn mean_value(&self) -> f64 {
+        if self.visits == 0 {
+            0.0
+        } else {
+            self.value_sum / self.visits as f64
+        }
+    }
+}
+
+// what the search thinks of one root move
+#[derive(Clone, Debug)]
+pub struct MoveStats {
+    pub chess_move: String,
+    pub visits: u32,
+    pub prior: f32,
+    // for the side to move at the root, -1 to 1
+    pub value: f64,
+}
+
+// The tree lives in one Vec with the root at index 0. Playing a move keeps the subtree
+// under it, so the next search starts from what was already learned.
+pub struct Mcts {
+    game: Game,
+    nodes: Vec<Node>,
+    config: MctsConfig,
+    prng: Prng,
+}
+
+impl Mcts {
+    pub fn new(game: &Game, config: MctsConfig) -> Self {
+        Mcts {
+            game: game.clone(),
+            nodes: vec![Node::new(None, 1.0)],
+            config,
+            prng: Prng::new(config.seed),
+        }
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    pub fn root_visits(&self) -> u32 {
+        self.nodes[0].visits
+    }
+
+    // adds playouts to the tree and returns the move it likes best so far
+    pub fn search(&mut self, policy: &mut dyn PolicyValue, playouts: u32) -> Option<String> {
+        for _ in 0..playouts {
+            self.playout(policy);
+        }
+        self.best_move()
+    }
+
+    fn playout(&mut self, policy: &mut dyn PolicyValue) {
+        let mut game = Game {
+            state: self.game.state.clone(),
+            turn_history: Vec::new(),
+        };
+        let mut path = vec![0];
+        let mut node = 0;
+        while self.nodes[node].expanded && self.nodes[node].terminal.is_none() {
+            node = self.select_child(node);
+            let chess_move = self.nodes[node].chess_move.clone().unwrap();
+            game.make_move(&chess_move).expect("tree move is legal");
+            path.push(node);
+        }
+        let value = match self.nodes[node].terminal {
+            Some(value) => value,
+            None => self.expand(node, &game, policy),
+        };
+        // value is for the side to move at the leaf, each node is scored for the side that moved into it
+        let mut value = -value as f64;
+        for &node in path.iter().rev() {
+            self.nodes[node].visits += 1;
+            self.nodes[node].value_sum += value;
+            value = -value;
+        }
+    }
+
+    fn expand(&mut self, node: usize, game: &Game, policy: &mut dyn PolicyValue) -> f32 {
+        self.nodes[node].expanded = true;
+        let moves = game.get_validated_moves(game.state.player_turn);
+        if moves.is_empty() || game.state.halfmove_clock >= 100 {
+            let value = terminal_value(game).unwrap_or(0.0);
+            self.nodes[node].terminal = Some(value);
+            return value;
+        }
+        let (priors, value) = policy.evaluate(game, &moves);
+        let total: f32 = priors.iter().sum();
+        let count = moves.len();
+        for (index, chess_move) in moves.into_iter().enumerate() {
+            let prior = match priors.get(index) {
+                Some(prior) if total > 0.0 => prior / total,
+                _ => 1.0 / count as f32,
+            };
+            let child = self.nodes.len();
+            self.nodes[node].children.push(child);
+            self.nodes.push(Node::new(Some(chess_move), prior));
+        }
+        value.clamp(-1.0, 1.0)
+    }
+
+    fn select_child(&mut self, node: usize) -> usize {
+        let parent_visits = self.nodes[node].visits.max(1) as f64;
+        let mut best = (Vec::new(), f64::NEG_INFINITY);
+        for &child in &self.nodes[node].children {
+            let child_node = &self.nodes[child];
+            let score = match self.config.selection {
+                Selection::Uct if child_node.visits == 0 => f64::INFINITY,
+                Selection::Uct => {
+                    child_node.mean_value() + self.config.exploration * (parent_visits.ln() / child_node.visits as f64).sqrt()
+                }
+                Selection::Puct => {
+                    child_node.mean_value()
+                        + self.config.exploration * child_node.prior as f64 * parent_visits.sqrt() / (1 + child_node.visits) as f64
+                }
+            };
+            if score > best.1 {
+                best = (vec![child], score);
+            } else if score == best.1 {
+                best.0.push(child);
+            }
+        }
+        // ties are broken by the seeded generator so no move order is favoured
+        let ties = best.0;
+        ties[self.prng.below(ties.len() as u64) as usize]
+    }
+
+    // the most visited root move
+    pub fn best_move(&self) -> Option<String> {
+        self.nodes[0]
+            .children
+            .iter()
+            .max_by_key(|&&child| self.nodes[child].visits)
+            .and_then(|&child| self.nodes[child].chess_move.clone())
+    }
+
+    // the line the tree expects, following the most visited children
+    pub fn pv(&self) -> Vec<String> {
+        let mut pv = Vec::new();
+        let mut node = 0;
+        while let Some(&child) = self.nodes[node].children.iter().max_by_key(|&&child| self.nodes[child].visits) {
+            if self.nodes[child].visits == 0 {
+                break;
+            }
+            pv.push(self.nodes[child].chess_move.clone().unwrap());
+            node = child;
+        }
+        pv
+    }
+
+    pub fn root_stats(&self) -> Vec<MoveStats> {
+        self.nodes[0]
+            .children
+            .iter()
+            .map(|&child| {
+                let node = &self.nodes[child];
+                MoveStats {
+                    chess_move: node.chess_move.clone().unwrap(),
+                    visits: node.visits,
+                    prior: node.prior,
+                    value: node.mean_value(),
+                }
+            })
+            .collect()
+    }
+
+    // Plays chess_move on the root. The subtree under it becomes the new tree;
+    // a move the tree never looked at starts a fresh one.
+    pub fn advance(&mut self, chess_move: &str) -> Result<(), ChessErrors> {
+        self.game.make_move(chess_move)?;
+        let child = self.nodes[0]
+            .children
+            .iter()
+            .copied()
+            .find(|&child| self.nodes[child].chess_move.as_deref() == Some(chess_move));
+        let mut nodes = vec![Node::new(None, 1.0)];
+        if let Some(child) = child {
+            // copy the subtree over, renumbering as it goes
+            nodes[0] = self.nodes[child].clone();
+            nodes[0].chess_move = None;
+            let mut index = 0;
+            while index < nodes.len() {
+                let children = std::mem::take(&mut nodes[index].children);
+                for old in children {
+                    let renumbered = nodes.len();
+                    nodes[index].children.push(renumbered);
+                    nodes.push(self.nodes[old].clone());
+                }
+                index += 1;
+            }
+        }
+        self.nodes = nodes;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    fn stats(mcts: &Mcts) -> Vec<(String, u32, f64)> {
+        mcts.root_stats().into_iter().map(|stats| (stats.chess_move, stats.visits, stats.value)).collect()
+    }
+
+    fn searched(fen: &str, config: MctsConfig, rollout: Rollout, playouts: u32) -> (Mcts, Option<String>) {
+        let mut mcts = Mcts::new(&Game::game_from_fen(fen).unwrap(), config);
+        let best_move = mcts.search(&mut RolloutEvaluator::new(rollout, config.seed), playouts);
+        (mcts, best_move)
+    }
+
+    #[test]
+    fn the_same_seed_searches_the_same_tree() {
+        for selection in [Selection::Uct, Selection::Puct] {
+            let config = MctsConfig { selection, seed: 7, ..MctsConfig::default() };
+            let (first, first_move) = searched(START_FEN, config, Rollout::Random(8), 100);
+            let (second, second_move) = searched(START_FEN, config, Rollout::Random(8), 100);
+            assert_eq!(stats(&first), stats(&second));
+            assert_eq!(first_move, second_move);
+            assert_eq!(first.pv(), second.pv());
+            assert_eq!(first.root_visits(), 100);
+        }
+    }
+
+    #[test]
+    fn advancing_keeps_the_subtree() {
+        let (mut mcts, best_move) = searched(START_FEN, MctsConfig::default(), Rollout::Evaluation, 200);
+        let best_move = best_move.unwrap();
+        let child = mcts.nodes[0]
+            .children
+            .iter()
+            .copied()
+            .find(|&child| mcts.nodes[child].chess_move.as_deref() == Some(best_move.as_str()))
+            .unwrap();
+        let visits = mcts.nodes[child].visits;
+        let grandchildren: Vec<(String, u32)> =
+            mcts.nodes[child].children.iter().map(|&node| (mcts.nodes[node].chess_move.clone().unwrap(), mcts.nodes[node].visits)).collect();
+        assert!(visits > 1);
+
+        mcts.advance(&best_move).unwrap();
+        assert_eq!(mcts.root_visits(), visits);
+        let kept: Vec<(String, u32)> = mcts.root_stats().into_iter().map(|stats| (stats.chess_move, stats.visits)).collect();
+        assert_eq!(kept, grandchildren);
+        assert_eq!(mcts.game().turn_history, vec![best_move]);
+
+        // a move the tree never tried starts over
+        let mut fresh = Mcts::new(&Game::game_from_fen(START_FEN).unwrap(), MctsConfig::default());
+        fresh.advance("e2-e4").unwrap();
+        assert_eq!(fresh.root_visits(), 0);
+        assert!(fresh.root_stats().is_empty());
+        assert!(fresh.advance("a1-a8").is_err());
+    }
+
+    #[test]
+    fn finds_a_mate_in_one() {
+        // the back rank
+        let fen = "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1";
+        for selection in [Selection::Uct, Selection::Puct] {
+            let config = MctsConfig { selection, ..MctsConfig::default() };
+            let (mcts, best_move) = searched(fen, config, Rollout::Evaluation, 400);
+            assert_eq!(best_move.as_deref(), Some("a1-a8"));
+            let mate = mcts.root_stats().into_iter().find(|stats| stats.chess_move == "a1-a8").unwrap();
+            assert_eq!(mate.value, 1.0);
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use crate::chess_notation_utilities;
+use crate::visual;
+
+use visual::GameState;
+
+pub const MAX_PLY: usize = 64;
+
+// history scores are halved once any of them gets this big
+const HISTORY_MAX: i32 = 1 << 20;
+
+pub fn piece_value(unicode_val: char) -> i32 {
+    match unicode_val {
+        visual::WHITE_PAWN | visual::BLACK_PAWN => 100,
+        visual::WHITE_KNIGHT | visual::BLACK_KNIGHT => 320,
+        visual::WHITE_BISHOP | visual::BLACK_BISHOP => 330,
+        visual::WHITE_ROOK | visual::BLACK_ROOK => 500,
+        visual::WHITE_QUEEN | visual::BLACK_QUEEN => 900,
+        visual::WHITE_KING | visual::BLACK_KING => 20_000,
+        _ => 0,
+    }
+}
+
+fn is_pawn(unicode_val: char) -> bool {
+    unicode_val == visual::WHITE_PAWN || unicode_val == visual::BLACK_PAWN
+}
+
+// a pawn reaching the last rank; the repo notation promotes to a queen unless told otherwise
+fn promotion_value(state: &GameState, chess_move: &str) -> i32 {
+    if let Ok((from, to)) = chess_notation_utilities::move_notation_to_indexes(chess_move) {
+        if let Some(piece) = state.get_piece_at(from) {
+            let to_row = to / 8;
+            if is_pawn(piece.get_unicode_val()) && (to_row == 0 || to_row == 7) {
+                return match chess_move.find('p').map(|index_of_p| &chess_move[index_of_p + 1..]) {
+                    Some("r") => piece_value(visual::WHITE_ROOK),
+                    Some("b") => piece_value(visual::WHITE_BISHOP),
+                    Some("k") => piece_value(visual::WHITE_KNIGHT),
+                    _ => piece_value(visual::WHITE_QUEEN),
+                };
+            }
+        }
+    }
+    0
+}
+
+// most valuable victim, least valuable attacker. None when the move is not a capture.
+pub fn mvv_lva(state: &GameState, chess_move: &str) -> Option<i32> {
+    let (from, to) = chess_notation_utilities::move_notation_to_indexes(chess_move).ok()?;
+    let attacker = state.get_piece_at(from)?;
+    let victim_value = match state.get_piece_at(to) {
+        Some(victim) => {
+            if victim.get_player() == attacker.get_player() {
+                return None;
+            }
+            piece_value(victim.get_unicode_val())
+        }
+        None => {
+            // a pawn moving diagonally onto an empty square is taking en passant
+            if is_pawn(attacker.get_unicode_val()) && from % 8 != to % 8 {
+                piece_value(visual::WHITE_PAWN)
+            } else {
+                return None;
+            }
+        }
+    };
+    Some(victim_value * 10 - piece_value(attacker.get_unicode_val()) / 100)
+}
+
+pub fn is_capture(state: &GameState, chess_move: &str) -> bool {
+    mvv_lva(state, chess_move).is_some()
+}
+
+pub struct KillerMoves {
+    moves: Vec<[Option<String>; 2]>,
+}
+
+impl Default for KillerMoves {
+    fn default() -> Self {
+        KillerMoves {
+            moves: vec![[None, None]; MAX_PLY],
+        }
+    }
+}
+
+impl KillerMoves {
+    // quiet moves that caused a beta cutoff at this ply, newest first
+    pub fn store(&mut self, ply: usize, chess_move: &str) {
+        if ply >= MAX_PLY {
+            return;
+        }
+        let slot = &mut self.moves[ply];
+        if slot[0].as_deref() == Some(chess_move) {
+            return;
+        }
+        slot[1] = slot[0].take();
+        slot[0] = Some(chess_move.to_string());
+    }
+
+    pub fn get(&self, ply: usize) -> &[Option<String>] {
+        if ply >= MAX_PLY {
+            return &[];
+        }
+        &self.moves[ply]
+    }
+
+    pub fn is_killer(&self, ply: usize, chess_move: &str) -> bool {
+        self.get(ply).iter().any(|killer| killer.as_deref() == Some(chess_move))
+    }
+
+    pub fn clear(&mut self) {
+        for slot in self.moves.iter_mut() {
+            *slot = [None, None];
+        }
+    }
+}
+
+pub struct HistoryTable {
+    // indexed by from * 64 + to
+    scores: Vec<i32>,
+}
+
+impl Default for HistoryTable {
+    fn default() -> Self {
+        HistoryTable { scores: vec![0; 64 * 64] }
+    }
+}
+
+impl HistoryTable {
+    pub fn get(&self, chess_move: &str) -> i32 {
+        match chess_notation_utilities::move_notation_to_indexes(chess_move) {
+            Ok((from, to)) => self.scores[from * 64 + to],
+            Err(_) => 0,
+        }
+    }
+
+    pub fn update(&mut self, chess_move: &str, depth: u32) {
+        if let Ok((from, to)) = chess_notation_utilities::move_notation_to_indexes(chess_move) {
+            let bonus = (depth * depth) as i32;
+            let score = &mut self.scores[from * 64 + to];
+            *score += bonus;
+            if *score >= HISTORY_MAX {
+                self.age();
+            }
+        }
+    }
+
+    pub fn age(&mut self) {
+        for score in self.scores.iter_mut() {
+            *score /= 2;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for score in self.scores.iter_mut() {
+            *score = 0;
+        }
+    }
+}
+
+// Hands out the moves of a position best-first:
+// hash move, captures by MVV-LVA (promotions included), killers, then quiets by history.
+pub struct MovePicker {
+    hash_move: Option<String>,
+    captures: Vec<(i32, String)>,
+    killers: Vec<String>,
+    quiets: Vec<(i32, String)>,
+}
+
+impl MovePicker {
+    pub fn new(state: &GameState, moves: Vec<String>, hash_move: Option<&str>, ply: usize,
+               killers: &KillerMoves, history: &HistoryTable) -> Self {
+        let mut picker = MovePicker {
+            hash_move: None,
+            captures: Vec::new(),
+            killers: Vec::new(),
+            quiets: Vec::new(),
+        };
+        for chess_move in moves {
+            if hash_move == Some(chess_move.as_str()) {
+                picker.hash_move = Some(chess_move);
+                continue;
+            }
+            let promotion = promotion_value(state, &chess_move);
+            if let Some(score) = mvv_lva(state, &chess_move) {
+                picker.captures.push((score + promotion, chess_move));
+            } else if promotion > 0 {
+                picker.captures.push((promotion, chess_move));
+            } else if killers.is_killer(ply, &chess_move) {
+                picker.killers.push(chess_move);
+            } else {
+                let score = history.get(&chess_move);
+                picker.quiets.push((score, chess_move));
+            }
+        }
+        // sorted ascending so the best move is popped off the end
+        picker.captures.sort_by_key(|(score, _)| *score);
+        picker.quiets.sort_by_key(|(score, _)| *score);
+        // newest killer first
+        let order = killers.get(ply);
+        picker.killers.sort_by_key(|chess_move| {
+            std::cmp::Reverse(order.iter().position(|killer| killer.as_deref() == Some(chess_move.as_str())))
+        });
+        picker
+    }
+
+    pub fn len(&self) -> usize {
+        self.hash_move.iter().count() + self.captures.len() + self.killers.len() + self.quiets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Iterator for MovePicker {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        if let Some(hash_move) = self.hash_move.take() {
+            return Some(hash_move);
+        }
+        if let Some((_, capture)) = self.captures.pop() {
+            return Some(capture);
+        }
+        if let Some(killer) = self.killers.pop() {
+            return Some(killer);
+        }
+        self.quiets.pop().map(|(_, quiet)| quiet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Game;
+
+    #[test]
+    fn emits_hash_then_captures_then_killers_then_history() {
+        let game = Game::game_from_fen("4k3/8/8/3q1r2/4P3/2NP4/8/4K3 w - - 0 1").unwrap();
+        let moves = game.get_validated_moves(game.state.player_turn);
+        let mut killers = KillerMoves::default();
+        killers.store(3, "c3-b5");
+        killers.store(3, "e1-d1");
+        let mut history = HistoryTable::default();
+        history.update("e1-e2", 5);
+        history.update("c3-a4", 3);
+
+        let picker = MovePicker::new(&game.state, moves.clone(), Some("c3-d5"), 3, &killers, &history);
+        assert_eq!(picker.len(), moves.len());
+        let order: Vec<String> = picker.collect();
+        let expected = [
+            // the hash move, even though it is a capture
+            "c3-d5",
+            // pawn takes queen, pawn takes rook
+            "e4-d5", "e4-f5",
+            // newest killer first
+            "e1-d1", "c3-b5",
+            "e1-e2", "c3-a4",
+        ];
+        assert_eq!(&order[..expected.len()], &expected);
+        let mut sorted = order.clone();
+        sorted.sort();
+        let mut legal = moves;
+        legal.sort();
+        assert_eq!(sorted, legal);
+
+        // killers from another ply are just quiet moves
+        let order: Vec<String> = MovePicker::new(&game.state, legal, None, 4, &killers, &history).collect();
+        assert_eq!(&order[..5], &["e4-d5", "c3-d5", "e4-f5", "e1-e2", "c3-a4"]);
+    }
+
+    #[test]
+    fn mvv_lva_prefers_big_victims_and_small_attackers() {
+        let state = Game::game_from_fen("4k3/8/8/3q1r2/4P3/2NP4/8/4K3 w - - 0 1").unwrap().state;
+        assert!(mvv_lva(&state, "e4-d5") > mvv_lva(&state, "c3-d5"));
+        assert!(mvv_lva(&state, "c3-d5") > mvv_lva(&state, "e4-f5"));
+        assert_eq!(mvv_lva(&state, "d3-d4"), None);
+        assert!(!is_capture(&state, "c3-b5"));
+    }
+
+    #[test]
+    fn killers_keep_the_two_newest() {
+        let mut killers = KillerMoves::default();
+        killers.store(2, "a2-a3");
+        killers.store(2, "b2-b3");
+        assert_eq!(killers.get(2), &[Some("b2-b3".to_string()), Some("a2-a3".to_string())]);
+        // storing the newest again changes nothing
+        killers.store(2, "b2-b3");
+        assert_eq!(killers.get(2), &[Some("b2-b3".to_string()), Some("a2-a3".to_string())]);
+        killers.store(2, "c2-c3");
+        assert_eq!(killers.get(2), &[Some("c2-c3".to_string()), Some("b2-b3".to_string())]);
+        assert!(!killers.is_killer(2, "a2-a3"));
+        assert!(!killers.is_killer(1, "c2-c3"));
+        // past the last ply nothing is kept
+        killers.store(MAX_PLY, "a2-a3");
+        assert!(killers.get(MAX_PLY).is_empty());
+        killers.clear();
+        assert_eq!(killers.get(2), &[None, None]);
+    }
+
+    #[test]
+    fn history_halves_when_a_score_gets_too_big() {
+        let mut history = HistoryTable::default();
+        history.update("g1-f3", 10);
+        history.update("g1-f3", 10);
+        assert_eq!(history.get("g1-f3"), 200);
+        assert_eq!(history.get("not a move"), 0);
+        // 1024 squared is the cap, which ages every score
+        history.update("e2-e4", 1024);
+        assert_eq!(history.get("e2-e4"), HISTORY_MAX / 2);
+        assert_eq!(history.get("g1-f3"), 100);
+        history.age();
+        assert_eq!(history.get("g1-f3"), 50);
+        history.clear();
+        assert_eq!(history.get("e2-e4"), 0);
+    }
+}
@@ -0,0 +1,283 @@
+// A small efficiently updatable network: 768 inputs (colour x piece x square, seen from
+// each side), one hidden layer per perspective and a single output. Integers all the way.
+//
+// The file is little endian: b"CNUE", a u32 version (1), a u32 hidden size, then the i16
+// feature weights input by input, the i16 feature biases, the i16 output weights (side to
+// move's half first) and an i32 output bias.
+
+use std::fs;
+use std::sync::Arc;
+
+use crate::chess_errors::ChessErrors;
+use crate::visual::{self, GameState, PLAYER};
+
+pub const INPUTS: usize = 768;
+const MAGIC: &[u8; 4] = b"CNUE";
+const VERSION: u32 = 1;
+// the hidden layer is clipped to 0..QA, the output weights are scaled by QB
+const QA: i32 = 255;
+const QB: i32 = 64;
+// network output to centipawns
+const SCALE: i64 = 400;
+// well short of the scores the search keeps for mates and tablebase wins
+const MAX_EVAL: i64 = 10_000;
+
+pub struct Network {
+    hidden: usize,
+    feature_weights: Vec<i16>,
+    feature_bias: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i32,
+}
+
+// the hidden layer before activation, kept for both sides so a move only adds and
+// removes the few inputs it changes
+#[derive(Clone, Debug, Default)]
+pub struct Accumulator {
+    white: Vec<i16>,
+    black: Vec<i16>,
+}
+
+fn piece_kind(unicode_val: char) -> Option<usize> {
+    match unicode_val {
+        visual::WHITE_PAWN | visual::BLACK_PAWN => Some(0),
+        visual::WHITE_KNIGHT | visual::BLACK_KNIGHT => Some(1),
+        visual::WHITE_BISHOP | visual::BLACK_BISHOP => Some(2),
+        visual::WHITE_ROOK | visual::BLACK_ROOK => Some(3),
+        visual::WHITE_QUEEN | visual::BLACK_QUEEN => Some(4),
+        visual::WHITE_KING | visual::BLACK_KING => Some(5),
+        _ => None,
+    }
+}
+
+// the input for a piece on the board index (a8 is 0) as perspective sees it: its own
+// pieces first, and the board flipped for black so both play up the board
+fn feature(perspective: PLAYER, owner: PLAYER, kind: usize, index: usize) -> usize {
+    let square = match perspective {
+        PLAYER::WHITE => index ^ 56,
+        PLAYER::BLACK => index,
+    };
+    (owner != perspective) as usize * 384 + kind * 64 + square
+}
+
+fn read_u32(bytes: &[u8], at: &mut usize) -> Option<u32> {
+    let value = u32::from_le_bytes(bytes.get(*at..*at + 4)?.try_into().ok()?);
+    *at += 4;
+    Some(value)
+}
+
+fn read_i16s(bytes: &[u8], at: &mut usize, count: usize) -> Option<Vec<i16>> {
+    let slice = bytes.get(*at..*at + count * 2)?;
+    *at += count * 2;
+    Some(slice.chunks_exact(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect())
+}
+
+impl Network {
+    pub fn open(path: &str) -> Result<Arc<Network>, ChessErrors> {
+        let bytes = fs::read(path).map_err(|e| ChessErrors::NetworkError(format!("{}: {}", path, e)))?;
+        Network::from_bytes(&bytes)
+            .map(Arc::new)
+            .map_err(|e| ChessErrors::NetworkError(format!("{}: {}", path, e)))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Network, ChessErrors> {
+        let invalid = |what: &str| ChessErrors::NetworkError(what.to_string());
+        if bytes.get(..4) != Some(&MAGIC[..]) {
+            return Err(invalid("not a network file"));
+        }
+        let mut at = 4;
+        if read_u32(bytes, &mut at) != Some(VERSION) {
+            return Err(invalid("unsupported version"));
+        }
+        let hidden = read_u32(bytes, &mut at).ok_or_else(|| invalid("truncated"))? as usize;
+        let network = (|| {
+            let network = Network {
+                hidden,
+                feature_weights: read_i16s(bytes, &mut at, INPUTS.checked_mul(hidden)?)?,
+                feature_bias: read_i16s(bytes, &mut at, hidden)?,
+                output_weights: read_i16s(bytes, &mut at, 2 * hidden)?,
+                output_bias: read_u32(bytes, &mut at)? as i32,
+            };
+            Some(network)
+        })()
+        .ok_or_else(|| invalid("truncated"))?;
+        if at != bytes.len() {
+            return Err(invalid("trailing data"));
+        }
+        Ok(network)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.hidden as u32).to_le_bytes());
+        for value in self.feature_weights.iter().chain(&self.feature_bias).chain(&self.output_weights) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.output_bias.to_le_bytes());
+        bytes
+    }
+
+    pub fn hidden_size(&self) -> usize {
+        self.hidden
+    }
+
+    fn add(&self, values: &mut [i16], feature: usize) {
+        let weights = &self.feature_weights[feature * self.hidden..(feature + 1) * self.hidden];
+        for (value, weight) in values.iter_mut().zip(weights) {
+            *value = value.wrapping_add(*weight);
+        }
+    }
+
+    fn remove(&self, values: &mut [i16], feature: usize) {
+        let weights = &self.feature_weights[feature * self.hidden..(feature + 1) * self.hidden];
+        for (value, weight) in values.iter_mut().zip(weights) {
+            *value = value.wrapping_sub(*weight);
+        }
+    }
+
+    // builds the accumulator for state from nothing
+    pub fn refresh(&self, state: &GameState, accumulator: &mut Accumulator) {
+        accumulator.white.clone_from(&self.feature_bias);
+        accumulator.black.clone_from(&self.feature_bias);
+        for (index, piece) in state.state.iter().enumerate() {
+            if let Some(piece) = piece {
+                if let Some(kind) = piece_kind(piece.get_unicode_val()) {
+                    let owner = piece.get_player();
+                    self.add(&mut accumulator.white, feature(PLAYER::WHITE, owner, kind, index));
+                    self.add(&mut accumulator.black, feature(PLAYER::BLACK, owner, kind, index));
+                }
+            }
+        }
+    }
+
+    // The accumulator for after, which is before with one move played, from before's
+    // accumulator. Only the squares that changed are looked at.
+    pub fn update(&self, accumulator: &Accumulator, before: &GameState, after: &GameState, into: &mut Accumulator) {
+        into.white.clone_from(&accumulator.white);
+        into.black.clone_from(&accumulator.black);
+        for index in 0..64 {
+            let old = before.state[index].as_ref().map(|piece| (piece.get_unicode_val(), piece.get_player()));
+            let new = after.state[index].as_ref().map(|piece| (piece.get_unicode_val(), piece.get_player()));
+            if old.map(|(unicode_val, _)| unicode_val) == new.map(|(unicode_val, _)| unicode_val) {
+                continue;
+            }
+            if let Some((kind, owner)) = old.and_then(|(unicode_val, owner)| Some((piece_kind(unicode_val)?, owner))) {
+                self.remove(&mut into.white, feature(PLAYER::WHITE, owner, kind, index));
+                self.remove(&mut into.black, feature(PLAYER::BLACK, owner, kind, index));
+            }
+            if let Some((kind, owner)) = new.and_then(|(unicode_val, owner)| Some((piece_kind(unicode_val)?, owner))) {
+                self.add(&mut into.white, feature(PLAYER::WHITE, owner, kind, index));
+                self.add(&mut into.black, feature(PLAYER::BLACK, owner, kind, index));
+            }
+        }
+    }
+
+    // centipawns for player, the side to move
+    pub fn evaluate(&self, accumulator: &Accumulator, player: PLAYER) -> i32 {
+        let (us, them) = match player {
+            PLAYER::WHITE => (&accumulator.white, &accumulator.black),
+            PLAYER::BLACK => (&accumulator.black, &accumulator.white),
+        };
+        let (our_weights, their_weights) = self.output_weights.split_at(self.hidden);
+        // a wide hidden layer of big weights adds up past what an i32 holds
+        let mut output: i64 = 0;
+        for (value, weight) in us.iter().zip(our_weights).chain(them.iter().zip(their_weights)) {
+            output += ((*value as i32).clamp(0, QA) * *weight as i32) as i64;
+        }
+        ((output + self.output_bias as i64) * SCALE / (QA * QB) as i64).clamp(-MAX_EVAL, MAX_EVAL) as i32
+    }
+
+    // without an accumulator to start from
+    pub fn evaluate_state(&self, state: &GameState) -> i32 {
+        let mut accumulator = Accumulator::default();
+        self.refresh(state, &mut accumulator);
+        self.evaluate(&accumulator, state.player_turn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Game;
+    use crate::prng::Prng;
+
+    fn random_network(hidden: usize, seed: u64) -> Network {
+        let mut prng = Prng::new(seed);
+        let mut weights = |count: usize| -> Vec<i16> { (0..count).map(|_| prng.below(201) as i16 - 100).collect() };
+        Network {
+            hidden,
+            feature_weights: weights(INPUTS * hidden),
+            feature_bias: weights(hidden),
+            output_weights: weights(2 * hidden),
+            output_bias: 1000,
+        }
+    }
+
+    fn refreshed(network: &Network, state: &GameState) -> Accumulator {
+        let mut accumulator = Accumulator::default();
+        network.refresh(state, &mut accumulator);
+        accumulator
+    }
+
+    // every move from fen, and every reply, updated from the parent matches a refresh
+    fn assert_updates_match(network: &Network, fen: &str) {
+        let game = Game::game_from_fen(fen).unwrap();
+        let root = refreshed(network, &game.state);
+        for chess_move in game.get_validated_moves(game.state.player_turn) {
+            let mut child = game.clone();
+            child.make_move(&chess_move).unwrap();
+            let mut updated = Accumulator::default();
+            network.update(&root, &game.state, &child.state, &mut updated);
+            let full = refreshed(network, &child.state);
+            assert_eq!((&updated.white, &updated.black), (&full.white, &full.black), "{} after {}", fen, chess_move);
+            assert_eq!(network.evaluate(&updated, child.state.player_turn), network.evaluate_state(&child.state));
+            for reply in child.get_validated_moves(child.state.player_turn) {
+                let mut grandchild = child.clone();
+                grandchild.make_move(&reply).unwrap();
+                let mut next = Accumulator::default();
+                network.update(&updated, &child.state, &grandchild.state, &mut next);
+                let full = refreshed(network, &grandchild.state);
+                assert_eq!((&next.white, &next.black), (&full.white, &full.black), "{} after {} {}", fen, chess_move, reply);
+            }
+        }
+    }
+
+    #[test]
+    fn incremental_updates_match_a_refresh() {
+        let network = random_network(8, 1);
+        // captures and castling on both sides
+        assert_updates_match(&network, "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        // promotions, with and without a capture, to every piece
+        assert_updates_match(&network, "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1");
+        // en passant
+        assert_updates_match(&network, "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3");
+    }
+
+    #[test]
+    fn big_outputs_do_not_overflow() {
+        let hidden = 256;
+        let network = Network {
+            hidden,
+            feature_weights: vec![0; INPUTS * hidden],
+            feature_bias: vec![QA as i16; hidden],
+            output_weights: vec![i16::MAX; 2 * hidden],
+            output_bias: i32::MAX,
+        };
+        let game = Game::game_from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(network.evaluate_state(&game.state), MAX_EVAL as i32);
+        let network = Network { output_weights: vec![i16::MIN; 2 * hidden], output_bias: i32::MIN, ..network };
+        assert_eq!(network.evaluate_state(&game.state), -MAX_EVAL as i32);
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let network = random_network(4, 2);
+        let read = Network::from_bytes(&network.to_bytes()).unwrap();
+        assert_eq!(read.to_bytes(), network.to_bytes());
+        let mut truncated = network.to_bytes();
+        truncated.pop();
+        assert!(Network::from_bytes(&truncated).is_err());
+        assert!(Network::from_bytes(b"NOPE").is_err());
+    }
+}
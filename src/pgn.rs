@@ -0,0 +1,198 @@
+use crate::chess_notation_utilities;
+use crate::game::Game;
+use crate::visual;
+
+use visual::PLAYER;
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+// Standard algebraic notation for a legal move in game, e.g. "Nbd7", "exd6", "O-O", "e8=Q+".
+pub fn move_to_san(game: &Game, chess_move: &str) -> Option<String> {
+    let (from, to) = chess_notation_utilities::move_notation_to_indexes(chess_move).ok()?;
+    let piece = game.state.state[from].as_ref()?;
+    let letter = visual::fen_char_from_unicode(piece.get_unicode_val()).to_ascii_uppercase();
+    let from_spot = &chess_move[0..2];
+    let to_spot = &chess_move[3..5];
+    let mut san = if letter == 'K' && (from % 8).abs_diff(to % 8) == 2 {
+        if to % 8 > from % 8 { "O-O".to_string() } else { "O-O-O".to_string() }
+    } else if letter == 'P' {
+        // a pawn changing file always captures, en passant onto an empty square included
+        let mut san = if from % 8 != to % 8 { format!("{}x{}", &from_spot[0..1], to_spot) } else { to_spot.to_string() };
+        if let Some(promotion) = chess_move.get(6..7) {
+            san.push('=');
+            san.push(if promotion == "k" { 'N' } else { promotion.to_ascii_uppercase().chars().next()? });
+        }
+        san
+    } else {
+        // name the file, the rank or both when another piece of the kind can go there too
+        let rivals: Vec<String> = game
+            .get_validated_moves(game.state.player_turn)
+            .into_iter()
+            .filter(|other| other != chess_move && &other[3..5] == to_spot && &other[0..2] != from_spot)
+            .filter(|other| {
+                chess_notation_utilities::move_notation_to_indexes(other)
+                    .ok()
+                    .and_then(|(other_from, _)| game.state.state[other_from].as_ref().map(|p| p.get_unicode_val()))
+                    == Some(piece.get_unicode_val())
+            })
+            .collect();
+        let mut san = letter.to_string();
+        if !rivals.is_empty() {
+            if rivals.iter().all(|other| other[0..1] != from_spot[0..1]) {
+                san.push_str(&from_spot[0..1]);
+            } else if rivals.iter().all(|other| other[1..2] != from_spot[1..2]) {
+                san.push_str(&from_spot[1..2]);
+            } else {
+                san.push_str(from_spot);
+            }
+        }
+        if game.state.state[to].is_some() {
+            san.push('x');
+        }
+        san.push_str(to_spot);
+        san
+    };
+    let mut after = Game {
+        state: game.state.clone(),
+        turn_history: Vec::new(),
+    };
+    after.make_move(chess_move).ok()?;
+    let opponent = after.state.player_turn;
+    if after.state.is_in_check(opponent) {
+        san.push(if after.get_validated_moves(opponent).is_empty() { '#' } else { '+' });
+    }
+    Some(san)
+}
+
+// one game's worth of PGN
+#[derive(Clone, Debug, Default)]
+pub struct PgnGame {
+    // in the order they are written, after the seven tag roster
+    pub tags: Vec<(String, String)>,
+    pub event: String,
+    pub site: String,
+    pub date: String,
+    pub round: String,
+    pub white: String,
+    pub black: String,
+    // "1-0", "0-1", "1/2-1/2" or "*"
+    pub result: String,
+    pub start_fen: Option<String>,
+    // in this crate's notation
+    pub moves: Vec<String>,
+    // a comment after the moves, such as why the game ended
+    pub termination: Option<String>,
+}
+
+impl PgnGame {
+    pub fn to_pgn(&self) -> String {
+        let quote = |value: &str| value.replace('\\', "\\\\").replace('"', "\\\"");
+        let mut pgn = String::new();
+        for (tag, value) in [
+            ("Event", &self.event),
+            ("Site", &self.site),
+            ("Date", &self.date),
+            ("Round", &self.round),
+            ("White", &self.white),
+            ("Black", &self.black),
+            ("Result", &self.result),
+        ] {
+            pgn.push_str(&format!("[{} \"{}\"]\n", tag, quote(if value.is_empty() { "?" } else { value })));
+        }
+        if let Some(fen) = self.start_fen.as_ref().filter(|fen| fen.as_str() != START_FEN) {
+            pgn.push_str(&format!("[SetUp \"1\"]\n[FEN \"{}\"]\n", quote(fen)));
+        }
+        for (tag, value) in &self.tags {
+            pgn.push_str(&format!("[{} \"{}\"]\n", tag, quote(value)));
+        }
+        pgn.push('\n');
+
+        let mut game = Game::game_from_fen(self.start_fen.as_deref().unwrap_or(START_FEN)).unwrap_or_default();
+        let mut tokens = Vec::new();
+        for (index, chess_move) in self.moves.iter().enumerate() {
+            let white = game.state.player_turn == PLAYER::WHITE;
+            if white {
+                tokens.push(format!("{}.", game.state.fullmove_number));
+            } else if index == 0 {
+                tokens.push(format!("{}...", game.state.fullmove_number));
+            }
+            match move_to_san(&game, chess_move) {
+                Some(san) => tokens.push(san),
+                None => break,
+            }
+            if game.make_move(chess_move).is_err() {
+                break;
+            }
+        }
+        if let Some(termination) = &self.termination {
+            tokens.push(format!("{{{}}}", termination));
+        }
+        tokens.push(self.result.clone());
+
+        // movetext lines stay under 80 characters
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > 79 {
+                pgn.push_str(&line);
+                pgn.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        pgn.push_str(&line);
+        pgn.push_str("\n\n");
+        pgn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn san(fen: &str, chess_move: &str) -> String {
+        move_to_san(&Game::game_from_fen(fen).unwrap(), chess_move).unwrap()
+    }
+
+    #[test]
+    fn names_the_file_or_rank_only_when_needed() {
+        let knights = "rnbqkbnr/ppp1pppp/8/3p4/3P4/5N2/PPP1PPPP/RNBQKB1R w KQkq - 0 1";
+        assert_eq!(san(knights, "b1-d2"), "Nbd2");
+        assert_eq!(san(knights, "f3-d2"), "Nfd2");
+        assert_eq!(san(knights, "b1-c3"), "Nc3");
+        let rooks = "4k3/8/8/R7/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(san(rooks, "a1-a3"), "R1a3");
+        assert_eq!(san(rooks, "a5-a3"), "R5a3");
+        // one rival shares the file, another the rank
+        assert_eq!(san("4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1", "a1-b2"), "Qa1b2");
+    }
+
+    #[test]
+    fn writes_captures_castling_and_promotions() {
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        assert_eq!(san(kiwipete, "e1-g1"), "O-O");
+        assert_eq!(san(kiwipete, "e1-c1"), "O-O-O");
+        assert_eq!(san(kiwipete, "e5-f7"), "Nxf7");
+        assert_eq!(san(kiwipete, "d5-e6"), "dxe6");
+        // en passant lands on an empty square
+        assert_eq!(san("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3", "e5-f6"), "exf6");
+        // "k" is our letter for a knight
+        assert_eq!(san("4k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7-a8pk"), "a8=N");
+        assert_eq!(san("4k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7-a8pr"), "a8=R+");
+        assert_eq!(san("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7-b8pq"), "axb8=Q+");
+    }
+
+    #[test]
+    fn marks_check_and_mate() {
+        assert_eq!(san("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", "a1-a8"), "Ra8+");
+        assert_eq!(san("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "a1-a8"), "Ra8#");
+        let mut fools = Game::game_from_fen(START_FEN).unwrap();
+        for chess_move in ["f2-f3", "e7-e5", "g2-g4"] {
+            fools.make_move(chess_move).unwrap();
+        }
+        assert_eq!(move_to_san(&fools, "d8-h4").as_deref(), Some("Qh4#"));
+        assert_eq!(move_to_san(&fools, "e2-e4"), None);
+    }
+}
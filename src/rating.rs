@@ -0,0 +1,249 @@
+// Glicko-2 (Glickman, "Example of the Glicko-2 system"), with every game its own rating period.
+
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chess_errors::ChessErrors;
+use crate::time_control::TimeControl;
+
+pub const DEFAULT_RATING: f64 = 1500.0;
+pub const DEFAULT_DEVIATION: f64 = 350.0;
+pub const DEFAULT_VOLATILITY: f64 = 0.06;
+// a rating this uncertain is only a guess yet
+pub const PROVISIONAL_DEVIATION: f64 = 110.0;
+// keeps an active player's rating from setting in stone
+const MIN_DEVIATION: f64 = 45.0;
+// how much volatility may change, the system constant tau
+const TAU: f64 = 0.5;
+const SCALE: f64 = 173.7178;
+const CONVERGENCE: f64 = 0.000001;
+
+// ratings are kept apart by how long the games last
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pool {
+    Bullet,
+    Blitz,
+    Rapid,
+    Classical,
+}
+
+impl Pool {
+    pub const ALL: [Pool; 4] = [Pool::Bullet, Pool::Blitz, Pool::Rapid, Pool::Classical];
+
+    // by the time a side gets for 40 moves: under 3 minutes bullet, 8 blitz, 25 rapid
+    pub fn for_time_control(time_control: &TimeControl) -> Pool {
+        let seconds = time_control.estimated_time(40).as_secs_f64();
+        if seconds < 180.0 {
+            Pool::Bullet
+        } else if seconds < 480.0 {
+            Pool::Blitz
+        } else if seconds < 1500.0 {
+            Pool::Rapid
+        } else {
+            Pool::Classical
+        }
+    }
+}
+
+impl fmt::Display for Pool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Pool::Bullet => "bullet",
+            Pool::Blitz => "blitz",
+            Pool::Rapid => "rapid",
+            Pool::Classical => "classical",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Pool {
+    type Err = ChessErrors;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Pool::ALL
+            .into_iter()
+            .find(|pool| pool.to_string() == text)
+            .ok_or_else(|| ChessErrors::InvalidPool(text.to_string()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Rating {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+    pub games: u32,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Rating {
+            rating: DEFAULT_RATING,
+            deviation: DEFAULT_DEVIATION,
+            volatility: DEFAULT_VOLATILITY,
+            games: 0,
+        }
+    }
+}
+
+fn g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+}
+
+fn expected(mu: f64, opponent_mu: f64, opponent_phi: f64) -> f64 {
+    1.0 / (1.0 + (-g(opponent_phi) * (mu - opponent_mu)).exp())
+}
+
+impl Rating {
+    pub fn is_provisional(&self) -> bool {
+        self.deviation > PROVISIONAL_DEVIATION
+    }
+
+    // The rating after one period with these results, each the opponent's rating before
+    // the period and the score against them (1 win, 0.5 draw, 0 loss).
+    pub fn update(&self, results: &[(Rating, f64)]) -> Rating {
+        let mu = (self.rating - DEFAULT_RATING) / SCALE;
+        let phi = self.deviation / SCALE;
+        if results.is_empty() {
+            // no games: only the uncertainty grows
+            let phi = (phi * phi + self.volatility * self.volatility).sqrt();
+            return Rating { deviation: (phi * SCALE).min(DEFAULT_DEVIATION), ..*self };
+        }
+
+        let mut inverse_v = 0.0;
+        let mut improvement = 0.0;
+        for (opponent, score) in results {
+            let opponent_mu = (opponent.rating - DEFAULT_RATING) / SCALE;
+            let opponent_phi = opponent.deviation / SCALE;
+            let e = expected(mu, opponent_mu, opponent_phi);
+            inverse_v += g(opponent_phi).powi(2) * e * (1.0 - e);
+            improvement += g(opponent_phi) * (score - e);
+        }
+        let v = 1.0 / inverse_v;
+        let delta = v * improvement;
+
+        // the new volatility is the root of f, found by the Illinois algorithm
+        let a = (self.volatility * self.volatility).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            ex * (delta * delta - phi * phi - v - ex) / (2.0 * (phi * phi + v + ex).powi(2)) - (x - a) / (TAU * TAU)
+        };
+        let mut low = a;
+        let mut high = if delta * delta > phi * phi + v {
+            (delta * delta - phi * phi - v).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * TAU) < 0.0 {
+                k += 1.0;
+            }
+            a - k * TAU
+        };
+        let (mut f_low, mut f_high) = (f(low), f(high));
+        while (high - low).abs() > CONVERGENCE {
+            let middle = low + (low - high) * f_low / (f_high - f_low);
+            let f_middle = f(middle);
+            if f_middle * f_high <= 0.0 {
+                low = high;
+                f_low = f_high;
+            } else {
+                f_low /= 2.0;
+            }
+            high = middle;
+            f_high = f_middle;
+        }
+        let volatility = (low / 2.0).exp();
+
+        let phi_star = (phi * phi + volatility * volatility).sqrt();
+        let new_phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let new_mu = mu + new_phi * new_phi * improvement;
+        Rating {
+            rating: new_mu * SCALE + DEFAULT_RATING,
+            deviation: (new_phi * SCALE).clamp(MIN_DEVIATION, DEFAULT_DEVIATION),
+            volatility,
+            games: self.games + results.len() as u32,
+        }
+    }
+}
+
+// both players' ratings after one game between them; white_score is 1, 0.5 or 0
+pub fn rate_game(white: &Rating, black: &Rating, white_score: f64) -> (Rating, Rating) {
+    (white.update(&[(*black, white_score)]), black.update(&[(*white, 1.0 - white_score)]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating(rating: f64, deviation: f64) -> Rating {
+        Rating { rating, deviation, ..Rating::default() }
+    }
+
+    fn pool(time_control: &str) -> Pool {
+        Pool::for_time_control(&time_control.parse().unwrap())
+    }
+
+    #[test]
+    fn matches_glickmans_worked_example() {
+        let player = Rating { rating: 1500.0, deviation: 200.0, volatility: 0.06, games: 0 };
+        let updated = player.update(&[(rating(1400.0, 30.0), 1.0), (rating(1550.0, 100.0), 0.0), (rating(1700.0, 300.0), 0.0)]);
+        assert!((updated.rating - 1464.06).abs() < 0.01, "{}", updated.rating);
+        assert!((updated.deviation - 151.52).abs() < 0.01, "{}", updated.deviation);
+        assert!((updated.volatility - 0.05999).abs() < 0.00001, "{}", updated.volatility);
+        assert_eq!(updated.games, 3);
+    }
+
+    #[test]
+    fn a_period_without_games_only_adds_uncertainty() {
+        let player = rating(1800.0, 60.0);
+        let idle = player.update(&[]);
+        assert_eq!(idle.rating, 1800.0);
+        assert!(idle.deviation > 60.0);
+        assert_eq!(Rating::default().update(&[]).deviation, DEFAULT_DEVIATION);
+    }
+
+    #[test]
+    fn a_game_moves_both_ratings_the_opposite_way() {
+        let (white, black) = rate_game(&Rating::default(), &Rating::default(), 1.0);
+        assert!(white.rating > DEFAULT_RATING && black.rating < DEFAULT_RATING);
+        assert!((white.rating - DEFAULT_RATING - (DEFAULT_RATING - black.rating)).abs() < 1e-9);
+        let (white, black) = rate_game(&Rating::default(), &Rating::default(), 0.5);
+        assert!((white.rating - DEFAULT_RATING).abs() < 1e-9 && (black.rating - DEFAULT_RATING).abs() < 1e-9);
+    }
+
+    #[test]
+    fn pools_split_on_the_time_for_forty_moves() {
+        // two minutes, or one plus forty two second increments
+        assert_eq!(pool("2+0"), Pool::Bullet);
+        assert_eq!(pool("1+2"), Pool::Bullet);
+        assert_eq!(pool("2.98+0"), Pool::Bullet);
+        assert_eq!(pool("3+0"), Pool::Blitz);
+        assert_eq!(pool("3+2"), Pool::Blitz);
+        assert_eq!(pool("7.98+0"), Pool::Blitz);
+        assert_eq!(pool("8+0"), Pool::Rapid);
+        assert_eq!(pool("15+10"), Pool::Rapid);
+        assert_eq!(pool("24.98+0"), Pool::Rapid);
+        assert_eq!(pool("25+0"), Pool::Classical);
+        assert_eq!(pool("40/90+30,30+30"), Pool::Classical);
+    }
+
+    #[test]
+    fn provisional_above_110() {
+        assert!(Rating::default().is_provisional());
+        assert!(rating(1500.0, 110.01).is_provisional());
+        assert!(!rating(1500.0, 110.0).is_provisional());
+        assert!(!rating(1500.0, MIN_DEVIATION).is_provisional());
+    }
+
+    #[test]
+    fn pools_read_back_as_written() {
+        for pool in Pool::ALL {
+            assert_eq!(pool.to_string().parse::<Pool>().unwrap(), pool);
+        }
+        assert!("correspondence".parse::<Pool>().is_err());
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::chess_errors::ChessErrors;
+use crate::session::GameStatus;
+use crate::time_control::TimeControl;
+use crate::visual::PLAYER;
+
+// A game as it is kept between runs: where it started, the moves played since, and
+// everything about it the moves can't tell.
+#[derive(Clone, Debug)]
+pub struct StoredGame {
+    pub id: String,
+    pub start_fen: String,
+    pub moves: Vec<String>,
+    pub status: GameStatus,
+    pub result: Option<String>,
+    pub draw_offer: Option<PLAYER>,
+    pub time_control: Option<TimeControl>,
+    pub rated: bool,
+    pub invite: Option<String>,
+    // white's first, as in the session
+    pub tokens: [Option<String>; 2],
+    pub users: [Option<String>; 2],
+    // the time each side had left after the last move, for games with clocks
+    pub clocks: [Option<Duration>; 2],
+}
+
+// where the server keeps its games
+pub trait GameRepository: Send + Sync {
+    // adds the game, or brings the stored one up to date
+    fn save(&self, game: &StoredGame) -> Result<(), ChessErrors>;
+
+    fn load(&self, id: &str) -> Result<Option<StoredGame>, ChessErrors>;
+
+    // the games still being played
+    fn active(&self) -> Result<Vec<StoredGame>, ChessErrors>;
+
+    // the highest numeric id given out so far, 0 for none
+    fn last_id(&self) -> Result<u64, ChessErrors>;
+}
+
+// keeps nothing once the process ends; for tests and throwaway servers
+#[derive(Default)]
+pub struct InMemoryGameRepository {
+    games: Mutex<HashMap<String, StoredGame>>,
+}
+
+impl GameRepository for InMemoryGameRepository {
+    fn save(&self, game: &StoredGame) -> Result<(), ChessErrors> {
+        self.games.lock().unwrap().insert(game.id.clone(), game.clone());
+        Ok(())
+    }
+
+    fn load(&self, id: &str) -> Result<Option<StoredGame>, ChessErrors> {
+        Ok(self.games.lock().unwrap().get(id).cloned())
+    }
+
+    fn active(&self) -> Result<Vec<StoredGame>, ChessErrors> {
+        Ok(self.games.lock().unwrap().values().filter(|game| game.status == GameStatus::Active).cloned().collect())
+    }
+
+    fn last_id(&self) -> Result<u64, ChessErrors> {
+        Ok(self.games.lock().unwrap().keys().filter_map(|id| id.parse().ok()).max().unwrap_or(0))
+    }
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS games (
+        id TEXT PRIMARY KEY,
+        start_fen TEXT NOT NULL,
+        status TEXT NOT NULL,
+        result TEXT,
+        draw_offer TEXT,
+        time_control TEXT,
+        rated INTEGER NOT NULL,
+        invite TEXT,
+        white_token TEXT,
+        black_token TEXT,
+        white_user TEXT,
+        black_user TEXT,
+        white_clock_ms INTEGER,
+        black_clock_ms INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS moves (
+        game_id TEXT NOT NULL REFERENCES games (id),
+        ply INTEGER NOT NULL,
+        notation TEXT NOT NULL,
+        PRIMARY KEY (game_id, ply)
+    );
+    CREATE INDEX IF NOT EXISTS games_by_status ON games (status);
+";
+
+fn storage_error(error: impl std::fmt::Display) -> ChessErrors {
+    ChessErrors::StorageError(error.to_string())
+}
+
+fn player_text(player: PLAYER) -> &'static str {
+    match player {
+        PLAYER::WHITE => "white",
+        PLAYER::BLACK => "black",
+    }
+}
+
+fn player_from_text(text: &str) -> Result<PLAYER, ChessErrors> {
+    match text {
+        "white" => Ok(PLAYER::WHITE),
+        "black" => Ok(PLAYER::BLACK),
+        _ => Err(storage_error(format!("{} is not a colour", text))),
+    }
+}
+
+fn clock_millis(clock: Option<Duration>) -> Option<i64> {
+    clock.map(|left| left.as_millis() as i64)
+}
+
+// one games row, before its text is parsed
+struct GameRow {
+    start_fen: String,
+    status: String,
+    result: Option<String>,
+    draw_offer: Option<String>,
+    time_control: Option<String>,
+    rated: bool,
+    invite: Option<String>,
+    tokens: [Option<String>; 2],
+    users: [Option<String>; 2],
+    clocks: [Option<i64>; 2],
+}
+
+// A SQLite database file, with a row per game and a row per move. Moves are only ever
+// added, so saving after a move writes just that move.
+pub struct SqliteGameRepository {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteGameRepository {
+    pub fn open(path: &Path) -> Result<SqliteGameRepository, ChessErrors> {
+        let connection = Connection::open(path).map_err(|e| storage_error(format!("{}: {}", path.display(), e)))?;
+        connection.execute_batch(SCHEMA).map_err(storage_error)?;
+        Ok(SqliteGameRepository { connection: Mutex::new(connection) })
+    }
+
+    fn load_with(connection: &Connection, id: &str) -> Result<Option<StoredGame>, ChessErrors> {
+        let row = connection
+            .query_row(
+                "SELECT start_fen, status, result, draw_offer, time_control, rated, invite, white_token, black_token,
+                        white_user, black_user, white_clock_ms, black_clock_ms
+                 FROM games WHERE id = ?1",
+                [id],
+                |row| {
+                    Ok(GameRow {
+                        start_fen: row.get(0)?,
+                        status: row.get(1)?,
+                        result: row.get(2)?,
+                        draw_offer: row.get(3)?,
+                        time_control: row.get(4)?,
+                        rated: row.get(5)?,
+                        invite: row.get(6)?,
+                        tokens: [row.get(7)?, row.get(8)?],
+                        users: [row.get(9)?, row.get(10)?],
+                        clocks: [row.get(11)?, row.get(12)?],
+                    })
+                },
+            )
+            .optional()
+            .map_err(storage_error)?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let mut statement = connection.prepare_cached("SELECT notation FROM moves WHERE game_id = ?1 ORDER BY ply").map_err(storage_error)?;
+        let moves = statement
+            .query_map([id], |row| row.get(0))
+            .and_then(|moves| moves.collect::<Result<Vec<String>, _>>())
+            .map_err(storage_error)?;
+        Ok(Some(StoredGame {
+            id: id.to_string(),
+            start_fen: row.start_fen,
+            moves,
+            status: row.status.parse()?,
+            result: row.result,
+            draw_offer: row.draw_offer.as_deref().map(player_from_text).transpose()?,
+            time_control: row.time_control.map(|text| text.parse()).transpose()?,
+            rated: row.rated,
+            invite: row.invite,
+            tokens: row.tokens,
+            users: row.users,
+            clocks: row.clocks.map(|clock| clock.map(|millis| Duration::from_millis(millis.max(0) as u64))),
+        }))
+    }
+}
+
+impl GameRepository for SqliteGameRepository {
+    fn save(&self, game: &StoredGame) -> Result<(), ChessErrors> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(storage_error)?;
+        transaction
+            .execute(
+                "INSERT INTO games (id, start_fen, status, result, draw_offer, time_control, rated, invite,
+                                    white_token, black_token, white_user, black_user, white_clock_ms, black_clock_ms)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+                 ON CONFLICT (id) DO UPDATE SET
+                     status = excluded.status, result = excluded.result, draw_offer = excluded.draw_offer,
+                     time_control = excluded.time_control, rated = excluded.rated, invite = excluded.invite,
+                     white_token = excluded.white_token, black_token = excluded.black_token,
+                     white_user = excluded.white_user, black_user = excluded.black_user,
+                     white_clock_ms = excluded.white_clock_ms, black_clock_ms = excluded.black_clock_ms",
+                params![
+                    game.id,
+                    game.start_fen,
+                    game.status.to_string(),
+                    game.result,
+                    game.draw_offer.map(player_text),
+                    game.time_control.as_ref().map(|time_control| time_control.to_string()),
+                    game.rated,
+                    game.invite,
+                    game.tokens[0],
+                    game.tokens[1],
+                    game.users[0],
+                    game.users[1],
+                    clock_millis(game.clocks[0]),
+                    clock_millis(game.clocks[1]),
+                ],
+            )
+            .map_err(storage_error)?;
+        let stored: i64 = transaction
+            .query_row("SELECT COUNT(*) FROM moves WHERE game_id = ?1", [&game.id], |row| row.get(0))
+            .map_err(storage_error)?;
+        for (ply, notation) in game.moves.iter().enumerate().skip(stored as usize) {
+            transaction
+                .execute("INSERT INTO moves (game_id, ply, notation) VALUES (?1, ?2, ?3)", params![game.id, ply as i64, notation])
+                .map_err(storage_error)?;
+        }
+        transaction.commit().map_err(storage_error)
+    }
+
+    fn load(&self, id: &str) -> Result<Option<StoredGame>, ChessErrors> {
+        SqliteGameRepository::load_with(&self.connection.lock().unwrap(), id)
+    }
+
+    fn active(&self) -> Result<Vec<StoredGame>, ChessErrors> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare("SELECT id FROM games WHERE status = ?1").map_err(storage_error)?;
+        let ids = statement
+            .query_map([GameStatus::Active.to_string()], |row| row.get(0))
+            .and_then(|ids| ids.collect::<Result<Vec<String>, _>>())
+            .map_err(storage_error)?;
+        ids.iter().filter_map(|id| SqliteGameRepository::load_with(&connection, id).transpose()).collect()
+    }
+
+    fn last_id(&self) -> Result<u64, ChessErrors> {
+        let connection = self.connection.lock().unwrap();
+        let last: Option<i64> =
+            connection.query_row("SELECT MAX(CAST(id AS INTEGER)) FROM games", [], |row| row.get(0)).map_err(storage_error)?;
+        Ok(last.unwrap_or(0).max(0) as u64)
+    }
+}
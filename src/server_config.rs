@@ -0,0 +1,107 @@
+use std::fs;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::chess_errors::ChessErrors;
+
+// How the web server runs. Each setting comes from the first of: a command line flag, an
+// environment variable, the TOML file given by --config or CHESS_CONFIG, or the default.
+//
+//   flag             variable             key            default
+//   --bind           CHESS_BIND           bind           127.0.0.1
+//   --port           CHESS_PORT           port           9090
+//   --cors-origins   CHESS_CORS_ORIGINS   cors_origins   none, comma separated outside the file
+//   --static-dir     CHESS_STATIC_DIR     static_dir     webUI
+//   --api-base       CHESS_API_BASE       api_base       the page's own origin
+//   --users          CHESS_USERS          users          users.json
+//   --games          CHESS_GAMES          games          games.db
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerConfig {
+    pub bind: String,
+    pub port: u16,
+    // the other sites whose pages may call the api; "*" lets any
+    pub cors_origins: Vec<String>,
+    // the front end, served from /
+    pub static_dir: PathBuf,
+    // where the front end sends its requests, for when the api lives on another host
+    pub api_base: String,
+    pub users: String,
+    pub games: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: "127.0.0.1".to_string(),
+            port: 9090,
+            cors_origins: Vec::new(),
+            static_dir: PathBuf::from("webUI"),
+            api_base: String::new(),
+            users: "users.json".to_string(),
+            games: "games.db".to_string(),
+        }
+    }
+}
+
+// the config file, where everything is optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    bind: Option<String>,
+    port: Option<u16>,
+    cors_origins: Option<Vec<String>>,
+    static_dir: Option<PathBuf>,
+    api_base: Option<String>,
+    users: Option<String>,
+    games: Option<String>,
+}
+
+fn origins(text: &str) -> Vec<String> {
+    text.split(',').map(str::trim).filter(|origin| !origin.is_empty()).map(str::to_string).collect()
+}
+
+impl ServerConfig {
+    // args as the process got them, name first; env looks up a variable
+    pub fn load(args: &[String], env: impl Fn(&str) -> Option<String>) -> Result<ServerConfig, ChessErrors> {
+        let arg_value = |name: &str| args.iter().position(|arg| arg == name).and_then(|index| args.get(index + 1)).cloned();
+        let setting = |flag: &str, variable: &str| arg_value(flag).or_else(|| env(variable));
+
+        let file = match setting("--config", "CHESS_CONFIG") {
+            Some(path) => {
+                let text = fs::read_to_string(&path).map_err(|e| ChessErrors::InvalidConfig(format!("{}: {}", path, e)))?;
+                toml::from_str(&text).map_err(|e| ChessErrors::InvalidConfig(format!("{}: {}", path, e)))?
+            }
+            None => ConfigFile::default(),
+        };
+        let defaults = ServerConfig::default();
+        let cors_origins = setting("--cors-origins", "CHESS_CORS_ORIGINS")
+            .map(|text| origins(&text))
+            .or(file.cors_origins)
+            .unwrap_or(defaults.cors_origins);
+        if let Some(origin) = cors_origins
+            .iter()
+            .find(|origin| *origin != "*" && !origin.starts_with("http://") && !origin.starts_with("https://"))
+        {
+            return Err(ChessErrors::InvalidConfig(format!("{} is not an origin like https://example.com", origin)));
+        }
+        let port = match setting("--port", "CHESS_PORT") {
+            Some(port) => port.parse().map_err(|_| ChessErrors::InvalidConfig(format!("{} is not a port", port)))?,
+            None => file.port.unwrap_or(defaults.port),
+        };
+        Ok(ServerConfig {
+            bind: setting("--bind", "CHESS_BIND").or(file.bind).unwrap_or(defaults.bind),
+            port,
+            cors_origins,
+            static_dir: setting("--static-dir", "CHESS_STATIC_DIR").map(PathBuf::from).or(file.static_dir).unwrap_or(defaults.static_dir),
+            // no trailing slash, the paths have their own
+            api_base: setting("--api-base", "CHESS_API_BASE")
+                .or(file.api_base)
+                .unwrap_or(defaults.api_base)
+                .trim_end_matches('/')
+                .to_string(),
+            users: setting("--users", "CHESS_USERS").or(file.users).unwrap_or(defaults.users),
+            games: setting("--games", "CHESS_GAMES").or(file.games).unwrap_or(defaults.games),
+        })
+    }
+}
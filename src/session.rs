@@ -0,0 +1,507 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chess_clock::ChessClock;
+use crate::chess_errors::ChessErrors;
+use crate::game::{Game, GameEnd};
+use crate::pgn::START_FEN;
+use crate::repository::{GameRepository, InMemoryGameRepository, StoredGame};
+use crate::time_control::TimeControl;
+use crate::time_manager::{Clock, SystemClock};
+use crate::visual::PLAYER;
+use crate::zobrist;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameStatus {
+    Active,
+    Checkmate,
+    Stalemate,
+    Repetition,
+    FiftyMoves,
+    InsufficientMaterial,
+    DrawAgreed,
+    // the side to move ran out of time
+    Timeout,
+    // ran out of time, but the other side had nothing to mate with
+    TimeoutVsInsufficientMaterial,
+}
+
+impl GameStatus {
+    const ALL: [GameStatus; 9] = [
+        GameStatus::Active,
+        GameStatus::Checkmate,
+        GameStatus::Stalemate,
+        GameStatus::Repetition,
+        GameStatus::FiftyMoves,
+        GameStatus::InsufficientMaterial,
+        GameStatus::DrawAgreed,
+        GameStatus::Timeout,
+        GameStatus::TimeoutVsInsufficientMaterial,
+    ];
+}
+
+// the same names as in json, e.g. "fifty_moves"
+impl fmt::Display for GameStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            GameStatus::Active => "active",
+            GameStatus::Checkmate => "checkmate",
+            GameStatus::Stalemate => "stalemate",
+            GameStatus::Repetition => "repetition",
+            GameStatus::FiftyMoves => "fifty_moves",
+            GameStatus::InsufficientMaterial => "insufficient_material",
+            GameStatus::DrawAgreed => "draw_agreed",
+            GameStatus::Timeout => "timeout",
+            GameStatus::TimeoutVsInsufficientMaterial => "timeout_vs_insufficient_material",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for GameStatus {
+    type Err = ChessErrors;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        GameStatus::ALL
+            .into_iter()
+            .find(|status| status.to_string() == text)
+            .ok_or_else(|| ChessErrors::StorageError(format!("{} is not a game status", text)))
+    }
+}
+
+// a game being played on the server
+#[derive(Clone)]
+pub struct GameSession {
+    pub id: String,
+    pub start_fen: String,
+    pub game: Game,
+    pub status: GameStatus,
+    // "1-0", "0-1" or "1/2-1/2" once the game is over
+    pub result: Option<String>,
+    // the side with a draw offer standing
+    pub draw_offer: Option<PLAYER>,
+    pub time_control: Option<TimeControl>,
+    // running once the first move is made, for games with a time control
+    clock: Option<ChessClock>,
+    // counts for the players' ratings
+    pub rated: bool,
+    // private games can only be joined with this
+    pub invite: Option<String>,
+    // the secret each seated player moves with, white's first
+    tokens: [Option<String>; 2],
+    // the accounts sitting there, for players who are logged in
+    users: [Option<String>; 2],
+    // one per position so far, for threefold repetition
+    keys: Vec<u64>,
+}
+
+impl GameSession {
+    pub fn new(id: &str, fen: Option<&str>) -> Result<GameSession, ChessErrors> {
+        let start_fen = fen.unwrap_or(START_FEN).to_string();
+        let game = Game::game_from_fen(&start_fen)?;
+        let mut session = GameSession {
+            id: id.to_string(),
+            start_fen,
+            keys: vec![zobrist::position_key(&game.state)],
+            game,
+            status: GameStatus::Active,
+            result: None,
+            draw_offer: None,
+            time_control: None,
+            clock: None,
+            rated: false,
+            invite: None,
+            tokens: [None, None],
+            users: [None, None],
+        };
+        session.update_status();
+        Ok(session)
+    }
+
+    // The stored game back as it was, its moves replayed from the start. A clock that was
+    // running carries on from now, so time the server was down isn't charged.
+    pub fn restore(stored: &StoredGame, now: Duration) -> Result<GameSession, ChessErrors> {
+        let mut session = GameSession::new(&stored.id, Some(&stored.start_fen))?;
+        let first = session.game.state.player_turn;
+        for chess_move in &stored.moves {
+            session.play(chess_move)?;
+        }
+        // an agreed draw or a flag can't be told from the moves
+        session.status = stored.status;
+        session.result = stored.result.clone();
+        session.draw_offer = stored.draw_offer;
+        session.time_control = stored.time_control.clone();
+        session.clock = stored.time_control.clone().map(|time_control| {
+            let start = time_control.stages[0].time;
+            let played = stored.moves.len() as u32;
+            let mut moves = [played / 2, played / 2];
+            moves[seat_index(first)] += played % 2;
+            let running = Some(session.game.state.player_turn).filter(|_| played > 0 && !session.is_over());
+            let banked = [stored.clocks[0].unwrap_or(start), stored.clocks[1].unwrap_or(start)];
+            ChessClock::resume(time_control, banked, moves, running, now)
+        });
+        session.rated = stored.rated;
+        session.invite = stored.invite.clone();
+        session.tokens = stored.tokens.clone();
+        session.users = stored.users.clone();
+        Ok(session)
+    }
+
+    pub fn to_stored(&self) -> StoredGame {
+        StoredGame {
+            id: self.id.clone(),
+            start_fen: self.start_fen.clone(),
+            moves: self.moves().to_vec(),
+            status: self.status,
+            result: self.result.clone(),
+            draw_offer: self.draw_offer,
+            time_control: self.time_control.clone(),
+            rated: self.rated,
+            invite: self.invite.clone(),
+            tokens: self.tokens.clone(),
+            users: self.users.clone(),
+            clocks: match &self.clock {
+                Some(clock) => [Some(clock.banked(PLAYER::WHITE)), Some(clock.banked(PLAYER::BLACK))],
+                None => [None, None],
+            },
+        }
+    }
+
+    // the game gets a clock for the time control, or loses it for none
+    pub fn set_time_control(&mut self, time_control: Option<TimeControl>) {
+        self.clock = time_control.clone().map(ChessClock::new);
+        self.time_control = time_control;
+    }
+
+    pub fn clock(&self) -> Option<&ChessClock> {
+        self.clock.as_ref()
+    }
+
+    // Ends the game if the side to move is out of time, which loses unless the other side
+    // couldn't mate anyway. True if it did.
+    pub fn flag(&mut self, now: Duration) -> bool {
+        let Some(player) = self.clock.as_ref().and_then(|clock| clock.flagged(now)).filter(|_| !self.is_over()) else {
+            return false;
+        };
+        self.stop_clock(now);
+        self.draw_offer = None;
+        let winner = match player {
+            PLAYER::WHITE => PLAYER::BLACK,
+            PLAYER::BLACK => PLAYER::WHITE,
+        };
+        if self.game.state.has_mating_material(winner) {
+            self.status = GameStatus::Timeout;
+            self.result = Some(if winner == PLAYER::WHITE { "1-0" } else { "0-1" }.to_string());
+        } else {
+            self.status = GameStatus::TimeoutVsInsufficientMaterial;
+            self.result = Some("1/2-1/2".to_string());
+        }
+        true
+    }
+
+    pub fn stop_clock(&mut self, now: Duration) {
+        if let Some(clock) = &mut self.clock {
+            clock.stop(now);
+        }
+    }
+
+    pub fn is_seated(&self, player: PLAYER) -> bool {
+        self.tokens[seat_index(player)].is_some()
+    }
+
+    pub fn seat_of(&self, token: &str) -> Option<PLAYER> {
+        [PLAYER::WHITE, PLAYER::BLACK]
+            .into_iter()
+            .find(|player| self.tokens[seat_index(*player)].as_deref() == Some(token))
+    }
+
+    pub fn user(&self, player: PLAYER) -> Option<&str> {
+        self.users[seat_index(player)].as_deref()
+    }
+
+    // the token that moves for player from now on
+    pub fn take_seat(&mut self, player: PLAYER, user: Option<&str>) -> Result<String, ChessErrors> {
+        if self.is_seated(player) {
+            return Err(ChessErrors::SeatTaken(self.id.clone()));
+        }
+        // nobody plays themselves
+        if user.is_some() && self.users[1 - seat_index(player)].as_deref() == user {
+            return Err(ChessErrors::AlreadySeated(self.id.clone()));
+        }
+        let token = secret_token()?;
+        self.tokens[seat_index(player)] = Some(token.clone());
+        self.users[seat_index(player)] = user.map(str::to_string);
+        Ok(token)
+    }
+
+    // the seat left over, for whoever joins an open game or has the invite to a private one
+    pub fn join(&mut self, invite: Option<&str>, user: Option<&str>) -> Result<(PLAYER, String), ChessErrors> {
+        if self.invite.is_some() && self.invite.as_deref() != invite {
+            return Err(ChessErrors::InvalidInvite(self.id.clone()));
+        }
+        let player = [PLAYER::WHITE, PLAYER::BLACK]
+            .into_iter()
+            .find(|player| !self.is_seated(*player))
+            .ok_or_else(|| ChessErrors::SeatTaken(self.id.clone()))?;
+        Ok((player, self.take_seat(player, user)?))
+    }
+
+    pub fn moves(&self) -> &[String] {
+        &self.game.turn_history
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.status != GameStatus::Active
+    }
+
+    pub fn play(&mut self, chess_move: &str) -> Result<(), ChessErrors> {
+        if self.is_over() {
+            return Err(ChessErrors::GameOver(self.id.clone()));
+        }
+        let player = self.game.state.player_turn;
+        // played on a copy, so a move that fails leaves the stored game as it was
+        let mut game = self.game.clone();
+        game.make_move(chess_move)?;
+        // moving instead of answering declines the opponent's offer
+        if self.draw_offer.is_some_and(|by| by != player) {
+            self.draw_offer = None;
+        }
+        self.game = game;
+        self.keys.push(zobrist::position_key(&self.game.state));
+        self.update_status();
+        Ok(())
+    }
+
+    // a move from a seated player, who has to be the one to move and still have time
+    pub fn play_as(&mut self, player: PLAYER, chess_move: &str, now: Duration) -> Result<(), ChessErrors> {
+        if player != self.game.state.player_turn {
+            return Err(ChessErrors::NotYourTurn(self.id.clone()));
+        }
+        if self.clock.as_ref().is_some_and(|clock| clock.flagged(now).is_some()) {
+            return Err(ChessErrors::GameOver(self.id.clone()));
+        }
+        self.play(chess_move)?;
+        if let Some(clock) = &mut self.clock {
+            clock.press(player, now);
+        }
+        if self.is_over() {
+            self.stop_clock(now);
+        }
+        Ok(())
+    }
+
+    pub fn offer_draw(&mut self, player: PLAYER) -> Result<(), ChessErrors> {
+        if self.is_over() {
+            return Err(ChessErrors::GameOver(self.id.clone()));
+        }
+        self.draw_offer = Some(player);
+        Ok(())
+    }
+
+    // accepting ends the game drawn, declining just withdraws the offer
+    pub fn answer_draw(&mut self, player: PLAYER, accept: bool) -> Result<(), ChessErrors> {
+        if self.is_over() {
+            return Err(ChessErrors::GameOver(self.id.clone()));
+        }
+        match self.draw_offer {
+            Some(by) if by != player => {}
+            _ => return Err(ChessErrors::NoDrawOffer(self.id.clone())),
+        }
+        self.draw_offer = None;
+        if accept {
+            self.status = GameStatus::DrawAgreed;
+            self.result = Some("1/2-1/2".to_string());
+        }
+        Ok(())
+    }
+
+    fn update_status(&mut self) {
+        let player = self.game.state.player_turn;
+        self.status = match self.game.game_end(&self.keys) {
+            None => GameStatus::Active,
+            Some(GameEnd::Checkmate) => GameStatus::Checkmate,
+            Some(GameEnd::Stalemate) => GameStatus::Stalemate,
+            Some(GameEnd::Repetition) => GameStatus::Repetition,
+            Some(GameEnd::FiftyMoves) => GameStatus::FiftyMoves,
+            Some(GameEnd::InsufficientMaterial) => GameStatus::InsufficientMaterial,
+        };
+        self.result = match (self.status, player) {
+            (GameStatus::Active, _) => None,
+            (GameStatus::Checkmate, PLAYER::WHITE) => Some("0-1".to_string()),
+            (GameStatus::Checkmate, PLAYER::BLACK) => Some("1-0".to_string()),
+            _ => Some("1/2-1/2".to_string()),
+        };
+    }
+}
+
+fn seat_index(player: PLAYER) -> usize {
+    match player {
+        PLAYER::WHITE => 0,
+        PLAYER::BLACK => 1,
+    }
+}
+
+// 128 random bits from the operating system, as hex
+pub fn secret_token() -> Result<String, ChessErrors> {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes).map_err(|e| ChessErrors::RandomError(e.to_string()))?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+// Every game the server knows about, by id, kept in the repository as it changes. The
+// games being played stay in memory; finished ones are loaded when asked for.
+pub struct GameStore {
+    games: Mutex<HashMap<String, GameSession>>,
+    repository: Box<dyn GameRepository>,
+    // what the games' clocks run on
+    clock: Arc<dyn Clock>,
+    next_id: AtomicU64,
+}
+
+impl Default for GameStore {
+    fn default() -> Self {
+        GameStore {
+            games: Mutex::new(HashMap::new()),
+            repository: Box::new(InMemoryGameRepository::default()),
+            clock: Arc::new(SystemClock::default()),
+            next_id: AtomicU64::new(0),
+        }
+    }
+}
+
+impl GameStore {
+    // picks up the active games where they were left
+    pub fn open(repository: Box<dyn GameRepository>, clock: Arc<dyn Clock>) -> Result<GameStore, ChessErrors> {
+        let mut games = HashMap::new();
+        for stored in repository.active()? {
+            // a game whose moves no longer replay shouldn't keep the server from starting
+            match GameSession::restore(&stored, clock.now()) {
+                Ok(session) => {
+                    games.insert(stored.id.clone(), session);
+                }
+                Err(e) => eprintln!("game {}: not restored: {}", stored.id, e),
+            }
+        }
+        Ok(GameStore {
+            games: Mutex::new(games),
+            next_id: AtomicU64::new(repository.last_id()?),
+            repository,
+            clock,
+        })
+    }
+
+    // a new game with its creator sitting as player, returned with the creator's token
+    pub fn create(&self, fen: Option<&str>, player: PLAYER, private: bool, user: Option<&str>) -> Result<(GameSession, String), ChessErrors> {
+        let id = (self.next_id.fetch_add(1, Ordering::Relaxed) + 1).to_string();
+        let mut session = GameSession::new(&id, fen)?;
+        if private {
+            session.invite = Some(secret_token()?);
+        }
+        let token = session.take_seat(player, user)?;
+        let mut games = self.games.lock().unwrap();
+        self.repository.save(&session.to_stored())?;
+        games.insert(id, session.clone());
+        Ok((session, token))
+    }
+
+    pub fn get(&self, id: &str) -> Result<GameSession, ChessErrors> {
+        let mut games = self.games.lock().unwrap();
+        Ok(self.find(&mut games, id)?.clone())
+    }
+
+    // Runs f on the game while holding the lock, so two moves can't interleave. What f
+    // changes is only kept once it is stored.
+    pub fn with<T>(&self, id: &str, f: impl FnOnce(&mut GameSession) -> Result<T, ChessErrors>) -> Result<T, ChessErrors> {
+        let mut games = self.games.lock().unwrap();
+        let session = self.find(&mut games, id)?;
+        let mut changed = session.clone();
+        let value = f(&mut changed)?;
+        self.repository.save(&changed.to_stored())?;
+        *session = changed;
+        Ok(value)
+    }
+
+    fn find<'a>(&self, games: &'a mut HashMap<String, GameSession>, id: &str) -> Result<&'a mut GameSession, ChessErrors> {
+        if !games.contains_key(id) {
+            let stored = self.repository.load(id)?.ok_or_else(|| ChessErrors::GameNotFound(id.to_string()))?;
+            games.insert(id.to_string(), GameSession::restore(&stored, self.clock.now())?);
+        }
+        Ok(games.get_mut(id).unwrap())
+    }
+
+    // the games whose side to move has run out of time but hasn't been flagged yet
+    pub fn out_of_time(&self) -> Vec<String> {
+        let now = self.clock.now();
+        let games = self.games.lock().unwrap();
+        games
+            .values()
+            .filter(|session| !session.is_over() && session.clock().is_some_and(|clock| clock.flagged(now).is_some()))
+            .map(|session| session.id.clone())
+            .collect()
+    }
+
+    // flags the game if its side to move is out of time, returning it if that ended it
+    pub fn flag(&self, id: &str) -> Result<Option<GameSession>, ChessErrors> {
+        let now = self.clock.now();
+        let mut games = self.games.lock().unwrap();
+        let session = self.find(&mut games, id)?;
+        let mut changed = session.clone();
+        if !changed.flag(now) {
+            return Ok(None);
+        }
+        self.repository.save(&changed.to_stored())?;
+        *session = changed;
+        Ok(Some(session.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time_manager::ManualClock;
+
+    #[test]
+    fn games_that_no_longer_replay_are_skipped() {
+        let repository = InMemoryGameRepository::default();
+        let mut playable = GameSession::new("1", None).unwrap();
+        playable.play("e2-e4").unwrap();
+        repository.save(&playable.to_stored()).unwrap();
+        let mut broken = GameSession::new("2", None).unwrap().to_stored();
+        broken.moves = vec!["e2-e4".to_string(), "e7-e4".to_string()];
+        repository.save(&broken).unwrap();
+
+        let store = GameStore::open(Box::new(repository), Arc::new(ManualClock::new(Duration::ZERO))).unwrap();
+        assert_eq!(store.get("1").unwrap().moves(), ["e2-e4"]);
+        assert!(store.get("2").is_err());
+        // its id isn't given out again
+        let (created, _) = store.create(None, PLAYER::WHITE, false, None).unwrap();
+        assert_eq!(created.id, "3");
+    }
+
+    #[test]
+    fn the_rules_end_the_game() {
+        let mut mated = GameSession::new("1", None).unwrap();
+        for chess_move in ["f2-f3", "e7-e5", "g2-g4", "d8-h4"] {
+            mated.play(chess_move).unwrap();
+        }
+        assert_eq!((mated.status, mated.result.as_deref()), (GameStatus::Checkmate, Some("0-1")));
+
+        let mut repeated = GameSession::new("2", None).unwrap();
+        for chess_move in ["g1-f3", "g8-f6", "f3-g1", "f6-g8"].repeat(2) {
+            assert_eq!(repeated.status, GameStatus::Active);
+            repeated.play(&chess_move).unwrap();
+        }
+        assert_eq!((repeated.status, repeated.result.as_deref()), (GameStatus::Repetition, Some("1/2-1/2")));
+
+        let mut slow = GameSession::new("3", Some("4k3/8/8/8/8/8/4P3/R3K3 w - - 99 80")).unwrap();
+        slow.play("a1-a2").unwrap();
+        assert_eq!(slow.status, GameStatus::FiftyMoves);
+    }
+}
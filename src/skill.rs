@@ -0,0 +1,99 @@
+use crate::engine::SearchLimits;
+use crate::prng::Prng;
+
+pub const MAX_SKILL_LEVEL: u32 = 20;
+// the UCI_Elo range the skill levels are spread over
+pub const MIN_ELO: u32 = 800;
+pub const MAX_ELO: u32 = 2400;
+
+// how many root moves a weakened engine looks at to choose from
+const WEAK_MULTIPV: usize = 4;
+
+// Playing strength from 0 to 20. Anything below 20 searches less deep, sees the board
+// through some noise and now and then plays one of its lesser candidate moves.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Skill {
+    level: u32,
+}
+
+impl Default for Skill {
+    fn default() -> Self {
+        Skill { level: MAX_SKILL_LEVEL }
+    }
+}
+
+impl Skill {
+    pub fn new(level: u32) -> Self {
+        Skill { level: level.min(MAX_SKILL_LEVEL) }
+    }
+
+    pub fn from_elo(elo: u32) -> Self {
+        let elo = elo.clamp(MIN_ELO, MAX_ELO);
+        Skill::new((elo - MIN_ELO) * MAX_SKILL_LEVEL / (MAX_ELO - MIN_ELO))
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    pub fn is_full_strength(&self) -> bool {
+        self.level >= MAX_SKILL_LEVEL
+    }
+
+    pub fn depth_limit(&self) -> Option<u32> {
+        if self.is_full_strength() {
+            return None;
+        }
+        Some(1 + self.level / 3)
+    }
+
+    pub fn node_limit(&self) -> Option<u64> {
+        if self.is_full_strength() {
+            return None;
+        }
+        Some(200 << (self.level / 2))
+    }
+
+    // how far, in centipawns, the evaluation may be off either way
+    pub fn eval_noise(&self) -> i32 {
+        (MAX_SKILL_LEVEL - self.level) as i32 * 10
+    }
+
+    pub fn multipv(&self) -> usize {
+        if self.is_full_strength() {
+            1
+        } else {
+            WEAK_MULTIPV
+        }
+    }
+
+    pub fn restrict(&self, limits: &mut SearchLimits) {
+        if let Some(depth) = self.depth_limit() {
+            limits.depth = Some(limits.depth.map_or(depth, |limit| limit.min(depth)));
+        }
+        if let Some(nodes) = self.node_limit() {
+            limits.nodes = Some(limits.nodes.map_or(nodes, |limit| limit.min(nodes)));
+        }
+        limits.multipv = limits.multipv.max(self.multipv());
+    }
+
+    // Which of the candidate lines to play, given their scores best first. Every line gets
+    // a random push that grows with how weak the level is and how much worse the line is,
+    // so low levels drop material now and then while high levels mostly play the best move.
+    pub fn pick(&self, scores: &[i32], prng: &mut Prng) -> usize {
+        if scores.len() < 2 || self.is_full_strength() {
+            return 0;
+        }
+        let weakness = 120 - 2 * self.level as i32;
+        let top = scores[0];
+        let delta = (top - scores[scores.len() - 1]).min(100);
+        let mut best = (0, i32::MIN);
+        for (index, score) in scores.iter().enumerate() {
+            let push = (weakness * (top - score) + delta * prng.below(weakness as u64) as i32) / 128;
+            if score + push > best.1 {
+                best = (index, score + push);
+            }
+        }
+        best.0
+    }
+}
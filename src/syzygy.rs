@@ -0,0 +1,226 @@
+use std::path::Path;
+
+use shakmaty::fen::Fen;
+use shakmaty::{CastlingMode, Chess, Position};
+use shakmaty_syzygy::{AmbiguousWdl, Material, Tablebase as SyzygyTables};
+
+use crate::chess_errors::ChessErrors;
+use crate::chess_notation_utilities;
+use crate::game::Game;
+use crate::visual::GameState;
+
+// win/draw/loss for the side to move. The cursed and blessed results are the ones
+// the 50 move rule turns into a draw. The maybe results are for when the moves already
+// played since the last capture or pawn move leave it too close to tell, as the tables
+// round some distances.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss,
+    MaybeLoss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    MaybeWin,
+    Win,
+}
+
+impl From<AmbiguousWdl> for Wdl {
+    fn from(wdl: AmbiguousWdl) -> Self {
+        match wdl {
+            AmbiguousWdl::Loss => Wdl::Loss,
+            AmbiguousWdl::MaybeLoss => Wdl::MaybeLoss,
+            AmbiguousWdl::BlessedLoss => Wdl::BlessedLoss,
+            AmbiguousWdl::Draw => Wdl::Draw,
+            AmbiguousWdl::CursedWin => Wdl::CursedWin,
+            AmbiguousWdl::MaybeWin => Wdl::MaybeWin,
+            AmbiguousWdl::Win => Wdl::Win,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Probe {
+    pub wdl: Wdl,
+    // plies to the next capture or pawn move on the best line, negative when losing
+    pub dtz: i32,
+}
+
+pub struct Tablebase {
+    tables: SyzygyTables<Chess>,
+}
+
+pub fn piece_count(state: &GameState) -> usize {
+    state.state.iter().filter(|piece| piece.is_some()).count()
+}
+
+fn to_position(state: &GameState) -> Option<Chess> {
+    let fen: Fen = state.to_fen().parse().ok()?;
+    fen.into_position(CastlingMode::Standard).ok()
+}
+
+// the file a position is found in, e.g. "KRPvKR" for KRPvKR.rtbw and KRPvKR.rtbz
+pub fn table_name(state: &GameState) -> Option<String> {
+    to_position(state).map(|position| Material::from_board(position.board()).to_string())
+}
+
+impl Tablebase {
+    // every .rtbw/.rtbz file in the directory. Several directories can be given
+    // separated the way the platform separates PATH entries.
+    pub fn open(directories: &str) -> Result<Tablebase, ChessErrors> {
+        let mut tables = SyzygyTables::new();
+        for directory in std::env::split_paths(directories) {
+            tables
+                .add_directory(&directory)
+                .map_err(|e| ChessErrors::TablebaseError(format!("{}: {}", Path::new(&directory).display(), e)))?;
+        }
+        Ok(Tablebase { tables })
+    }
+
+    // the most pieces on the board any of the loaded tables covers
+    pub fn max_pieces(&self) -> usize {
+        self.tables.max_pieces()
+    }
+
+    pub fn covers(&self, state: &GameState) -> bool {
+        piece_count(state) <= self.max_pieces() && state.castling_rights().is_empty()
+    }
+
+    // needs the DTZ tables as well as the WDL ones, to count the halfmove clock in
+    pub fn probe_wdl(&self, state: &GameState) -> Option<Wdl> {
+        if !self.covers(state) {
+            return None;
+        }
+        let position = to_position(state)?;
+        self.tables.probe_wdl(&position).ok().map(Wdl::from)
+    }
+
+    pub fn probe_dtz(&self, state: &GameState) -> Option<i32> {
+        if !self.covers(state) {
+            return None;
+        }
+        let position = to_position(state)?;
+        self.tables.probe_dtz(&position).ok().map(|dtz| dtz.ignore_rounding().0)
+    }
+
+    pub fn probe(&self, state: &GameState) -> Option<Probe> {
+        Some(Probe {
+            wdl: self.probe_wdl(state)?,
+            dtz: self.probe_dtz(state)?,
+        })
+    }
+
+    // the move that keeps the tablebase result, with the probe of the position it was picked in
+    pub fn best_move(&self, game: &Game) -> Option<(String, Probe)> {
+        if !self.covers(&game.state) {
+            return None;
+        }
+        let probe = self.probe(&game.state)?;
+        let position = to_position(&game.state)?;
+        let (best_move, _) = self.tables.best_move(&position).ok()??;
+        let uci_move = best_move.to_uci(CastlingMode::Standard).to_string();
+        let chess_move = chess_notation_utilities::uci_to_move_notation(&uci_move).ok()?;
+        Some((chess_move, probe))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::engine::{Engine, Score, SearchLimits, TB_WIN_SCORE};
+
+    fn state(fen: &str) -> GameState {
+        GameState::from_fen(fen).unwrap()
+    }
+
+    #[test]
+    fn counts_pieces_and_names_tables() {
+        let start = state("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert_eq!(piece_count(&start), 32);
+        let endgame = state("8/8/8/4k3/8/2p5/8/R5K1 w - - 0 1");
+        assert_eq!(piece_count(&endgame), 4);
+        // white's pieces first
+        assert_eq!(table_name(&endgame).as_deref(), Some("KRvKP"));
+        assert_eq!(table_name(&state("8/8/8/4k3/8/2P5/r7/6K1 b - - 0 1")).as_deref(), Some("KPvKR"));
+    }
+
+    #[test]
+    fn no_tables_cover_nothing() {
+        let tablebase = Tablebase::open(std::env::temp_dir().to_str().unwrap()).unwrap();
+        let endgame = state("8/8/8/4k3/8/8/8/R5K1 w - - 0 1");
+        assert!(!tablebase.covers(&endgame));
+        assert_eq!(tablebase.probe_wdl(&endgame), None);
+    }
+
+    // KRvK, KQvK, KPvK and KRvKP, in the format of the published tables and checked against
+    // every position they hold. KPvK is there for the pawn taking the rook in KRvKP.
+    fn fixtures() -> Tablebase {
+        Tablebase::open(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/syzygy")).unwrap()
+    }
+
+    fn probe(wdl: Wdl, dtz: i32) -> Option<Probe> {
+        Some(Probe { wdl, dtz })
+    }
+
+    #[test]
+    fn probes_the_fixture_tables() {
+        let tablebase = fixtures();
+        assert_eq!(tablebase.max_pieces(), 4);
+        let winning = state("8/8/8/4k3/8/8/8/R5K1 w - - 0 1");
+        assert!(tablebase.covers(&winning));
+        assert!(!tablebase.covers(&state("r3k3/8/8/8/8/8/8/6K1 b q - 0 1")));
+        assert_eq!(tablebase.probe(&winning), probe(Wdl::Win, 27));
+        assert_eq!(tablebase.probe(&state("8/8/8/4k3/8/8/8/R5K1 b - - 0 1")), probe(Wdl::Loss, -30));
+        assert_eq!(tablebase.probe(&state("8/8/8/4k3/8/8/8/1Q4K1 w - - 0 1")), probe(Wdl::Win, 17));
+        // mate in one, and mated
+        assert_eq!(tablebase.probe(&state("6k1/8/6K1/8/8/8/8/R7 w - - 0 1")), probe(Wdl::Win, 1));
+        assert_eq!(tablebase.probe(&state("R5k1/8/6K1/8/8/8/8/8 b - - 0 1")), probe(Wdl::Loss, -1));
+        // the king takes the rook
+        assert_eq!(tablebase.probe(&state("8/8/8/8/8/8/1k6/R6K b - - 0 1")), probe(Wdl::Draw, 0));
+        // the same table whichever colour has the rook
+        assert_eq!(tablebase.probe(&state("8/8/8/4k3/8/2p5/8/R5K1 w - - 0 1")), probe(Wdl::Win, 7));
+        assert_eq!(tablebase.probe(&state("r5k1/8/2P5/8/4K3/8/8/8 b - - 0 1")), probe(Wdl::Win, 7));
+        assert_eq!(tablebase.probe(&state("8/8/8/4k3/8/2p5/8/R5K1 b - - 0 1")), probe(Wdl::Loss, -14));
+        // ten plies left before the 50 move rule is not enough to mate from here
+        let late = tablebase.probe_wdl(&state("8/8/8/4k3/8/8/8/R5K1 w - - 90 80")).unwrap();
+        assert!(matches!(late, Wdl::CursedWin | Wdl::MaybeWin), "{:?}", late);
+    }
+
+    #[test]
+    fn the_best_move_keeps_the_result() {
+        let tablebase = fixtures();
+        let positions = [
+            ("8/8/8/4k3/8/8/8/R5K1 w - - 0 1", Wdl::Loss),
+            ("8/8/8/4k3/8/8/8/R5K1 b - - 0 1", Wdl::Win),
+            ("8/8/8/4k3/8/2p5/8/R5K1 w - - 0 1", Wdl::Loss),
+            ("8/8/8/4k3/8/2p5/8/R5K1 b - - 0 1", Wdl::Win),
+            ("8/8/8/8/8/8/1k6/R6K b - - 0 1", Wdl::Draw),
+        ];
+        for (fen, after) in positions {
+            let mut game = Game::game_from_fen(fen).unwrap();
+            let (best_move, best_probe) = tablebase.best_move(&game).unwrap();
+            assert_eq!(Some(best_probe), tablebase.probe(&game.state));
+            game.make_move(&best_move).unwrap();
+            assert_eq!(tablebase.probe_wdl(&game.state), Some(after), "{} after {}", fen, best_move);
+        }
+        let (mate, _) = tablebase.best_move(&Game::game_from_fen("6k1/8/6K1/8/8/8/8/R7 w - - 0 1").unwrap()).unwrap();
+        assert_eq!(mate, "a1-a8");
+    }
+
+    #[test]
+    fn the_engine_plays_the_tablebase_move_at_the_root() {
+        let tablebase = Arc::new(fixtures());
+        let game = Game::game_from_fen("8/8/8/4k3/8/2p5/8/R5K1 w - - 0 1").unwrap();
+        let (best_move, best_probe) = tablebase.best_move(&game).unwrap();
+        let mut engine = Engine::new(1);
+        engine.set_tablebase(Some(tablebase));
+        let limits = SearchLimits { depth: Some(6), ..SearchLimits::default() };
+        let result = engine.search(&game, &limits, &AtomicBool::new(false), |_| {});
+        assert_eq!(result.best_move, Some(best_move));
+        assert_eq!(result.score, Score::Centipawns(TB_WIN_SCORE - best_probe.dtz));
+        // straight from the tables, nothing was searched
+        assert_eq!((result.depth, result.nodes), (1, 0));
+    }
+}
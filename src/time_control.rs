@@ -0,0 +1,164 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chess_errors::ChessErrors;
+
+const MAX_STAGES: usize = 5;
+
+// what the seconds after a stage's minutes do on each move
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Bonus {
+    // "+3": added after every move (Fischer), 0 for sudden death
+    Increment,
+    // "b3": the time the move took is given back, up to this much
+    Bronstein,
+    // "d3": the clock waits this long before it starts running down
+    Delay,
+}
+
+// minutes for a number of moves, or for the rest of the game when moves is None
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Stage {
+    pub moves: Option<u32>,
+    pub time: Duration,
+    pub bonus: Bonus,
+    pub seconds: Duration,
+}
+
+// One or more stages, written like "5+3", "15d5", or "40/90+30,30+30" for 90 minutes for
+// the first 40 moves, then 30 more for the rest. A last stage with a move count starts over
+// whenever it is used up, so "40/120" gives 120 minutes every 40 moves.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeControl {
+    pub stages: Vec<Stage>,
+}
+
+impl TimeControl {
+    // the stage a side's nth move (from 1) is played in
+    pub fn stage_for(&self, move_number: u32) -> &Stage {
+        let mut first = 1;
+        for stage in &self.stages {
+            match stage.moves {
+                Some(moves) if move_number >= first + moves => first += moves,
+                _ => return stage,
+            }
+        }
+        // past every stage, so the last one is starting over
+        self.stages.last().unwrap()
+    }
+
+    // the time a side gets once it has played this many moves, if a stage ends there
+    pub fn added_after(&self, moves_played: u32) -> Option<Duration> {
+        let mut played = 0;
+        for (index, stage) in self.stages.iter().enumerate() {
+            let moves = stage.moves?;
+            played += moves;
+            if moves_played == played {
+                return Some(self.stages.get(index + 1).unwrap_or(stage).time);
+            }
+            if moves_played < played {
+                return None;
+            }
+        }
+        // the last stage repeating
+        let last = self.stages.last()?;
+        let moves = last.moves?;
+        (moves_played - played).is_multiple_of(moves).then_some(last.time)
+    }
+
+    // all the time a side can have used by its nth move, had it used none of its bonus;
+    // how long a game of that many moves lasts, roughly
+    pub fn estimated_time(&self, moves: u32) -> Duration {
+        let mut total = self.stages[0].time;
+        for played in 1..=moves {
+            total += self.stage_for(played).seconds;
+            if played < moves {
+                total += self.added_after(played).unwrap_or_default();
+            }
+        }
+        total
+    }
+}
+
+fn parse_stage(text: &str) -> Option<Stage> {
+    let (moves, rest) = match text.split_once('/') {
+        Some((moves, rest)) => (Some(moves.trim().parse::<u32>().ok().filter(|moves| (1..=200).contains(moves))?), rest),
+        None => (None, text),
+    };
+    let split = rest.find(['+', 'b', 'd']).unwrap_or(rest.len());
+    let (minutes, bonus_text) = rest.split_at(split);
+    let minutes: f64 = minutes.trim().parse().ok()?;
+    let (bonus, seconds) = match bonus_text.chars().next() {
+        None => (Bonus::Increment, 0),
+        Some(symbol) => {
+            let bonus = match symbol {
+                '+' => Bonus::Increment,
+                'b' => Bonus::Bronstein,
+                _ => Bonus::Delay,
+            };
+            (bonus, bonus_text[1..].trim().parse::<u64>().ok()?)
+        }
+    };
+    if !(minutes > 0.0 && minutes <= 24.0 * 60.0) || seconds > 3600 {
+        return None;
+    }
+    Some(Stage {
+        moves,
+        time: Duration::from_secs_f64(minutes * 60.0),
+        bonus,
+        seconds: Duration::from_secs(seconds),
+    })
+}
+
+impl FromStr for TimeControl {
+    type Err = ChessErrors;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || ChessErrors::InvalidTimeControl(text.to_string());
+        let stages: Vec<Stage> = text.split(',').map(|stage| parse_stage(stage.trim())).collect::<Option<_>>().ok_or_else(invalid)?;
+        // only the last stage can run to the end of the game
+        if stages.len() > MAX_STAGES || stages[..stages.len() - 1].iter().any(|stage| stage.moves.is_none()) {
+            return Err(invalid());
+        }
+        Ok(TimeControl { stages })
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(moves) = self.moves {
+            write!(f, "{}/", moves)?;
+        }
+        let symbol = match self.bonus {
+            Bonus::Increment => '+',
+            Bonus::Bronstein => 'b',
+            Bonus::Delay => 'd',
+        };
+        write!(f, "{}{}{}", self.time.as_secs_f64() / 60.0, symbol, self.seconds.as_secs())
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stages: Vec<String> = self.stages.iter().map(Stage::to_string).collect();
+        write!(f, "{}", stages.join(","))
+    }
+}
+
+impl TryFrom<String> for TimeControl {
+    type Error = ChessErrors;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl From<TimeControl> for String {
+    fn from(time_control: TimeControl) -> Self {
+        time_control.to_string()
+    }
+}
@@ -0,0 +1,283 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::engine::SearchLimits;
+use crate::visual::PLAYER;
+
+// kept back from every allocation for the GUI and the pipe
+const MOVE_OVERHEAD: Duration = Duration::from_millis(50);
+const MIN_THINK: Duration = Duration::from_millis(10);
+// sudden death is played as if this many moves were left
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+// the soft limit never stretches past this many times itself
+const MAX_EXTENSION: f64 = 3.0;
+
+// a monotonic time source. The engine reads the time only through this,
+// so a test can drive the clock by hand.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Duration;
+}
+
+pub struct SystemClock {
+    origin: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock { origin: Instant::now() }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+}
+
+// only moves when told to
+#[derive(Default)]
+pub struct ManualClock {
+    millis: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(start: Duration) -> Self {
+        ManualClock { millis: AtomicU64::new(start.as_millis() as u64) }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.millis.fetch_add(by.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, to: Duration) {
+        self.millis.store(to.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_millis(self.millis.load(Ordering::Relaxed))
+    }
+}
+
+// How long one move may take. The soft limit decides whether another iteration is
+// worth starting and grows while the search is unsure of itself; the hard limit is
+// where the search gets stopped no matter what.
+pub struct TimeManager {
+    clock: Arc<dyn Clock>,
+    start: Duration,
+    soft: Option<Duration>,
+    hard: Option<Duration>,
+    // only clock based budgets get stretched, a fixed movetime is what was asked for
+    extendable: bool,
+    instability: f64,
+    score_drop: f64,
+    last_best_move: Option<String>,
+    last_score: Option<i32>,
+}
+
+impl TimeManager {
+    pub fn new(clock: Arc<dyn Clock>, limits: &SearchLimits, player: PLAYER) -> Self {
+        let start = clock.now();
+        let mut manager = TimeManager {
+            clock,
+            start,
+            soft: None,
+            hard: None,
+            extendable: false,
+            instability: 0.0,
+            score_drop: 1.0,
+            last_best_move: None,
+            last_score: None,
+        };
+        if limits.infinite {
+            return manager;
+        }
+        let (time_left, increment) = match player {
+            PLAYER::WHITE => (limits.wtime, limits.winc),
+            PLAYER::BLACK => (limits.btime, limits.binc),
+        };
+        if let Some(movetime) = limits.movetime {
+            // the next depth takes several times as long as the last, don't start what can't finish
+            manager.soft = Some(movetime / 2);
+            manager.hard = Some(movetime);
+        } else if let Some(time_left) = time_left {
+            let (soft, hard) = allocate(time_left, increment, limits.movestogo);
+            manager.soft = Some(soft);
+            manager.hard = Some(hard);
+            manager.extendable = true;
+        }
+        manager
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.clock.now().saturating_sub(self.start)
+    }
+
+    pub fn soft_limit(&self) -> Option<Duration> {
+        let soft = self.soft?;
+        if !self.extendable {
+            return Some(soft);
+        }
+        let factor = ((1.0 + self.instability) * self.score_drop).min(MAX_EXTENSION);
+        let extended = soft.mul_f64(factor);
+        Some(match self.hard {
+            Some(hard) => extended.min(hard),
+            None => extended,
+        })
+    }
+
+    pub fn hard_limit(&self) -> Option<Duration> {
+        self.hard
+    }
+
+    // the clock reading at which the search must stop
+    pub fn deadline(&self) -> Option<Duration> {
+        self.hard.map(|hard| self.start + hard)
+    }
+
+    // called after every finished iteration with its best move and score
+    pub fn on_iteration(&mut self, best_move: &str, score: i32) {
+        self.instability *= 0.5;
+        if let Some(last_best_move) = &self.last_best_move {
+            if last_best_move != best_move {
+                self.instability += 1.0;
+            }
+        }
+        self.score_drop = match self.last_score {
+            Some(last_score) if last_score - score > 80 => 2.0,
+            Some(last_score) if last_score - score > 30 => 1.5,
+            _ => 1.0,
+        };
+        self.last_best_move = Some(best_move.to_string());
+        self.last_score = Some(score);
+    }
+
+    pub fn should_start_iteration(&self) -> bool {
+        match self.soft_limit() {
+            Some(soft) => self.elapsed() < soft,
+            None => true,
+        }
+    }
+}
+
+// the soft and hard limits for one move out of time_left
+pub fn allocate(time_left: Duration, increment: Duration, moves_to_go: Option<u32>) -> (Duration, Duration) {
+    let moves_to_go = moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+    let usable = time_left.saturating_sub(MOVE_OVERHEAD);
+    // with one move left before the next time control most of it can go
+    let max_usable = if moves_to_go == 1 { usable * 4 / 5 } else { usable / 3 };
+    let base = usable / moves_to_go + increment * 3 / 4;
+    let soft = base.min(max_usable).max(MIN_THINK);
+    let hard = (soft * 3).min(max_usable).max(soft);
+    (soft, hard)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use super::*;
+    use crate::engine::Engine;
+    use crate::game::Game;
+
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn manager(clock: &Arc<ManualClock>, limits: SearchLimits) -> TimeManager {
+        TimeManager::new(clock.clone(), &limits, PLAYER::WHITE)
+    }
+
+    #[test]
+    fn moves_to_go_splits_the_time() {
+        // 50ms overhead off the top, then a tenth each with at most a third for one move
+        assert_eq!(allocate(millis(10_050), Duration::ZERO, Some(10)), (millis(1000), millis(3000)));
+        // the last move before the time control can use most of what is left
+        assert_eq!(allocate(millis(10_050), Duration::ZERO, Some(1)), (millis(8000), millis(8000)));
+    }
+
+    #[test]
+    fn increment_adds_to_the_allocation() {
+        // sudden death as 30 moves to go, plus three quarters of the increment
+        assert_eq!(allocate(millis(60_050), millis(2000), None), (millis(3500), millis(10_500)));
+        // nearly flagging still thinks a little
+        assert_eq!(allocate(millis(40), Duration::ZERO, None), (MIN_THINK, MIN_THINK));
+    }
+
+    #[test]
+    fn movetime_is_not_stretched() {
+        let clock = Arc::new(ManualClock::new(millis(5000)));
+        let mut time = manager(&clock, SearchLimits { movetime: Some(millis(1000)), ..SearchLimits::default() });
+        assert_eq!(time.soft_limit(), Some(millis(500)));
+        assert_eq!(time.hard_limit(), Some(millis(1000)));
+        assert_eq!(time.deadline(), Some(millis(6000)));
+        time.on_iteration("e2-e4", 20);
+        time.on_iteration("d2-d4", -200);
+        assert_eq!(time.soft_limit(), Some(millis(500)));
+        clock.advance(millis(499));
+        assert!(time.should_start_iteration());
+        clock.advance(millis(1));
+        assert!(!time.should_start_iteration());
+    }
+
+    #[test]
+    fn unstable_best_moves_and_falling_scores_extend_the_soft_limit() {
+        let clock = Arc::new(ManualClock::new(Duration::ZERO));
+        let mut time = manager(&clock, SearchLimits { wtime: Some(millis(60_050)), ..SearchLimits::default() });
+        assert_eq!((time.soft_limit(), time.hard_limit()), (Some(millis(2000)), Some(millis(6000))));
+        time.on_iteration("e2-e4", 20);
+        assert_eq!(time.soft_limit(), Some(millis(2000)));
+        // the best move changed
+        time.on_iteration("d2-d4", 20);
+        assert_eq!(time.soft_limit(), Some(millis(4000)));
+        // and the score fell a long way, which would go past the hard limit
+        time.on_iteration("d2-d4", -80);
+        assert_eq!(time.soft_limit(), Some(millis(6000)));
+        // a smaller drop, with the earlier change mostly forgotten
+        time.on_iteration("d2-d4", -120);
+        assert_eq!(time.soft_limit(), Some(millis(3750)));
+        time.on_iteration("d2-d4", -120);
+        assert_eq!(time.soft_limit(), Some(millis(2250)));
+        clock.set(millis(2249));
+        assert!(time.should_start_iteration());
+        clock.set(millis(2250));
+        assert!(!time.should_start_iteration());
+    }
+
+    #[test]
+    fn infinite_has_no_limits() {
+        let clock = Arc::new(ManualClock::new(Duration::ZERO));
+        let time = manager(&clock, SearchLimits { infinite: true, wtime: Some(millis(1000)), ..SearchLimits::default() });
+        assert_eq!((time.soft_limit(), time.deadline()), (None, None));
+        clock.advance(millis(1_000_000));
+        assert!(time.should_start_iteration());
+    }
+
+    #[test]
+    fn the_hard_deadline_raises_the_stop_flag() {
+        let clock = Arc::new(ManualClock::new(Duration::ZERO));
+        let mut engine = Engine::new(1);
+        engine.set_clock(clock.clone());
+        let game = Game::game_from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        let limits = SearchLimits { movetime: Some(millis(1000)), multipv: 2, ..SearchLimits::default() };
+
+        // within the time, the search finishes on its depth and leaves the flag alone
+        let stop = AtomicBool::new(false);
+        let result = engine.search(&game, &SearchLimits { depth: Some(2), ..limits.clone() }, &stop, |_| {});
+        assert_eq!(result.depth, 2);
+        assert!(!stop.load(Ordering::Relaxed));
+
+        // the time runs out between the two lines of depth 5, in the middle of the second
+        let stop = AtomicBool::new(false);
+        let result = engine.search(&game, &limits, &stop, |info| {
+            if info.depth == 5 && info.multipv == 1 {
+                clock.advance(millis(1000));
+            }
+        });
+        assert!(stop.load(Ordering::Relaxed));
+        assert_eq!(result.depth, 4);
+        assert!(result.best_move.is_some());
+    }
+}
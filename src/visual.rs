@@ -3,8 +3,9 @@
 //ai
 //https://github.com/werner-duvaud/muzero-general
 
-use std::{cell::RefCell, fmt};
-use std::rc::Rc;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 
@@ -12,19 +13,19 @@ use crate::chess_notation_utilities;
 use crate::chess_errors;
 
 pub const WHITE_PAWN: char = '\u{2659}';
-const WHITE_ROOK: char = '\u{2656}';
-const WHITE_KNIGHT: char = '\u{2658}';
-const WHITE_BISHOP: char = '\u{2657}';
+pub const WHITE_ROOK: char = '\u{2656}';
+pub const WHITE_KNIGHT: char = '\u{2658}';
+pub const WHITE_BISHOP: char = '\u{2657}';
 
 pub const BLACK_PAWN: char = '\u{265F}';
-const BLACK_ROOK: char = '\u{265C}';
-const BLACK_KNIGHT: char = '\u{265E}';
-const BLACK_BISHOP: char = '\u{265D}';
-const BLACK_QUEEN: char = '\u{265B}';
-const BLACK_KING: char = '\u{265A}';
+pub const BLACK_ROOK: char = '\u{265C}';
+pub const BLACK_KNIGHT: char = '\u{265E}';
+pub const BLACK_BISHOP: char = '\u{265D}';
+pub const BLACK_QUEEN: char = '\u{265B}';
+pub const BLACK_KING: char = '\u{265A}';
 
-const WHITE_QUEEN: char = '\u{2655}';
-const WHITE_KING: char = '\u{2654}';
+pub const WHITE_QUEEN: char = '\u{2655}';
+pub const WHITE_KING: char = '\u{2654}';
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum PLAYER {
@@ -32,7 +33,7 @@ pub enum PLAYER {
     BLACK,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MoveType {
     Enpassant(usize),
     Castling,
@@ -40,11 +41,11 @@ pub enum MoveType {
     Promotion(char)
 }
 
-pub trait GamePiece : std::fmt::Debug {
+pub trait GamePiece : std::fmt::Debug + Send + Sync {
     fn get_unicode_val(&self) -> char;
-    fn move_horizontal(&self, to_spot: &str, state: &GameState, delta_x: i8, promotion: Option<&str>) -> Result<String, chess_errors::ChessErrors>;
+    fn move_horizontal(&self, to_spot: &str, state: &GameState, delta_x: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>;
     fn move_vertical(&self, to_spot: &str, state: &GameState, delta_y: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>;
-    fn move_diagonal(&self, to_spot: &str, state: &GameState, delta_y: i8, promotion: Option<&str>) -> Result<String, chess_errors::ChessErrors>;
+    fn move_diagonal(&self, to_spot: &str, state: &GameState, delta_y: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>;
     fn move_knight(&self, to_spot: &str, state: &GameState, promotion: Option<&str>) -> Result<String, chess_errors::ChessErrors>;
     fn get_player(&self) -> PLAYER;
     fn toggle_moved(&self);
@@ -55,7 +56,7 @@ pub trait GamePiece : std::fmt::Debug {
 pub struct Pawn {
     unicode_val: char,
     player: PLAYER,
-    moved: RefCell<bool> ,
+    moved: AtomicBool ,
 }
 impl fmt::Display for Pawn {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -123,10 +124,10 @@ impl GamePiece for Pawn {
     }
 
     fn get_moved(&self) -> bool {
-        *self.moved.borrow_mut() == true
+        self.moved.load(Ordering::Relaxed)
     }
     fn toggle_moved(&self){
-        self.moved.replace(true);
+        self.moved.store(true, Ordering::Relaxed);
     }
     fn get_unicode_val(&self) -> char {
         self.unicode_val
@@ -138,7 +139,7 @@ impl GamePiece for Pawn {
         let msg = format!("{}",to_spot);
         return Err(chess_errors::ChessErrors::InvalidMove(msg));
     }
-    fn  move_horizontal(&self, to_spot: &str, _state: &GameState, _delta_x: i8, promotion: Option<&str>) -> Result<String, chess_errors::ChessErrors>{
+    fn  move_horizontal(&self, to_spot: &str, _state: &GameState, _delta_x: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>{
         let msg = format!("{}",to_spot);
         return Err(chess_errors::ChessErrors::InvalidMove(msg));
     }
@@ -174,62 +175,14 @@ impl GamePiece for Pawn {
             return Err(chess_errors::ChessErrors::InvalidMove(msg));
         }
         //check for promotion
-        if let Ok(row) =chess_notation_utilities::convert_row(to_spot){
-            if self.get_player() == PLAYER::BLACK {
-                if row == 7 {
-                    match promotion_opt {
-                        None => {
-                            return Ok((to_spot.to_string(),MoveType::Promotion(BLACK_QUEEN)))
-                        },
-                        Some(promotion) => {
-                            match promotion {
-                                "r" => {
-                                    return Ok((to_spot.to_string(),MoveType::Promotion(BLACK_ROOK)));
-                                },
-                                "b" => {
-                                    return Ok((to_spot.to_string(),MoveType::Promotion(BLACK_BISHOP)));
-                                },
-                                "k" => {
-                                    return Ok((to_spot.to_string(),MoveType::Promotion(BLACK_KNIGHT)));
-                                },
-                                _ => {
-                                    return Ok((to_spot.to_string(),MoveType::Promotion(BLACK_QUEEN)));
-                                },
-                            }
-                        }
-                    }
-                }
-            } else {
-                if row == 0 {
-                    match promotion_opt {
-                        None => {
-                            return Ok((to_spot.to_string(),MoveType::Promotion(WHITE_QUEEN)))
-                        },
-                        Some(promotion) => {
-                            match promotion {
-                                "r" => {
-                                    return Ok((to_spot.to_string(),MoveType::Promotion(WHITE_ROOK)));
-                                },
-                                "b" => {
-                                    return Ok((to_spot.to_string(),MoveType::Promotion(WHITE_BISHOP)));
-                                },
-                                "k" => {
-                                    return Ok((to_spot.to_string(),MoveType::Promotion(WHITE_KNIGHT)));
-                                },
-                                _ => {
-                                    return Ok((to_spot.to_string(),MoveType::Promotion(WHITE_QUEEN)));
-                                },
-                            }
-                            
-                        }
-                    }
-                }
-            }   
-        }
-       
-        Ok((to_spot.to_string(),MoveType::Regular))
+        Ok((to_spot.to_string(),self.promotion_move_type(to_spot, promotion_opt)))
     }
-    fn  move_diagonal(&self, to_spot: &str, state: &GameState, delta_y: i8, promotion: Option<&str>) -> Result<String, chess_errors::ChessErrors>{
+    fn  move_diagonal(&self, to_spot: &str, state: &GameState, delta_y: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>{
+        if delta_y.abs() != 1 {
+            //pawns only attack the next square
+            let msg = format!("{}",to_spot);
+            return Err(chess_errors::ChessErrors::InvalidMove(msg));
+        }
         if self.get_player() == PLAYER::BLACK && delta_y > 0 {
             //black pawn annot move up
             let msg = format!("{}",to_spot);
@@ -245,14 +198,38 @@ impl GamePiece for Pawn {
                 return Err(chess_errors::ChessErrors::PawnCanOnlyAttackDiagonal(msg));
             }
         }
-        Ok(to_spot.to_string())
+        Ok((to_spot.to_string(),self.promotion_move_type(to_spot, promotion)))
+    }
+}
+
+impl Pawn {
+    fn promotion_move_type(&self, to_spot: &str, promotion_opt: Option<&str>) -> MoveType {
+        let last_row = match self.get_player() {
+            PLAYER::WHITE => 0,
+            PLAYER::BLACK => 7,
+        };
+        if let Ok(row) = chess_notation_utilities::convert_row(to_spot) {
+            if row == last_row {
+                let (queen, rook, bishop, knight) = match self.get_player() {
+                    PLAYER::WHITE => (WHITE_QUEEN, WHITE_ROOK, WHITE_BISHOP, WHITE_KNIGHT),
+                    PLAYER::BLACK => (BLACK_QUEEN, BLACK_ROOK, BLACK_BISHOP, BLACK_KNIGHT),
+                };
+                return match promotion_opt {
+                    Some("r") => MoveType::Promotion(rook),
+                    Some("b") => MoveType::Promotion(bishop),
+                    Some("k") => MoveType::Promotion(knight),
+                    _ => MoveType::Promotion(queen),
+                };
+            }
+        }
+        MoveType::Regular
     }
 }
 #[derive(Debug)]
 pub struct Rook {
     unicode_val: char,
     player: PLAYER,
-    moved: RefCell<bool> ,
+    moved: AtomicBool ,
 }
 impl fmt::Display for Rook {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -265,10 +242,10 @@ impl GamePiece for Rook {
         chess_notation_utilities::get_unvalidated_horiz_vert_moves(spot)
     }
     fn get_moved(&self) -> bool {
-        self.moved == RefCell::new(true)
+        self.moved.load(Ordering::Relaxed)
     }
     fn toggle_moved(&self){
-        self.moved.replace(true);
+        self.moved.store(true, Ordering::Relaxed);
     }
     fn get_unicode_val(&self) -> char {
         self.unicode_val
@@ -280,12 +257,12 @@ impl GamePiece for Rook {
         let msg = format!("{}",to_spot);
         return Err(chess_errors::ChessErrors::InvalidPromotion(msg));
     }
-    fn  move_horizontal(&self, to_spot: &str, _state: &GameState, _delta_x: i8, promotion: Option<&str>) -> Result<String, chess_errors::ChessErrors>{
+    fn  move_horizontal(&self, to_spot: &str, _state: &GameState, _delta_x: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>{
         if promotion.is_some() {
             let msg = format!("{}",to_spot);
             return Err(chess_errors::ChessErrors::InvalidPromotion(msg));
         }
-        Ok(to_spot.to_string())
+        Ok((to_spot.to_string(),MoveType::Regular))
     }
     fn  move_vertical(&self, to_spot: &str, _state: &GameState, _delta_y: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>{
         if promotion.is_some() {
@@ -294,7 +271,7 @@ impl GamePiece for Rook {
         }
         Ok((to_spot.to_string(),MoveType::Regular))
     }
-    fn  move_diagonal(&self, to_spot: &str, _state: &GameState, _delta_y: i8, promotion: Option<&str>) -> Result<String, chess_errors::ChessErrors>{
+    fn  move_diagonal(&self, to_spot: &str, _state: &GameState, _delta_y: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>{
         let msg = format!("{}",to_spot);
         return Err(chess_errors::ChessErrors::InvalidMove(msg));
     }
@@ -303,7 +280,7 @@ impl GamePiece for Rook {
 pub struct Knight {
     unicode_val: char,
     player: PLAYER,
-    moved: RefCell<bool> ,
+    moved: AtomicBool ,
 }
 
 impl fmt::Display for Knight {
@@ -400,10 +377,10 @@ impl GamePiece for Knight {
         Ok(unvalidated_moves)
     }
     fn get_moved(&self) -> bool {
-        self.moved == RefCell::new(true)
+        self.moved.load(Ordering::Relaxed)
     }
     fn toggle_moved(&self){
-        self.moved.replace(true);
+        self.moved.store(true, Ordering::Relaxed);
     }
     fn get_unicode_val(&self) -> char {
         self.unicode_val
@@ -418,7 +395,7 @@ impl GamePiece for Knight {
         }
         Ok(to_spot.to_string())
     }
-    fn  move_horizontal(&self, to_spot: &str, _state: &GameState, _delta_x: i8, promotion: Option<&str>) -> Result<String, chess_errors::ChessErrors>{
+    fn  move_horizontal(&self, to_spot: &str, _state: &GameState, _delta_x: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>{
         let msg = format!("{}",to_spot);
         return Err(chess_errors::ChessErrors::InvalidMove(msg));
     }
@@ -426,7 +403,7 @@ impl GamePiece for Knight {
         let msg = format!("{}",to_spot);
         return Err(chess_errors::ChessErrors::InvalidMove(msg));
     }
-    fn  move_diagonal(&self, to_spot: &str, _state: &GameState, _delta_y: i8, promotion: Option<&str>) -> Result<String, chess_errors::ChessErrors>{
+    fn  move_diagonal(&self, to_spot: &str, _state: &GameState, _delta_y: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>{
         let msg = format!("{}",to_spot);
         return Err(chess_errors::ChessErrors::InvalidMove(msg));
     }
@@ -435,7 +412,7 @@ impl GamePiece for Knight {
 pub struct Bishop {
     unicode_val: char,
     player: PLAYER,
-    moved: RefCell<bool> ,
+    moved: AtomicBool ,
 }
 
 impl fmt::Display for Bishop {
@@ -449,10 +426,10 @@ impl GamePiece for Bishop {
         chess_notation_utilities::get_unvalidated_diag_moves(spot)
     }
     fn get_moved(&self) -> bool {
-        self.moved == RefCell::new(true)
+        self.moved.load(Ordering::Relaxed)
     }
     fn toggle_moved(&self){
-        self.moved.replace(true);
+        self.moved.store(true, Ordering::Relaxed);
     }
     fn get_unicode_val(&self) -> char {
         self.unicode_val
@@ -464,7 +441,7 @@ impl GamePiece for Bishop {
         let msg = format!("{}",to_spot);
         return Err(chess_errors::ChessErrors::InvalidMove(msg));
     }
-    fn  move_horizontal(&self, to_spot: &str, _state: &GameState, _delta_x: i8, promotion: Option<&str>) -> Result<String, chess_errors::ChessErrors>{
+    fn  move_horizontal(&self, to_spot: &str, _state: &GameState, _delta_x: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>{
         let msg = format!("{}",to_spot);
         return Err(chess_errors::ChessErrors::InvalidMove(msg));
     }
@@ -472,19 +449,19 @@ impl GamePiece for Bishop {
         let msg = format!("{}",to_spot);
         return Err(chess_errors::ChessErrors::InvalidMove(msg));
     }
-    fn  move_diagonal(&self, to_spot: &str, _state: &GameState, _delta_y: i8, promotion: Option<&str>) -> Result<String, chess_errors::ChessErrors>{
+    fn  move_diagonal(&self, to_spot: &str, _state: &GameState, _delta_y: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>{
         if promotion.is_some() {
             let msg = format!("{}",to_spot);
             return Err(chess_errors::ChessErrors::InvalidPromotion(msg));
         }
-        Ok(to_spot.to_string())
+        Ok((to_spot.to_string(),MoveType::Regular))
     }
 }
 #[derive(Debug)]
 pub struct Queen {
     unicode_val: char,
     player: PLAYER,
-    moved: RefCell<bool> ,
+    moved: AtomicBool ,
 }
 
 impl fmt::Display for Queen {
@@ -500,10 +477,10 @@ impl GamePiece for Queen {
         Ok(unvalidated_moves)
     }
     fn get_moved(&self) -> bool {
-        self.moved == RefCell::new(true)
+        self.moved.load(Ordering::Relaxed)
     }
     fn toggle_moved(&self){
-        self.moved.replace(true);
+        self.moved.store(true, Ordering::Relaxed);
     }
     fn get_unicode_val(&self) -> char {
         self.unicode_val
@@ -515,12 +492,12 @@ impl GamePiece for Queen {
         let msg = format!("{}",to_spot);
         return Err(chess_errors::ChessErrors::InvalidMove(msg));
     }
-    fn  move_horizontal(&self, to_spot: &str, _state: &GameState, _delta_x: i8, promotion: Option<&str>) -> Result<String, chess_errors::ChessErrors>{
+    fn  move_horizontal(&self, to_spot: &str, _state: &GameState, _delta_x: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>{
         if promotion.is_some() {
             let msg = format!("{}",to_spot);
             return Err(chess_errors::ChessErrors::InvalidPromotion(msg));
         }
-        Ok(to_spot.to_string())
+        Ok((to_spot.to_string(),MoveType::Regular))
     }
     fn  move_vertical(&self, to_spot: &str, _state: &GameState, _delta_y: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>{
         if promotion.is_some() {
@@ -529,19 +506,19 @@ impl GamePiece for Queen {
         }
         Ok((to_spot.to_string(),MoveType::Regular))
     }
-    fn  move_diagonal(&self, to_spot: &str, _state: &GameState, _delta_y: i8, promotion: Option<&str>) -> Result<String, chess_errors::ChessErrors>{
+    fn  move_diagonal(&self, to_spot: &str, _state: &GameState, _delta_y: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>{
         if promotion.is_some() {
             let msg = format!("{}",to_spot);
             return Err(chess_errors::ChessErrors::InvalidPromotion(msg));
         }
-        Ok(to_spot.to_string())
+        Ok((to_spot.to_string(),MoveType::Regular))
     }
 }
 #[derive(Debug)]
 pub struct King {
     unicode_val: char,
     player: PLAYER,
-    moved: RefCell<bool> ,
+    moved: AtomicBool ,
 }
 
 impl fmt::Display for King {
@@ -595,14 +572,20 @@ impl GamePiece for King {
             let unvalidated_move = format!("{}-{}",spot,right);
             unvalidated_moves.push(unvalidated_move);
         }
+        //castling, the rest is checked when the move is validated
+        if !self.get_moved() && (spot == "e1" || spot == "e8") {
+            let row = &spot[1..];
+            unvalidated_moves.push(format!("{}-g{}",spot,row));
+            unvalidated_moves.push(format!("{}-c{}",spot,row));
+        }
 
         Ok(unvalidated_moves)
     }
     fn get_moved(&self) -> bool {
-        self.moved == RefCell::new(true)
+        self.moved.load(Ordering::Relaxed)
     }
     fn toggle_moved(&self){
-        self.moved.replace(true);
+        self.moved.store(true, Ordering::Relaxed);
     }
     fn get_unicode_val(&self) -> char {
         self.unicode_val
@@ -614,23 +597,116 @@ impl GamePiece for King {
         let msg = format!("{}",to_spot);
         return Err(chess_errors::ChessErrors::InvalidMove(msg));
     }
-    fn  move_horizontal(&self, to_spot: &str, _state: &GameState, _delta_x: i8, promotion: Option<&str>) -> Result<String, chess_errors::ChessErrors>{
+    fn  move_horizontal(&self, to_spot: &str, state: &GameState, delta_x: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>{
+        if promotion.is_some() {
+            let msg = format!("{}",to_spot);
+            return Err(chess_errors::ChessErrors::InvalidPromotion(msg));
+        }
+        if delta_x.abs() == 1 {
+            return Ok((to_spot.to_string(),MoveType::Regular));
+        }
+        if delta_x.abs() == 2 && !self.get_moved() {
+            //castling needs an unmoved rook in the corner and nothing between it and the king.
+            //check is handled by the game
+            let row = chess_notation_utilities::convert_row(to_spot)?;
+            let (rook_col, between_cols) = if delta_x < 0 { (7, 5..7) } else { (0, 1..4) };
+            if let Some(rook) = state.get_piece_at(row * 8 + rook_col) {
+                let rook_val = match self.get_player() {
+                    PLAYER::WHITE => WHITE_ROOK,
+                    PLAYER::BLACK => BLACK_ROOK,
+                };
+                if rook.get_unicode_val() == rook_val && !rook.get_moved() {
+                    for col in between_cols {
+                        if state.get_piece_at(row * 8 + col).is_some() {
+                            let msg = chess_notation_utilities::index_to_spot(row * 8 + col);
+                            return Err(chess_errors::ChessErrors::PieceBetween(msg));
+                        }
+                    }
+                    return Ok((to_spot.to_string(),MoveType::Castling));
+                }
+            }
+        }
         let msg = format!("{}",to_spot);
-        return Err(chess_errors::ChessErrors::InvalidMove(msg));
+        Err(chess_errors::ChessErrors::InvalidMove(msg))
     }
-    fn  move_vertical(&self, to_spot: &str, _state: &GameState, _delta_y: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>{
+    fn  move_vertical(&self, to_spot: &str, _state: &GameState, delta_y: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>{
         if promotion.is_some() {
             let msg = format!("{}",to_spot);
             return Err(chess_errors::ChessErrors::InvalidPromotion(msg));
         }
+        if delta_y.abs() != 1 {
+            let msg = format!("{}",to_spot);
+            return Err(chess_errors::ChessErrors::InvalidMove(msg));
+        }
         Ok((to_spot.to_string(),MoveType::Regular))
     }
-    fn  move_diagonal(&self, to_spot: &str, _state: &GameState, _delta_y: i8, promotion: Option<&str>) -> Result<String, chess_errors::ChessErrors>{
+    fn  move_diagonal(&self, to_spot: &str, _state: &GameState, delta_y: i8, promotion: Option<&str>) -> Result<(String,MoveType), chess_errors::ChessErrors>{
         if promotion.is_some() {
             let msg = format!("{}",to_spot);
             return Err(chess_errors::ChessErrors::InvalidPromotion(msg));
         }
-        Ok(to_spot.to_string())
+        if delta_y.abs() != 1 {
+            let msg = format!("{}",to_spot);
+            return Err(chess_errors::ChessErrors::InvalidMove(msg));
+        }
+        Ok((to_spot.to_string(),MoveType::Regular))
+    }
+}
+
+pub fn new_game_piece(piece_char: char, moved: bool) -> Option<Arc<dyn GamePiece>> {
+    let (player, moved) = if is_white_piece(piece_char) {
+        (PLAYER::WHITE, AtomicBool::new(moved))
+    } else {
+        (PLAYER::BLACK, AtomicBool::new(moved))
+    };
+    match piece_char {
+        WHITE_PAWN | BLACK_PAWN => Some(Arc::new(Pawn { unicode_val: piece_char, player, moved })),
+        WHITE_ROOK | BLACK_ROOK => Some(Arc::new(Rook { unicode_val: piece_char, player, moved })),
+        WHITE_KNIGHT | BLACK_KNIGHT => Some(Arc::new(Knight { unicode_val: piece_char, player, moved })),
+        WHITE_BISHOP | BLACK_BISHOP => Some(Arc::new(Bishop { unicode_val: piece_char, player, moved })),
+        WHITE_QUEEN | BLACK_QUEEN => Some(Arc::new(Queen { unicode_val: piece_char, player, moved })),
+        WHITE_KING | BLACK_KING => Some(Arc::new(King { unicode_val: piece_char, player, moved })),
+        _ => None,
+    }
+}
+
+fn is_white_piece(unicode: char) -> bool {
+    matches!(unicode, WHITE_PAWN | WHITE_ROOK | WHITE_KNIGHT | WHITE_BISHOP | WHITE_QUEEN | WHITE_KING)
+}
+
+pub fn fen_char_from_unicode(unicode: char) -> char {
+    match unicode {
+        WHITE_PAWN => 'P',
+        WHITE_ROOK => 'R',
+        WHITE_KNIGHT => 'N',
+        WHITE_BISHOP => 'B',
+        WHITE_QUEEN => 'Q',
+        WHITE_KING => 'K',
+        BLACK_PAWN => 'p',
+        BLACK_ROOK => 'r',
+        BLACK_KNIGHT => 'n',
+        BLACK_BISHOP => 'b',
+        BLACK_QUEEN => 'q',
+        BLACK_KING => 'k',
+        _ => '.',
+    }
+}
+
+pub fn unicode_from_fen_char(fen_char: char) -> Option<char> {
+    match fen_char {
+        'P' => Some(WHITE_PAWN),
+        'R' => Some(WHITE_ROOK),
+        'N' => Some(WHITE_KNIGHT),
+        'B' => Some(WHITE_BISHOP),
+        'Q' => Some(WHITE_QUEEN),
+        'K' => Some(WHITE_KING),
+        'p' => Some(BLACK_PAWN),
+        'r' => Some(BLACK_ROOK),
+        'n' => Some(BLACK_KNIGHT),
+        'b' => Some(BLACK_BISHOP),
+        'q' => Some(BLACK_QUEEN),
+        'k' => Some(BLACK_KING),
+        _ => None,
     }
 }
 
@@ -642,7 +718,7 @@ fn get_piece_char_from_unicode(unicode: char) -> char {
         WHITE_ROOK=> 'r',
         WHITE_QUEEN=> 'q',
         WHITE_PAWN => 'p',
-        BLACK_BISHOP   =>  'b',
+        BLACK_BISHOP   =>  'B',
         BLACK_KING => 'K',
         BLACK_KNIGHT=> 'N',
         BLACK_ROOK=> 'R',
@@ -658,9 +734,27 @@ pub struct WebGame {
 }
 
 pub struct GameState {
-    pub state: Vec<Option<Rc<dyn GamePiece>>>,
+    pub state: Vec<Option<Arc<dyn GamePiece>>>,
     pub player_turn: PLAYER,
     pub en_passant_enabled: Option<Vec<String>>,
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+}
+
+impl Clone for GameState {
+    fn clone(&self) -> Self {
+        // pieces keep their moved flag behind the Arc, so sharing them would leak moves between copies
+        let pieces = self.state.iter().map(|piece_opt| {
+            piece_opt.as_ref().and_then(|piece| new_game_piece(piece.get_unicode_val(), piece.get_moved()))
+        }).collect();
+        GameState {
+            state: pieces,
+            player_turn: self.player_turn,
+            en_passant_enabled: self.en_passant_enabled.clone(),
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+        }
+    }
 }
 impl std::convert::Into<WebGame> for &GameState {
     fn into(self) -> WebGame {
@@ -713,100 +807,100 @@ impl Default for GameState {
         let black_rook1 = Rook {
             unicode_val: BLACK_ROOK,
             player: PLAYER::BLACK,
-            moved: RefCell::new(false) ,
+            moved: AtomicBool::new(false) ,
         };
         let black_knight1 = Knight {
             unicode_val: BLACK_KNIGHT,
             player: PLAYER::BLACK,
-            moved: RefCell::new(false) ,
+            moved: AtomicBool::new(false) ,
         };
         let black_bishop1 = Bishop {
             unicode_val: BLACK_BISHOP,
             player: PLAYER::BLACK,
-            moved: RefCell::new(false) ,
+            moved: AtomicBool::new(false) ,
         };
         let black_queen = Queen {
             unicode_val: BLACK_QUEEN,
             player: PLAYER::BLACK,
-            moved: RefCell::new(false) ,
+            moved: AtomicBool::new(false) ,
         };
         let black_king = King {
             unicode_val: BLACK_KING,
             player: PLAYER::BLACK,
-            moved: RefCell::new(false) ,
+            moved: AtomicBool::new(false) ,
         };
         let black_bishop2 = Bishop {
             unicode_val: BLACK_BISHOP,
             player: PLAYER::BLACK,
-            moved: RefCell::new(false) ,
+            moved: AtomicBool::new(false) ,
         };
         let black_knight2 = Knight {
             unicode_val: BLACK_KNIGHT,
             player: PLAYER::BLACK,
-            moved: RefCell::new(false) ,
+            moved: AtomicBool::new(false) ,
         };
         let black_rook2 = Rook {
             unicode_val: BLACK_ROOK,
             player: PLAYER::BLACK,
-            moved: RefCell::new(false) ,
+            moved: AtomicBool::new(false) ,
         };
         let white_rook1 = Rook {
             unicode_val: WHITE_ROOK,
             player: PLAYER::WHITE,
-            moved: RefCell::new(false) ,
+            moved: AtomicBool::new(false) ,
         };
         let white_knight1 = Knight {
             unicode_val: WHITE_KNIGHT,
             player: PLAYER::WHITE,
-            moved: RefCell::new(false) ,
+            moved: AtomicBool::new(false) ,
         };
         let white_bishop1 = Bishop {
             unicode_val: WHITE_BISHOP,
             player: PLAYER::WHITE,
-            moved: RefCell::new(false) ,
+            moved: AtomicBool::new(false) ,
         };
         let white_queen = Queen {
             unicode_val: WHITE_QUEEN,
             player: PLAYER::WHITE,
-            moved: RefCell::new(false) ,
+            moved: AtomicBool::new(false) ,
         };
         let white_king = King {
             unicode_val: WHITE_KING,
             player: PLAYER::WHITE,
-            moved: RefCell::new(false) ,
+            moved: AtomicBool::new(false) ,
         };
         let white_bishop2 = Bishop {
             unicode_val: WHITE_BISHOP,
             player: PLAYER::WHITE,
-            moved: RefCell::new(false) ,
+            moved: AtomicBool::new(false) ,
         };
         let white_knight2 = Knight {
             unicode_val: WHITE_KNIGHT,
             player: PLAYER::WHITE,
-            moved: RefCell::new(false) ,
+            moved: AtomicBool::new(false) ,
         };
         let white_rook2 = Rook {
             unicode_val: WHITE_ROOK,
             player: PLAYER::WHITE,
-            moved: RefCell::new(false) ,
-        };
-
-        let mut pieces: Vec<Option<Rc<dyn GamePiece>>> = Vec::new();
-        pieces.push(Some(Rc::new(black_rook1)));
-        pieces.push(Some(Rc::new(black_knight1)));
-        pieces.push(Some(Rc::new(black_bishop1)));
-        pieces.push(Some(Rc::new(black_queen)));
-        pieces.push(Some(Rc::new(black_king)));
-        pieces.push(Some(Rc::new(black_bishop2)));
-        pieces.push(Some(Rc::new(black_knight2)));
-        pieces.push(Some(Rc::new(black_rook2)));
+            moved: AtomicBool::new(false) ,
+        };
+
+        let mut pieces: Vec<Option<Arc<dyn GamePiece>>> = Vec::new();
+        pieces.push(Some(Arc::new(black_rook1)));
+        pieces.push(Some(Arc::new(black_knight1)));
+        pieces.push(Some(Arc::new(black_bishop1)));
+        pieces.push(Some(Arc::new(black_queen)));
+        pieces.push(Some(Arc::new(black_king)));
+        pieces.push(Some(Arc::new(black_bishop2)));
+        pieces.push(Some(Arc::new(black_knight2)));
+        pieces.push(Some(Arc::new(black_rook2)));
         for _x in 0..8 {
             let black_pawn = Pawn {
                 unicode_val: BLACK_PAWN,
                 player: PLAYER::BLACK,
-                moved: RefCell::new(false) ,
+                moved: AtomicBool::new(false) ,
             };
-            pieces.push(Some(Rc::new(black_pawn)));
+            pieces.push(Some(Arc::new(black_pawn)));
         }
         for _x in 0..32 {
             pieces.push(None);
@@ -815,22 +909,24 @@ impl Default for GameState {
             let white_pawn = Pawn {
                 unicode_val: WHITE_PAWN,
                 player: PLAYER::WHITE,
-                moved: RefCell::new(false) ,
+                moved: AtomicBool::new(false) ,
             };
-            pieces.push(Some(Rc::new(white_pawn)));
-        }
-        pieces.push(Some(Rc::new(white_rook1)));
-        pieces.push(Some(Rc::new(white_knight1)));
-        pieces.push(Some(Rc::new(white_bishop1)));
-        pieces.push(Some(Rc::new(white_queen)));
-        pieces.push(Some(Rc::new(white_king)));
-        pieces.push(Some(Rc::new(white_bishop2)));
-        pieces.push(Some(Rc::new(white_knight2)));
-        pieces.push(Some(Rc::new(white_rook2)));
+            pieces.push(Some(Arc::new(white_pawn)));
+        }
+        pieces.push(Some(Arc::new(white_rook1)));
+        pieces.push(Some(Arc::new(white_knight1)));
+        pieces.push(Some(Arc::new(white_bishop1)));
+        pieces.push(Some(Arc::new(white_queen)));
+        pieces.push(Some(Arc::new(white_king)));
+        pieces.push(Some(Arc::new(white_bishop2)));
+        pieces.push(Some(Arc::new(white_knight2)));
+        pieces.push(Some(Arc::new(white_rook2)));
         let state = GameState {
             state: pieces,
             player_turn: PLAYER::WHITE,
             en_passant_enabled: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
         };
         state
     }
@@ -856,80 +952,38 @@ impl GameState {
         
     }
 
-    fn promotion_game_piece(&self, piece_char:char) -> Option<Rc<dyn GamePiece>>{
+    fn promotion_game_piece(&self, piece_char:char) -> Option<Arc<dyn GamePiece>>{
         match piece_char{
-            WHITE_QUEEN => {
-                let white_queen = Queen {
-                                unicode_val: piece_char,
-                                player: PLAYER::WHITE,
-                                moved: RefCell::new(true) ,
-                            };
-                Some(Rc::new(white_queen))
-            },
-            WHITE_ROOK => {
-                let white_rook = Rook {
-                                unicode_val: piece_char,
-                                player: PLAYER::WHITE,
-                                moved: RefCell::new(true) ,
-                            };
-                Some(Rc::new(white_rook))
-            },
-            WHITE_BISHOP => {
-                let white_bishop = Bishop {
-                                unicode_val: piece_char,
-                                player: PLAYER::WHITE,
-                                moved: RefCell::new(true) ,
-                            };
-                Some(Rc::new(white_bishop))
-            },
-            WHITE_KNIGHT => {
-                let white_knight = Knight {
-                                unicode_val: piece_char,
-                                player: PLAYER::WHITE,
-                                moved: RefCell::new(true) ,
-                            };
-                Some(Rc::new(white_knight))
-            },
-            BLACK_QUEEN => {
-                let black_queen = Queen {
-                    unicode_val: piece_char,
-                    player: PLAYER::BLACK,
-                    moved: RefCell::new(true) ,
-                };
-                Some(Rc::new(black_queen))
-            },
-            BLACK_ROOK => {
-                let black_rook = Rook {
-                    unicode_val: piece_char,
-                    player: PLAYER::BLACK,
-                    moved: RefCell::new(true) ,
-                };
-                Some(Rc::new(black_rook))
-            },
-            BLACK_KNIGHT => {
-                let black_knight = Knight {
-                    unicode_val: piece_char,
-                    player: PLAYER::BLACK,
-                    moved: RefCell::new(true) ,
-                };
-                Some(Rc::new(black_knight))
-            },
-            BLACK_BISHOP => {
-                let black_bishop = Bishop {
-                    unicode_val: piece_char,
-                    player: PLAYER::BLACK,
-                    moved: RefCell::new(true) ,
-                };
-                Some(Rc::new(black_bishop))
-            },
-            _ => None
+            WHITE_PAWN | WHITE_KING | BLACK_PAWN | BLACK_KING => None,
+            _ => new_game_piece(piece_char, true),
         }
-        
     }
     
     pub fn move_piece(&mut self, from: usize, to: usize, promotion:  Option<&str>, move_type: MoveType ) {
         // This function does not validate whether or not the move is valid. It is done from calling functions
         let value = std::mem::replace(&mut self.state[from], None);
+        let mover = value.as_ref().map(|piece| (piece.get_unicode_val(), piece.get_player()));
+        let is_capture = self.state[to].is_some() || matches!(move_type, MoveType::Enpassant(_));
+        if let Some((unicode_val, player)) = mover {
+            if is_capture || unicode_val == WHITE_PAWN || unicode_val == BLACK_PAWN {
+                self.halfmove_clock = 0;
+            } else {
+                self.halfmove_clock += 1;
+            }
+            if player == PLAYER::BLACK {
+                self.fullmove_number += 1;
+            }
+        }
+        if let MoveType::Castling = move_type {
+            //the king moves below, bring the rook over to the other side of it
+            let row = to / 8;
+            let (rook_from, rook_to) = if to % 8 == 6 { (row * 8 + 7, row * 8 + 5) } else { (row * 8, row * 8 + 3) };
+            let rook = self.state[rook_from].take();
+            if let Some(rook_piece) = rook.as_ref() {
+                rook_piece.toggle_moved();
+            }
+            let _ = std::mem::replace(&mut self.state[rook_to], rook);
+        }
         if let MoveType::Enpassant(index) =  move_type{
             let _= std::mem::replace(&mut self.state[index], None);
             self.en_passant_enabled = None;
@@ -947,7 +1001,263 @@ impl GameState {
        
     }
 
-    pub fn get_piece_at(&self, pos: usize) -> Option<Rc<dyn GamePiece>> {
+    pub fn find_king(&self, player: PLAYER) -> Option<usize> {
+        let king_val = match player {
+            PLAYER::WHITE => WHITE_KING,
+            PLAYER::BLACK => BLACK_KING,
+        };
+        self.state.iter().position(|piece_opt| {
+            piece_opt.as_ref().map(|piece| piece.get_unicode_val()) == Some(king_val)
+        })
+    }
+
+    pub fn is_in_check(&self, player: PLAYER) -> bool {
+        let opponent = match player {
+            PLAYER::WHITE => PLAYER::BLACK,
+            PLAYER::BLACK => PLAYER::WHITE,
+        };
+        match self.find_king(player) {
+            Some(index) => self.is_square_attacked(index, opponent),
+            None => false,
+        }
+    }
+
+    // neither side can ever mate: bare kings, a single minor piece, or bishops all on one colour
+    pub fn is_insufficient_material(&self) -> bool {
+        let mut knights = 0;
+        let mut bishop_colours = [false, false];
+        for (index, piece) in self.state.iter().enumerate() {
+            match piece.as_ref().map(|piece| piece.get_unicode_val()) {
+                None | Some(WHITE_KING) | Some(BLACK_KING) => {}
+                Some(WHITE_KNIGHT) | Some(BLACK_KNIGHT) => knights += 1,
+                Some(WHITE_BISHOP) | Some(BLACK_BISHOP) => bishop_colours[(index / 8 + index % 8) % 2] = true,
+                Some(_) => return false,
+            }
+        }
+        match knights {
+            0 => !(bishop_colours[0] && bishop_colours[1]),
+            1 => !bishop_colours[0] && !bishop_colours[1],
+            _ => false,
+        }
+    }
+
+    // whether player has more than a lone king, or a king and one knight or bishop,
+    // which is what it takes to win when the other side runs out of time
+    pub fn has_mating_material(&self, player: PLAYER) -> bool {
+        let mut minors = 0;
+        for piece in self.state.iter().flatten().filter(|piece| piece.get_player() == player) {
+            match piece.get_unicode_val() {
+                WHITE_KING | BLACK_KING => {}
+                WHITE_KNIGHT | BLACK_KNIGHT | WHITE_BISHOP | BLACK_BISHOP => minors += 1,
+                _ => return true,
+            }
+        }
+        minors > 1
+    }
+
+    pub fn is_square_attacked(&self, index: usize, by_player: PLAYER) -> bool {
+        let row = (index / 8) as i8;
+        let col = (index % 8) as i8;
+        let piece_at = |r: i8, c: i8| -> Option<char> {
+            if !(0..8).contains(&r) || !(0..8).contains(&c) {
+                return None;
+            }
+            self.state[(r * 8 + c) as usize].as_ref()
+                .filter(|piece| piece.get_player() == by_player)
+                .map(|piece| piece.get_unicode_val())
+        };
+        let (pawn, knight, bishop, rook, queen, king) = match by_player {
+            PLAYER::WHITE => (WHITE_PAWN, WHITE_KNIGHT, WHITE_BISHOP, WHITE_ROOK, WHITE_QUEEN, WHITE_KING),
+            PLAYER::BLACK => (BLACK_PAWN, BLACK_KNIGHT, BLACK_BISHOP, BLACK_ROOK, BLACK_QUEEN, BLACK_KING),
+        };
+        //white pawns attack up the board (towards row 0), so they sit one row below the square
+        let pawn_row = match by_player {
+            PLAYER::WHITE => row + 1,
+            PLAYER::BLACK => row - 1,
+        };
+        if piece_at(pawn_row, col - 1) == Some(pawn) || piece_at(pawn_row, col + 1) == Some(pawn) {
+            return true;
+        }
+        for (dr, dc) in [(-2, -1), (-2, 1), (-1, -2), (-1, 2), (1, -2), (1, 2), (2, -1), (2, 1)] {
+            if piece_at(row + dr, col + dc) == Some(knight) {
+                return true;
+            }
+        }
+        for (dr, dc) in [(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)] {
+            if piece_at(row + dr, col + dc) == Some(king) {
+                return true;
+            }
+            let slider = if dr == 0 || dc == 0 { rook } else { bishop };
+            let (mut r, mut c) = (row + dr, col + dc);
+            while (0..8).contains(&r) && (0..8).contains(&c) {
+                if let Some(piece) = self.state[(r * 8 + c) as usize].as_ref() {
+                    if piece.get_player() == by_player
+                        && (piece.get_unicode_val() == slider || piece.get_unicode_val() == queen) {
+                        return true;
+                    }
+                    break;
+                }
+                r += dr;
+                c += dc;
+            }
+        }
+        false
+    }
+
+    pub fn from_fen(fen: &str) -> Result<GameState, chess_errors::ChessErrors> {
+        let invalid = || chess_errors::ChessErrors::InvalidFen(fen.to_string());
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() < 4 {
+            return Err(invalid());
+        }
+        let castling = fields[2];
+        let mut pieces: Vec<Option<Arc<dyn GamePiece>>> = Vec::new();
+        let rows: Vec<&str> = fields[0].split('/').collect();
+        if rows.len() != 8 {
+            return Err(invalid());
+        }
+        for (row, row_str) in rows.iter().enumerate() {
+            for fen_char in row_str.chars() {
+                if let Some(empty) = fen_char.to_digit(10) {
+                    for _x in 0..empty {
+                        pieces.push(None);
+                    }
+                    continue;
+                }
+                let unicode_val = unicode_from_fen_char(fen_char).ok_or_else(invalid)?;
+                let index = pieces.len();
+                //only pawns, kings and rooks care whether they have moved
+                let unmoved = match unicode_val {
+                    WHITE_PAWN => row == 6,
+                    BLACK_PAWN => row == 1,
+                    WHITE_KING => index == 60 && (castling.contains('K') || castling.contains('Q')),
+                    BLACK_KING => index == 4 && (castling.contains('k') || castling.contains('q')),
+                    WHITE_ROOK => (index == 63 && castling.contains('K')) || (index == 56 && castling.contains('Q')),
+                    BLACK_ROOK => (index == 7 && castling.contains('k')) || (index == 0 && castling.contains('q')),
+                    _ => true,
+                };
+                pieces.push(new_game_piece(unicode_val, !unmoved));
+            }
+            if pieces.len() != (row + 1) * 8 {
+                return Err(invalid());
+            }
+        }
+        let player_turn = match fields[1] {
+            "w" => PLAYER::WHITE,
+            "b" => PLAYER::BLACK,
+            _ => return Err(invalid()),
+        };
+        let mut game_state = GameState {
+            state: pieces,
+            player_turn,
+            en_passant_enabled: None,
+            halfmove_clock: fields.get(4).and_then(|x| x.parse().ok()).unwrap_or(0),
+            fullmove_number: fields.get(5).and_then(|x| x.parse().ok()).unwrap_or(1),
+        };
+        if fields[3] != "-" {
+            //stored the same way Game::check_en_passant does, "<attacker>-<target><captured pawn>"
+            let target = chess_notation_utilities::notation_to_index(fields[3]).map_err(|_| invalid())?;
+            let (captured, attacker_pawn) = match player_turn {
+                PLAYER::WHITE => (target + 8, WHITE_PAWN),
+                PLAYER::BLACK => (target.wrapping_sub(8), BLACK_PAWN),
+            };
+            if captured >= 64 {
+                return Err(invalid());
+            }
+            let mut en_passant_moves = Vec::new();
+            let captured_col = captured % 8;
+            for attacker in [captured.wrapping_sub(1), captured + 1] {
+                if attacker < 64 && attacker / 8 == captured / 8 && (attacker % 8).abs_diff(captured_col) == 1 {
+                    if let Some(piece) = game_state.get_piece_at(attacker) {
+                        if piece.get_unicode_val() == attacker_pawn {
+                            en_passant_moves.push(format!("{}-{}{}",
+                                chess_notation_utilities::index_to_spot(attacker),
+                                fields[3],
+                                chess_notation_utilities::index_to_spot(captured)));
+                        }
+                    }
+                }
+            }
+            if !en_passant_moves.is_empty() {
+                game_state.en_passant_enabled = Some(en_passant_moves);
+            }
+        }
+        Ok(game_state)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for row in 0..8 {
+            let mut empty = 0;
+            for col in 0..8 {
+                match &self.state[row * 8 + col] {
+                    Some(piece) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        fen.push(fen_char_from_unicode(piece.get_unicode_val()));
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if row != 7 {
+                fen.push('/');
+            }
+        }
+        fen.push_str(match self.player_turn {
+            PLAYER::WHITE => " w ",
+            PLAYER::BLACK => " b ",
+        });
+        let castling = self.castling_rights();
+        if castling.is_empty() {
+            fen.push('-');
+        } else {
+            fen.push_str(&castling);
+        }
+        match self.en_passant_enabled.as_ref().and_then(|moves| moves.first()) {
+            Some(en_passant_move) => {
+                fen.push(' ');
+                fen.push_str(&en_passant_move[3..5]);
+            }
+            None => fen.push_str(" -"),
+        }
+        fen.push_str(&format!(" {} {}", self.halfmove_clock, self.fullmove_number));
+        fen
+    }
+
+    // "KQkq" style, worked out from which kings and rooks have not moved yet
+    pub fn castling_rights(&self) -> String {
+        let unmoved = |index: usize, unicode_val: char| -> bool {
+            match &self.state[index] {
+                Some(piece) => piece.get_unicode_val() == unicode_val && !piece.get_moved(),
+                None => false,
+            }
+        };
+        let mut rights = String::new();
+        if unmoved(60, WHITE_KING) {
+            if unmoved(63, WHITE_ROOK) {
+                rights.push('K');
+            }
+            if unmoved(56, WHITE_ROOK) {
+                rights.push('Q');
+            }
+        }
+        if unmoved(4, BLACK_KING) {
+            if unmoved(7, BLACK_ROOK) {
+                rights.push('k');
+            }
+            if unmoved(0, BLACK_ROOK) {
+                rights.push('q');
+            }
+        }
+        rights
+    }
+
+    pub fn get_piece_at(&self, pos: usize) -> Option<Arc<dyn GamePiece>> {
         if let Some(a) = self.state[pos].as_ref() {
             Some(a.clone())
         } else {
@@ -0,0 +1,81 @@
+use std::sync::OnceLock;
+
+use crate::chess_notation_utilities;
+use crate::prng::Prng;
+use crate::visual;
+
+use visual::{GameState, PLAYER};
+
+const PIECES: [char; 12] = [
+    visual::WHITE_PAWN, visual::WHITE_KNIGHT, visual::WHITE_BISHOP,
+    visual::WHITE_ROOK, visual::WHITE_QUEEN, visual::WHITE_KING,
+    visual::BLACK_PAWN, visual::BLACK_KNIGHT, visual::BLACK_BISHOP,
+    visual::BLACK_ROOK, visual::BLACK_QUEEN, visual::BLACK_KING,
+];
+
+struct Keys {
+    pieces: [[u64; 64]; 12],
+    black_to_move: u64,
+    castling: [u64; 4],
+    en_passant_file: [u64; 8],
+}
+
+fn keys() -> &'static Keys {
+    static KEYS: OnceLock<Keys> = OnceLock::new();
+    KEYS.get_or_init(|| {
+        let mut prng = Prng::new(0x1BAD_B002_C0FF_EE00);
+        let mut keys = Keys {
+            pieces: [[0; 64]; 12],
+            black_to_move: prng.next_u64(),
+            castling: [0; 4],
+            en_passant_file: [0; 8],
+        };
+        for piece in keys.pieces.iter_mut() {
+            for square in piece.iter_mut() {
+                *square = prng.next_u64();
+            }
+        }
+        for castle in keys.castling.iter_mut() {
+            *castle = prng.next_u64();
+        }
+        for file in keys.en_passant_file.iter_mut() {
+            *file = prng.next_u64();
+        }
+        keys
+    })
+}
+
+pub fn piece_index(unicode_val: char) -> Option<usize> {
+    PIECES.iter().position(|piece| *piece == unicode_val)
+}
+
+// hash of everything that makes two positions the same for repetition and the transposition table
+pub fn position_key(state: &GameState) -> u64 {
+    let keys = keys();
+    let mut key = 0;
+    for (index, piece_opt) in state.state.iter().enumerate() {
+        if let Some(piece) = piece_opt {
+            if let Some(piece_index) = piece_index(piece.get_unicode_val()) {
+                key ^= keys.pieces[piece_index][index];
+            }
+        }
+    }
+    if state.player_turn == PLAYER::BLACK {
+        key ^= keys.black_to_move;
+    }
+    for right in state.castling_rights().chars() {
+        let castle = match right {
+            'K' => 0,
+            'Q' => 1,
+            'k' => 2,
+            _ => 3,
+        };
+        key ^= keys.castling[castle];
+    }
+    if let Some(en_passant_move) = state.en_passant_enabled.as_ref().and_then(|moves| moves.first()) {
+        if let Ok(col) = chess_notation_utilities::convert_col(&en_passant_move[3..5]) {
+            key ^= keys.en_passant_file[col];
+        }
+    }
+    key
+}
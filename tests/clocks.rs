@@ -0,0 +1,158 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chess::chess_clock::ChessClock;
+use chess::repository::{GameRepository, InMemoryGameRepository};
+use chess::session::{GameSession, GameStatus, GameStore};
+use chess::time_control::TimeControl;
+use chess::time_manager::{Clock, ManualClock};
+use chess::visual::PLAYER;
+
+fn secs(seconds: u64) -> Duration {
+    Duration::from_secs(seconds)
+}
+
+fn clock(time_control: &str) -> ChessClock {
+    ChessClock::new(time_control.parse().unwrap())
+}
+
+#[test]
+fn time_controls_read_back_as_written() {
+    for text in ["5+3", "1+0", "15d5", "25b10", "40/90+30,30+30", "40/120+0"] {
+        assert_eq!(text.parse::<TimeControl>().unwrap().to_string(), text);
+    }
+    assert_eq!("3".parse::<TimeControl>().unwrap().to_string(), "3+0");
+    for text in ["", "5+", "0+3", "30+30,40/90+30", "40/90x30"] {
+        assert!(text.parse::<TimeControl>().is_err(), "{}", text);
+    }
+}
+
+#[test]
+fn fischer_adds_the_increment_after_each_move() {
+    let mut clock = clock("1+2");
+    // nothing runs before the first move
+    assert_eq!(clock.remaining(PLAYER::WHITE, secs(30)), secs(60));
+    clock.press(PLAYER::WHITE, secs(30));
+    assert_eq!(clock.remaining(PLAYER::WHITE, secs(30)), secs(62));
+    assert_eq!(clock.running(), Some(PLAYER::BLACK));
+    clock.press(PLAYER::BLACK, secs(40));
+    assert_eq!(clock.remaining(PLAYER::BLACK, secs(40)), secs(52));
+    assert_eq!(clock.remaining(PLAYER::WHITE, secs(100)), secs(2));
+    assert_eq!(clock.flagged(secs(101)), None);
+    assert_eq!(clock.flagged(secs(102)), Some(PLAYER::WHITE));
+}
+
+#[test]
+fn simple_delay_runs_down_only_after_the_delay() {
+    let mut clock = clock("1d5");
+    clock.press(PLAYER::WHITE, secs(0));
+    assert_eq!(clock.remaining(PLAYER::BLACK, secs(4)), secs(60));
+    clock.press(PLAYER::BLACK, secs(8));
+    assert_eq!(clock.remaining(PLAYER::BLACK, secs(8)), secs(57));
+}
+
+#[test]
+fn bronstein_gives_back_at_most_the_delay() {
+    let mut clock = clock("1b5");
+    clock.press(PLAYER::WHITE, secs(0));
+    clock.press(PLAYER::BLACK, secs(3));
+    assert_eq!(clock.remaining(PLAYER::BLACK, secs(3)), secs(60));
+    clock.press(PLAYER::WHITE, secs(13));
+    assert_eq!(clock.remaining(PLAYER::WHITE, secs(13)), secs(55));
+}
+
+#[test]
+fn stages_add_their_time_when_reached() {
+    let mut clock = clock("2/1+0,1/2+0");
+    let mut now = 0;
+    for _ in 0..2 {
+        clock.press(PLAYER::WHITE, secs(now));
+        now += 10;
+        clock.press(PLAYER::BLACK, secs(now));
+    }
+    // white's two moves took no time, black's two took 10 seconds each
+    assert_eq!(clock.remaining(PLAYER::WHITE, secs(now)), secs(60 + 120));
+    assert_eq!(clock.remaining(PLAYER::BLACK, secs(now)), secs(40 + 120));
+    // the last stage starts over every move
+    clock.press(PLAYER::WHITE, secs(now));
+    assert_eq!(clock.remaining(PLAYER::WHITE, secs(now)), secs(60 + 240));
+}
+
+fn store(clock: &Arc<ManualClock>) -> GameStore {
+    let repository: Box<dyn GameRepository> = Box::new(InMemoryGameRepository::default());
+    GameStore::open(repository, clock.clone()).unwrap()
+}
+
+fn timed(store: &GameStore, id: &str, time_control: &str) {
+    store.with(id, |session| {
+        session.set_time_control(Some(time_control.parse()?));
+        Ok(())
+    })
+    .unwrap();
+}
+
+fn play(store: &GameStore, clock: &ManualClock, id: &str, player: PLAYER, chess_move: &str) {
+    store.with(id, |session| session.play_as(player, chess_move, clock.now())).unwrap();
+}
+
+#[test]
+fn running_out_of_time_loses() {
+    let clock = Arc::new(ManualClock::new(Duration::ZERO));
+    let store = store(&clock);
+    let (session, _) = store.create(None, PLAYER::WHITE, false, None).unwrap();
+    timed(&store, &session.id, "1+0");
+    play(&store, &clock, &session.id, PLAYER::WHITE, "e2-e4");
+
+    clock.advance(secs(59));
+    assert!(store.out_of_time().is_empty());
+    assert!(store.flag(&session.id).unwrap().is_none());
+    clock.advance(secs(1));
+    assert_eq!(store.out_of_time(), vec![session.id.clone()]);
+    let flagged = store.flag(&session.id).unwrap().unwrap();
+    assert_eq!(flagged.status, GameStatus::Timeout);
+    assert_eq!(flagged.result.as_deref(), Some("1-0"));
+    // only once
+    assert!(store.flag(&session.id).unwrap().is_none());
+    let late = store.with(&session.id, |session| session.play_as(PLAYER::BLACK, "e7-e5", clock.now()));
+    assert!(late.is_err());
+}
+
+#[test]
+fn running_out_of_time_against_a_lone_king_draws() {
+    let clock = Arc::new(ManualClock::new(Duration::ZERO));
+    let store = store(&clock);
+    // white can mate with a pawn and a knight, but not with a knight alone
+    let (session, _) = store.create(Some("4k3/8/8/8/8/8/3PN3/4K3 w - - 0 1"), PLAYER::WHITE, false, None).unwrap();
+    timed(&store, &session.id, "1+0");
+    play(&store, &clock, &session.id, PLAYER::WHITE, "d2-d3");
+    clock.advance(secs(61));
+    let flagged = store.flag(&session.id).unwrap().unwrap();
+    assert_eq!(flagged.status, GameStatus::Timeout);
+
+    let (session, _) = store.create(Some("4k3/4p3/8/8/8/8/4N3/4K3 w - - 0 1"), PLAYER::WHITE, false, None).unwrap();
+    timed(&store, &session.id, "1+0");
+    play(&store, &clock, &session.id, PLAYER::WHITE, "e2-c3");
+    clock.advance(secs(61));
+    let flagged = store.flag(&session.id).unwrap().unwrap();
+    assert_eq!(flagged.status, GameStatus::TimeoutVsInsufficientMaterial);
+    assert_eq!(flagged.result.as_deref(), Some("1/2-1/2"));
+}
+
+#[test]
+fn clocks_carry_on_after_a_restore() {
+    let clock = Arc::new(ManualClock::new(Duration::ZERO));
+    let store = store(&clock);
+    let (session, _) = store.create(None, PLAYER::WHITE, false, None).unwrap();
+    timed(&store, &session.id, "1+2");
+    play(&store, &clock, &session.id, PLAYER::WHITE, "e2-e4");
+    clock.advance(secs(10));
+    play(&store, &clock, &session.id, PLAYER::BLACK, "e7-e5");
+
+    let stored = store.get(&session.id).unwrap().to_stored();
+    clock.advance(secs(100));
+    let restored = GameSession::restore(&stored, clock.now()).unwrap();
+    let restored_clock = restored.clock().unwrap();
+    assert_eq!(restored_clock.running(), Some(PLAYER::WHITE));
+    assert_eq!(restored_clock.remaining(PLAYER::WHITE, clock.now()), secs(62));
+    assert_eq!(restored_clock.remaining(PLAYER::BLACK, clock.now()), secs(52));
+}
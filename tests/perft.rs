@@ -0,0 +1,49 @@
+use chess::game::Game;
+
+// leaf nodes of the legal move tree to depth
+fn perft(game: &Game, depth: u32) -> u64 {
+    let moves = game.get_validated_moves(game.state.player_turn);
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    moves
+        .iter()
+        .map(|chess_move| {
+            let mut next = game.clone();
+            next.make_move(chess_move).unwrap();
+            perft(&next, depth - 1)
+        })
+        .sum()
+}
+
+fn assert_perft(fen: &str, counts: &[u64]) {
+    let game = Game::game_from_fen(fen).unwrap();
+    for (depth, count) in counts.iter().enumerate() {
+        assert_eq!(perft(&game, depth as u32 + 1), *count, "{} at depth {}", fen, depth + 1);
+    }
+}
+
+// the standard positions from the chess programming wiki, to the depths that stay quick
+#[test]
+fn start_position() {
+    assert_perft("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", &[20, 400, 8902]);
+}
+
+// castling both ways, pins, en passant and promotions
+#[test]
+fn kiwipete() {
+    assert_perft("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", &[48, 2039]);
+}
+
+// en passant that would expose the king along the rank
+#[test]
+fn rook_endgame() {
+    assert_perft("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", &[14, 191, 2812]);
+}
+
+// underpromotions, and castling through attacked squares
+#[test]
+fn promotions() {
+    assert_perft("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", &[6, 264, 9467]);
+    assert_perft("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", &[44, 1486]);
+}
@@ -0,0 +1,47 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// runs the uci binary on a script, as a GUI piping commands in would, and returns what it printed
+fn run_script(script: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_uci"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "uci exited with {}", output.status);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn answers_a_scripted_session() {
+    let output = run_script("uci\nisready\nsetoption name Hash value 1\nposition startpos moves e2e4 e7e5\ngo depth 3\n");
+    assert!(output.contains("uciok"));
+    assert!(output.contains("readyok"));
+    assert!(output.contains("info depth 3"));
+    let best_move = output.lines().find_map(|line| line.strip_prefix("bestmove ")).expect("no bestmove");
+    assert_ne!(best_move.split_whitespace().next(), Some("0000"));
+}
+
+#[test]
+fn malformed_setoption_is_reported() {
+    let output = run_script("setoption\nsetoption value 3\nsetoption name\nsetoption name value 3\nsetoption name Skill Level value\nisready\n");
+    assert_eq!(output.matches("info string setoption needs a name").count(), 4);
+    assert!(output.contains("info string invalid Skill Level value"));
+    assert!(output.contains("readyok"));
+}
+
+#[test]
+fn bad_positions_keep_the_last_good_one() {
+    let output = run_script("position fen 8/8/8 w - - 0 1\nposition startpos moves e2e5\nposition\ngo depth 1\n");
+    assert!(output.contains("info string invalid position"));
+    assert!(output.contains("info string position needs startpos or fen"));
+    assert!(output.contains("bestmove "));
+}
+
+#[test]
+fn an_infinite_search_stops_when_the_input_ends() {
+    let output = run_script("position startpos\ngo infinite\n");
+    assert!(output.contains("bestmove "), "{}", output);
+}
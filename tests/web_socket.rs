@@ -0,0 +1,665 @@
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Child, Command};
+use std::time::Duration;
+
+use awc::ws::{Frame, Message};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+
+// the web server on a free port, stopped when dropped
+struct Server {
+    child: Child,
+    port: u16,
+    users: PathBuf,
+    games: PathBuf,
+}
+
+impl Server {
+    async fn start() -> Server {
+        Server::start_with(&[]).await
+    }
+
+    // with more flags for the server
+    async fn start_with(args: &[&str]) -> Server {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let users = std::env::temp_dir().join(format!("chess-users-{}.json", port));
+        let games = std::env::temp_dir().join(format!("chess-games-{}.db", port));
+        Server::run(port, users, games, args).await
+    }
+
+    // stops the server and starts a new one on the same files
+    async fn restart(mut self) -> Server {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let (users, games) = (std::mem::take(&mut self.users), std::mem::take(&mut self.games));
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        Server::run(port, users, games, &[]).await
+    }
+
+    async fn run(port: u16, users: PathBuf, games: PathBuf, args: &[&str]) -> Server {
+        let child = Command::new(env!("CARGO_BIN_EXE_main_web_server"))
+            .args(["--port", &port.to_string(), "--users", users.to_str().unwrap(), "--games", games.to_str().unwrap()])
+            .args(args)
+            .spawn()
+            .unwrap();
+        let server = Server { child, port, users, games };
+        for _ in 0..100 {
+            if std::net::TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                return server;
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("web server did not start");
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{}", self.port, path)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_file(&self.users);
+        let _ = std::fs::remove_file(&self.games);
+    }
+}
+
+type Socket = actix_codec::Framed<awc::BoxedSocket, awc::ws::Codec>;
+
+// a player's socket with their token, a spectator's without
+async fn connect(server: &Server, id: &str, token: Option<&str>) -> Socket {
+    let query = token.map_or(String::new(), |token| format!("?token={}", token));
+    let (_, socket) = awc::Client::new().ws(server.url(&format!("/games/{}/ws{}", id, query))).connect().await.unwrap();
+    socket
+}
+
+// a new game with white seated, then black joining; the id and both tokens
+async fn start_game(server: &Server) -> (String, String, String) {
+    let client = awc::Client::new();
+    let white: Value = client
+        .post(server.url("/games"))
+        .send_json(&json!({"color": "white"}))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = white["game"]["id"].as_str().unwrap().to_string();
+    let black: Value = client
+        .post(server.url(&format!("/games/{}/join", id)))
+        .send_json(&json!({}))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(black["color"], "black");
+    (id, white["token"].as_str().unwrap().to_string(), black["token"].as_str().unwrap().to_string())
+}
+
+async fn next_event(socket: &mut Socket) -> Value {
+    loop {
+        let frame = actix_web::rt::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("no event in time")
+            .unwrap()
+            .unwrap();
+        if let Frame::Text(text) = frame {
+            return serde_json::from_slice(&text).unwrap();
+        }
+    }
+}
+
+async fn send(socket: &mut Socket, message: Value) {
+    socket.send(Message::Text(message.to_string().into())).await.unwrap();
+}
+
+#[actix_web::test]
+async fn both_players_see_each_move() {
+    let server = Server::start().await;
+    let client = awc::Client::new();
+    let (id, white_token, black_token) = start_game(&server).await;
+
+    let mut white = connect(&server, &id, Some(&white_token)).await;
+    let mut black = connect(&server, &id, Some(&black_token)).await;
+    let mut spectator = connect(&server, &id, None).await;
+    for socket in [&mut white, &mut black, &mut spectator] {
+        let event = next_event(socket).await;
+        assert_eq!(event["version"], 2);
+        assert_eq!(event["type"], "state");
+        assert_eq!(event["game"]["moves"], json!([]));
+    }
+
+    // only the side to move can move
+    send(&mut black, json!({"version": 2, "type": "move", "move": "e7-e5"})).await;
+    assert_eq!(next_event(&mut black).await["error"], "not_your_turn");
+    send(&mut spectator, json!({"version": 2, "type": "move", "move": "e2-e4"})).await;
+    assert_eq!(next_event(&mut spectator).await["error"], "not_a_player");
+
+    send(&mut white, json!({"version": 2, "type": "move", "move": "e2-e4"})).await;
+    for socket in [&mut white, &mut black, &mut spectator] {
+        let event = next_event(socket).await;
+        assert_eq!(event["type"], "move");
+        assert_eq!(event["move"], "e2-e4");
+        assert_eq!(event["game"]["turn"], "black");
+    }
+
+    send(&mut black, json!({"version": 2, "type": "move", "move": "e7-e5"})).await;
+    for socket in [&mut white, &mut black, &mut spectator] {
+        let event = next_event(socket).await;
+        assert_eq!(event["move"], "e7-e5");
+        assert_eq!(event["game"]["moves"], json!(["e2-e4", "e7-e5"]));
+    }
+
+    // a bad move only goes back to whoever sent it
+    send(&mut white, json!({"version": 2, "type": "move", "move": "e4-e5"})).await;
+    let event = next_event(&mut white).await;
+    assert_eq!(event["type"], "error");
+    assert_eq!(event["error"], "pawn_cant_attack_forward");
+    // so does one that isn't a move at all, and the game carries on
+    send(&mut white, json!({"version": 2, "type": "move", "move": "e2-"})).await;
+    let event = next_event(&mut white).await;
+    assert_eq!(event["type"], "error");
+    assert_eq!(event["error"], "invalid_move");
+
+    send(&mut white, json!({"version": 1, "type": "offer_draw"})).await;
+    assert_eq!(next_event(&mut white).await["error"], "unsupported_version");
+
+    // moves made over REST reach the sockets too
+    let response = client
+        .post(server.url(&format!("/games/{}/moves", id)))
+        .send_json(&json!({"move": "g1-f3"}))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+    client
+        .post(server.url(&format!("/games/{}/moves", id)))
+        .insert_header(("X-Player-Token", white_token.as_str()))
+        .send_json(&json!({"move": "g1-f3"}))
+        .await
+        .unwrap();
+    // black and the spectator saw neither error, their next event is this move
+    for socket in [&mut white, &mut black, &mut spectator] {
+        assert_eq!(next_event(socket).await["move"], "g1-f3");
+    }
+    send(&mut black, json!({"version": 2, "type": "move", "move": "b8-c6"})).await;
+    for socket in [&mut white, &mut black, &mut spectator] {
+        let event = next_event(socket).await;
+        assert_eq!(event["move"], "b8-c6");
+        assert_eq!(event["game"]["moves"], json!(["e2-e4", "e7-e5", "g1-f3", "b8-c6"]));
+    }
+}
+
+#[actix_web::test]
+async fn malformed_moves_are_refused() {
+    let server = Server::start().await;
+    let client = awc::Client::new();
+    let (id, white_token, _) = start_game(&server).await;
+    for chess_move in ["e2-", "e2-e", "-e4", "", "e2-\u{e9}", "\u{e9}2-e4", "e2-e\u{e9}"] {
+        let mut response = client
+            .post(server.url(&format!("/games/{}/moves", id)))
+            .insert_header(("X-Player-Token", white_token.as_str()))
+            .send_json(&json!({"move": chess_move}))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400, "{:?}", chess_move);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "invalid_move", "{:?}", chess_move);
+    }
+
+    // the server is still up and the game untouched
+    let game: Value = client.get(server.url(&format!("/games/{}", id))).send().await.unwrap().json().await.unwrap();
+    assert_eq!(game["moves"], json!([]));
+    let response = client
+        .post(server.url(&format!("/games/{}/moves", id)))
+        .insert_header(("X-Player-Token", white_token.as_str()))
+        .send_json(&json!({"move": "e2-e4"}))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+}
+
+// the status and body /valid_moves answers with
+async fn valid_moves(server: &Server, request: Value) -> (u16, Value) {
+    let mut response = awc::Client::new().post(server.url("/valid_moves")).send_json(&request).await.unwrap();
+    (response.status().as_u16(), response.json().await.unwrap())
+}
+
+// one origin square's targets, sorted
+fn targets(body: &Value, origin: &str) -> Vec<String> {
+    let mut targets: Vec<String> = serde_json::from_value(body["moves"][origin].clone()).unwrap_or_default();
+    targets.sort();
+    targets
+}
+
+#[actix_web::test]
+async fn valid_moves_come_from_a_position_or_a_game() {
+    let server = Server::start().await;
+
+    let (status, start) = valid_moves(&server, json!({})).await;
+    assert_eq!(status, 200);
+    assert_eq!(start["moves"].as_object().unwrap().len(), 10);
+    assert_eq!(targets(&start, "e2"), ["e3", "e4"]);
+    assert_eq!(targets(&start, "g1"), ["f3", "h3"]);
+    assert!(start["web_game"]["state"].is_array());
+
+    let (_, played) = valid_moves(&server, json!({"moves": ["e2-e4", "e7-e5"]})).await;
+    assert_eq!(targets(&played, "f1"), ["a6", "b5", "c4", "d3", "e2"]);
+    assert_eq!(targets(&played, "e4"), Vec::<String>::new());
+    let (_, from_fen) = valid_moves(&server, json!({"fen": "4k3/8/8/8/8/8/8/R3K3 w Q - 0 1", "moves": ["a1-a2", "e8-d8"]})).await;
+    assert_eq!(targets(&from_fen, "e1"), ["d1", "d2", "e2", "f1", "f2"]);
+
+    // the four promotions list their square once
+    let (_, promotion) = valid_moves(&server, json!({"fen": "1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1"})).await;
+    assert_eq!(targets(&promotion, "a7"), ["a8", "b8"]);
+
+    let (id, white_token, _) = start_game(&server).await;
+    awc::Client::new()
+        .post(server.url(&format!("/games/{}/moves", id)))
+        .insert_header(("X-Player-Token", white_token.as_str()))
+        .send_json(&json!({"move": "d2-d4"}))
+        .await
+        .unwrap();
+    let (_, stored) = valid_moves(&server, json!({"game_id": id})).await;
+    assert_eq!(targets(&stored, "d7"), ["d5", "d6"]);
+    assert_eq!(targets(&stored, "d2"), Vec::<String>::new());
+
+    for (request, status, error) in [
+        (json!({"fen": "not a fen"}), 400, "invalid_fen"),
+        (json!({"moves": ["e2-"]}), 400, "invalid_move"),
+        (json!({"moves": ["e2-e4", "e2-e4"]}), 400, "no_piece"),
+        (json!({"game_id": "nope"}), 404, "game_not_found"),
+    ] {
+        let (actual, body) = valid_moves(&server, request.clone()).await;
+        assert_eq!(actual, status, "{}", request);
+        assert_eq!(body["error"], error, "{}", request);
+    }
+    // the engine builds its game the same way
+    let mut response = awc::Client::new().post(server.url("/engine_move")).send_json(&json!({"moves": ["e2-"]})).await.unwrap();
+    assert_eq!(response.status(), 400);
+    assert_eq!(response.json::<Value>().await.unwrap()["error"], "invalid_move");
+}
+
+#[actix_web::test]
+async fn draw_offers_are_pushed_and_end_the_game() {
+    let server = Server::start().await;
+    let (id, white_token, black_token) = start_game(&server).await;
+    let mut white = connect(&server, &id, Some(&white_token)).await;
+    let mut black = connect(&server, &id, Some(&black_token)).await;
+    next_event(&mut white).await;
+    next_event(&mut black).await;
+
+    send(&mut white, json!({"version": 2, "type": "offer_draw"})).await;
+    for socket in [&mut white, &mut black] {
+        let event = next_event(socket).await;
+        assert_eq!(event["type"], "draw_offer");
+        assert_eq!(event["by"], "white");
+    }
+    send(&mut white, json!({"version": 2, "type": "move", "move": "d2-d4"})).await;
+    next_event(&mut white).await;
+    next_event(&mut black).await;
+
+    send(&mut black, json!({"version": 2, "type": "accept_draw"})).await;
+    for socket in [&mut white, &mut black] {
+        let event = next_event(socket).await;
+        assert_eq!(event["type"], "game_over");
+        assert_eq!(event["status"], "draw_agreed");
+        assert_eq!(event["result"], "1/2-1/2");
+    }
+}
+
+#[actix_web::test]
+async fn private_games_need_the_invite() {
+    let server = Server::start().await;
+    let client = awc::Client::new();
+    let created: Value = client
+        .post(server.url("/games"))
+        .send_json(&json!({"color": "black", "private": true}))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = created["game"]["id"].as_str().unwrap();
+    let invite = created["invite"].as_str().unwrap();
+    assert_eq!(created["color"], "black");
+
+    let join = |invite: Value| client.post(server.url(&format!("/games/{}/join", id))).send_json(&json!({"invite": invite}));
+    let mut refused = join(json!("wrong")).await.unwrap();
+    assert_eq!(refused.status(), 403);
+    assert_eq!(refused.json::<Value>().await.unwrap()["error"], "invalid_invite");
+    let mut joined = join(json!(invite)).await.unwrap();
+    assert_eq!(joined.json::<Value>().await.unwrap()["color"], "white");
+    let mut full = join(json!(invite)).await.unwrap();
+    assert_eq!(full.status(), 409);
+    assert_eq!(full.json::<Value>().await.unwrap()["error"], "seat_taken");
+}
+
+#[actix_web::test]
+async fn lobby_pairs_compatible_seeks() {
+    let server = Server::start().await;
+    let client = awc::Client::new();
+    let (_, mut feed) = client.ws(server.url("/lobby/ws")).connect().await.unwrap();
+    let event = next_event(&mut feed).await;
+    assert_eq!(event["type"], "seeks");
+    assert_eq!(event["seeks"], json!([]));
+
+    let post = |seek: Value| client.post(server.url("/lobby/seeks")).send_json(&seek);
+    let waiting: Value = post(json!({"time_control": "5+3", "color": "black", "rating": 1600, "min_rating": 1400}))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(waiting["status"], "open");
+    let seek_id = waiting["seek"]["id"].as_str().unwrap();
+    let seek_token = waiting["token"].as_str().unwrap();
+    let event = next_event(&mut feed).await;
+    assert_eq!(event["type"], "seek_added");
+    assert_eq!(event["seek"]["time_control"], "5+3");
+
+    // too weak for the waiting seek, then a different game: both wait
+    let weak: Value = post(json!({"time_control": "5+3", "rating": 1200})).await.unwrap().json().await.unwrap();
+    assert_eq!(weak["status"], "open");
+    let other: Value = post(json!({"time_control": "3+2", "rating": 1500})).await.unwrap().json().await.unwrap();
+    assert_eq!(other["status"], "open");
+    next_event(&mut feed).await;
+    next_event(&mut feed).await;
+
+    let paired: Value = post(json!({"time_control": "5+3", "rating": 1500})).await.unwrap().json().await.unwrap();
+    assert_eq!(paired["status"], "paired");
+    assert_eq!(paired["seat"]["color"], "white");
+    let game_id = paired["seat"]["game"]["id"].as_str().unwrap();
+    let event = next_event(&mut feed).await;
+    assert_eq!(event["type"], "seek_removed");
+    assert_eq!(event["id"], seek_id);
+    assert_eq!(event["reason"], "paired");
+    assert_eq!(event["game_id"], game_id);
+
+    let status: Value = client
+        .get(server.url(&format!("/lobby/seeks/{}", seek_id)))
+        .insert_header(("X-Seek-Token", seek_token))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(status["status"], "paired");
+    assert_eq!(status["seat"]["color"], "black");
+    assert_eq!(status["seat"]["game"]["id"], game_id);
+    assert_eq!(status["seat"]["game"]["white_joined"], true);
+    assert_eq!(status["seat"]["game"]["black_joined"], true);
+
+    let seeks: Value = client.get(server.url("/lobby/seeks")).send().await.unwrap().json().await.unwrap();
+    assert_eq!(seeks.as_array().unwrap().len(), 2);
+}
+
+#[actix_web::test]
+async fn logged_in_players_have_their_games_on_their_profile() {
+    let server = Server::start().await;
+    let client = awc::Client::new();
+    let mut tokens = Vec::new();
+    for name in ["alice", "bob"] {
+        let credentials = json!({"username": name, "password": "correct horse"});
+        let registered = client.post(server.url("/accounts/register")).send_json(&credentials).await.unwrap();
+        assert_eq!(registered.status(), 201);
+        let login: Value = client.post(server.url("/accounts/login")).send_json(&credentials).await.unwrap().json().await.unwrap();
+        tokens.push(format!("Bearer {}", login["token"].as_str().unwrap()));
+    }
+    let wrong = client
+        .post(server.url("/accounts/login"))
+        .send_json(&json!({"username": "alice", "password": "wrong password"}))
+        .await
+        .unwrap();
+    assert_eq!(wrong.status(), 401);
+
+    let created: Value = client
+        .post(server.url("/games"))
+        .insert_header(("Authorization", tokens[0].as_str()))
+        .send_json(&json!({"color": "white"}))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = created["game"]["id"].as_str().unwrap();
+    // nobody rates a game against themselves
+    let mut own = client
+        .post(server.url(&format!("/games/{}/join", id)))
+        .insert_header(("Authorization", tokens[0].as_str()))
+        .send_json(&json!({}))
+        .await
+        .unwrap();
+    assert_eq!(own.status(), 409);
+    assert_eq!(own.json::<Value>().await.unwrap()["error"], "already_seated");
+    let joined: Value = client
+        .post(server.url(&format!("/games/{}/join", id)))
+        .insert_header(("Authorization", tokens[1].as_str()))
+        .send_json(&json!({}))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(joined["game"]["white_user"], "alice");
+    assert_eq!(joined["game"]["black_user"], "bob");
+
+    let me: Value = client
+        .get(server.url("/me"))
+        .insert_header(("Authorization", tokens[1].as_str()))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(me["username"], "bob");
+    assert_eq!(me["games"][0]["id"], id);
+    assert_eq!(me["games"][0]["color"], "black");
+    assert_eq!(me["games"][0]["opponent"], "alice");
+    assert_eq!(client.get(server.url("/me")).send().await.unwrap().status(), 401);
+}
+
+#[actix_web::test]
+async fn rated_games_move_both_ratings() {
+    let server = Server::start().await;
+    let client = awc::Client::new();
+    let mut tokens = Vec::new();
+    for name in ["carol", "dave"] {
+        let credentials = json!({"username": name, "password": "correct horse"});
+        client.post(server.url("/accounts/register")).send_json(&credentials).await.unwrap();
+        let login: Value = client.post(server.url("/accounts/login")).send_json(&credentials).await.unwrap().json().await.unwrap();
+        tokens.push(format!("Bearer {}", login["token"].as_str().unwrap()));
+    }
+    let anonymous = client.post(server.url("/games")).send_json(&json!({"rated": true, "time_control": "5+0"})).await.unwrap();
+    assert_eq!(anonymous.status(), 401);
+
+    let created: Value = client
+        .post(server.url("/games"))
+        .insert_header(("Authorization", tokens[0].as_str()))
+        .send_json(&json!({"color": "white", "rated": true, "time_control": "5+0"}))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = created["game"]["id"].as_str().unwrap();
+    let joined: Value = client
+        .post(server.url(&format!("/games/{}/join", id)))
+        .insert_header(("Authorization", tokens[1].as_str()))
+        .send_json(&json!({}))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let seats = [created["token"].as_str().unwrap(), joined["token"].as_str().unwrap()];
+    // fool's mate
+    for (ply, chess_move) in ["f2-f3", "e7-e5", "g2-g4", "d8-h4"].into_iter().enumerate() {
+        let played = client
+            .post(server.url(&format!("/games/{}/moves", id)))
+            .insert_header(("X-Player-Token", seats[ply % 2]))
+            .send_json(&json!({"move": chess_move}))
+            .await
+            .unwrap();
+        assert_eq!(played.status(), 200);
+    }
+
+    let winner: Value = client.get(server.url("/users/dave")).send().await.unwrap().json().await.unwrap();
+    let loser: Value = client.get(server.url("/users/carol")).send().await.unwrap().json().await.unwrap();
+    assert!(winner["ratings"]["blitz"]["rating"].as_i64().unwrap() > 1500);
+    assert!(loser["ratings"]["blitz"]["rating"].as_i64().unwrap() < 1500);
+    assert_eq!(winner["ratings"]["blitz"]["games"], 1);
+    assert_eq!(winner["ratings"]["blitz"]["provisional"], true);
+    assert_eq!(winner["ratings"]["bullet"]["games"], 0);
+
+    let history: Value = client
+        .get(server.url("/users/dave/ratings/history?pool=blitz"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(history[0]["game_id"], id);
+    assert_eq!(history[0]["opponent"], "carol");
+    assert_eq!(history[0]["score"], 1.0);
+    let other_pool: Value = client
+        .get(server.url("/users/dave/ratings/history?pool=rapid"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(other_pool.as_array().unwrap().len(), 0);
+    let bad_pool = client.get(server.url("/users/dave/ratings/history?pool=armageddon")).send().await.unwrap();
+    assert_eq!(bad_pool.status(), 400);
+}
+
+#[actix_web::test]
+async fn games_survive_a_restart() {
+    let server = Server::start().await;
+    let client = awc::Client::new();
+    let (id, white, black) = start_game(&server).await;
+    for (token, chess_move) in [(&white, "e2-e4"), (&black, "e7-e5")] {
+        let played = client
+            .post(server.url(&format!("/games/{}/moves", id)))
+            .insert_header(("X-Player-Token", token.as_str()))
+            .send_json(&json!({"move": chess_move}))
+            .await
+            .unwrap();
+        assert_eq!(played.status(), 200);
+    }
+
+    let server = server.restart().await;
+    let game: Value = client.get(server.url(&format!("/games/{}", id))).send().await.unwrap().json().await.unwrap();
+    assert_eq!(game["moves"], json!(["e2-e4", "e7-e5"]));
+    assert_eq!(game["turn"], "white");
+    // the seats still belong to the same tokens
+    let played: Value = client
+        .post(server.url(&format!("/games/{}/moves", id)))
+        .insert_header(("X-Player-Token", white.as_str()))
+        .send_json(&json!({"move": "g1-f3"}))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(played["moves"].as_array().unwrap().len(), 3);
+    // and new games don't reuse its id
+    let (next, _, _) = start_game(&server).await;
+    assert_ne!(next, id);
+}
+
+#[actix_web::test]
+async fn the_front_end_is_served_with_its_config() {
+    let config = std::env::temp_dir().join(format!("chess-config-{}.toml", std::process::id()));
+    std::fs::write(&config, "cors_origins = [\"http://example.com\"]\napi_base = \"https://chess.example.com/\"\n").unwrap();
+    let server = Server::start_with(&["--config", config.to_str().unwrap()]).await;
+    let client = awc::Client::new();
+
+    let mut page = client.get(server.url("/")).send().await.unwrap();
+    assert_eq!(page.status(), 200);
+    assert!(String::from_utf8(page.body().await.unwrap().to_vec()).unwrap().contains("config.js"));
+    let mut script = client.get(server.url("/config.js")).send().await.unwrap();
+    let script = String::from_utf8(script.body().await.unwrap().to_vec()).unwrap();
+    assert_eq!(script.trim(), "window.CHESS_API = \"https://chess.example.com\";");
+
+    let allowed = client.get(server.url("/lobby/seeks")).insert_header(("Origin", "http://example.com")).send().await.unwrap();
+    assert_eq!(allowed.headers().get("Access-Control-Allow-Origin").unwrap(), "http://example.com");
+    let other = client.get(server.url("/lobby/seeks")).insert_header(("Origin", "http://elsewhere.com")).send().await.unwrap();
+    assert!(other.headers().get("Access-Control-Allow-Origin").is_none());
+    let _ = std::fs::remove_file(&config);
+}
+
+#[actix_web::test]
+async fn the_server_flags_a_player_out_of_time() {
+    let server = Server::start().await;
+    let client = awc::Client::new();
+    // 1.2 seconds each
+    let created: Value = client
+        .post(server.url("/games"))
+        .send_json(&json!({"color": "white", "time_control": "0.02+0"}))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = created["game"]["id"].as_str().unwrap();
+    assert_eq!(created["game"]["clock"]["white_ms"], 1200);
+    assert_eq!(created["game"]["clock"]["running"], Value::Null);
+    client.post(server.url(&format!("/games/{}/join", id))).send_json(&json!({})).await.unwrap();
+    let mut watcher = connect(&server, id, None).await;
+    next_event(&mut watcher).await;
+
+    let played: Value = client
+        .post(server.url(&format!("/games/{}/moves", id)))
+        .insert_header(("X-Player-Token", created["token"].as_str().unwrap()))
+        .send_json(&json!({"move": "e2-e4"}))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(played["clock"]["running"], "black");
+    assert_eq!(played["clock"]["white_ms"], 1200);
+    let event = next_event(&mut watcher).await;
+    assert_eq!(event["type"], "move");
+
+    // black never moves, and the clocks stop with black on nothing
+    let event = next_event(&mut watcher).await;
+    assert_eq!(event["type"], "clock");
+    assert_eq!(event["black_ms"], 0);
+    assert_eq!(event["white_ms"], 1200);
+    assert_eq!(event["running"], Value::Null);
+    let event = next_event(&mut watcher).await;
+    assert_eq!(event["type"], "game_over");
+    assert_eq!(event["status"], "timeout");
+    assert_eq!(event["result"], "1-0");
+    let game: Value = client.get(server.url(&format!("/games/{}", id))).send().await.unwrap().json().await.unwrap();
+    assert_eq!(game["clock"]["black_ms"], 0);
+    assert_eq!(game["clock"]["running"], Value::Null);
+}
+
+#[actix_web::test]
+async fn analysis_stops_when_its_time_is_up() {
+    let server = Server::start().await;
+    let started = std::time::Instant::now();
+    let analysis: Value = awc::Client::new()
+        .post(server.url("/analyse"))
+        .send_json(&json!({"depth": 100, "multipv": 10, "movetime_ms": 300}))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    let depth = analysis["depth"].as_u64().unwrap();
+    assert!((1..=8).contains(&depth), "{}", depth);
+    assert!(!analysis["lines"].as_array().unwrap().is_empty());
+}
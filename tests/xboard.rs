@@ -0,0 +1,111 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// runs the xboard binary on a script, as a GUI piping commands in would, and returns what it printed
+fn run_script(script: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_xboard"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "xboard exited with {}", output.status);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn engine_moves(output: &str) -> Vec<&str> {
+    output.lines().filter_map(|line| line.strip_prefix("move ")).collect()
+}
+
+#[test]
+fn protover_2_lists_the_features() {
+    let output = run_script("xboard\nprotover 2\nping 7\n");
+    let features = output.lines().find(|line| line.starts_with("feature ")).expect("no features");
+    for feature in ["ping=1", "setboard=1", "usermove=1", "playother=1", "colors=0", "done=1"] {
+        assert!(features.contains(feature), "{} missing from {}", feature, features);
+    }
+    assert!(features.contains(&format!("myname=\"{} ", env!("CARGO_PKG_NAME"))));
+    assert!(output.contains("pong 7"));
+}
+
+#[test]
+fn usermove_is_answered_with_a_move() {
+    let output = run_script("xboard\nprotover 2\nnew\nsd 2\nusermove e2e4\n");
+    assert!(!output.contains("Illegal move"));
+    let moves = engine_moves(&output);
+    assert_eq!(moves.len(), 1, "{}", output);
+    // black's reply, from one of black's own ranks
+    assert!(["7", "8"].iter().any(|rank| moves[0][1..2] == **rank), "{}", moves[0]);
+}
+
+#[test]
+fn go_plays_the_side_to_move() {
+    let output = run_script("new\nforce\nusermove e2e4\nusermove e7e5\nsd 1\ngo\n");
+    let moves = engine_moves(&output);
+    assert_eq!(moves.len(), 1, "{}", output);
+    // white to move, so it's one of white's pieces that moves
+    assert!(["1", "2", "4"].iter().any(|rank| moves[0][1..2] == **rank), "{}", moves[0]);
+}
+
+#[test]
+fn undo_and_remove_take_moves_back() {
+    let output = run_script(
+        "new\nforce\nusermove e2e4\nusermove e7e5\nremove\n\
+         usermove e7e5\nusermove e2e4\nundo\nundo\nremove\nusermove d2d4\nusermove d7d5\n",
+    );
+    // back at the start, white is to move again
+    assert_eq!(output.matches("Illegal move: e7e5").count(), 1, "{}", output);
+    assert!(output.contains("Error (no moves to take back): undo"));
+    assert!(output.contains("Error (no moves to take back): remove"));
+    assert!(!output.contains("Illegal move: d"));
+    assert!(engine_moves(&output).is_empty());
+}
+
+#[test]
+fn setboard_with_a_bad_fen_keeps_the_old_position() {
+    let output = run_script(
+        "new\nforce\nsetboard 4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\nsetboard 8/8/8 w - - 0 1\nusermove e1d2\nusermove e8d7\n",
+    );
+    assert_eq!(output.matches("tellusererror Illegal position").count(), 1, "{}", output);
+    // the king can only step onto d2 in the king and pawn position
+    assert!(!output.contains("Illegal move"), "{}", output);
+}
+
+#[test]
+fn sd_and_st_limit_the_search() {
+    // post shows every finished depth, and none go past sd
+    let output = run_script("new\npost\nsd 2\nforce\ngo\n");
+    let depths: Vec<u32> = output
+        .lines()
+        .filter_map(|line| line.split_whitespace().next()?.parse().ok())
+        .collect();
+    assert!(!depths.is_empty(), "{}", output);
+    assert!(depths.iter().all(|depth| *depth <= 2), "{}", output);
+    assert_eq!(engine_moves(&output).len(), 1);
+
+    let output = run_script("new\nst 0.1\nforce\ngo\n");
+    assert_eq!(engine_moves(&output).len(), 1, "{}", output);
+
+    let output = run_script("sd\nsd deep\nst\nst soon\nping 1\n");
+    assert_eq!(output.matches("Error (bad arguments): sd").count(), 2);
+    assert_eq!(output.matches("Error (bad arguments): st").count(), 2);
+    assert!(output.contains("pong 1"));
+}
+
+#[test]
+fn illegal_moves_are_reported() {
+    let output = run_script("new\nforce\nusermove e2e5\nusermove zz\nusermove\nusermove e7e5\n");
+    assert!(output.contains("Illegal move: e2e5"));
+    assert!(output.contains("Illegal move: zz"));
+    assert!(output.contains("Error (no move): usermove"));
+    assert!(output.contains("Illegal move: e7e5"));
+    assert!(engine_moves(&output).is_empty());
+}
+
+#[test]
+fn a_threefold_repetition_ends_the_game() {
+    let output = run_script("new\nforce\nusermove g1f3\nusermove g8f6\nusermove f3g1\nusermove f6g8\n\
+                             usermove g1f3\nusermove g8f6\nusermove f3g1\nusermove f6g8\n");
+    assert_eq!(output.matches("1/2-1/2 {Draw by repetition}").count(), 1, "{}", output);
+}
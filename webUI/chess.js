@@ -5,6 +5,14 @@ const svgns = "http://www.w3.org/2000/svg";
 var selectedElement = false;
 var offset;
 var values_map = {};
+var game_id;
+var socket;
+var seat;
+var from_square;
+// set by the server's config.js; opened as a loose file there is none, so use a local server
+// (started with --cors-origins "*")
+const apiBase = window.CHESS_API !== undefined ? window.CHESS_API : "http://localhost:9090";
+const socketBase = apiBase ? apiBase.replace(/^http/, "ws") : location.origin.replace(/^http/, "ws");
 
 let columns = 8;
 let rows = 8;
@@ -17,11 +25,11 @@ const colorArray = ["#774C3B","#C99468","#774C3B","#C99468","#774C3B","#C99468",
 
 var starterPosition;
 
-getValidMoves(); //only call when turn changes
+createGame();
 
 function start_game() {
-  console.log(starterPosition)
-  start_player = "white";
+  svg.innerHTML = "";
+  counter = 0;
   
   for (let i = 0; i < rows; i++) {
       for (let j = 0; j < columns; j++) {
@@ -99,22 +107,97 @@ function start_game() {
   //  }
 
 
-   async function getValidMoves() {
+   function sendRequest(method, url, body, on_success) {
     var xhr = new XMLHttpRequest();
-    var url = "http://localhost:9090/valid_moves";
-    xhr.open("POST", url, true);
+    xhr.open(method, apiBase + url, true);
     xhr.setRequestHeader("Content-Type", "application/json");
+    if (seat) {
+        xhr.setRequestHeader("X-Player-Token", seat.token);
+    }
     xhr.onreadystatechange = function () {
-        if (xhr.readyState === 4 && xhr.status === 200) {
-            get_valid_move_resp = JSON.parse(xhr.responseText);
-            starterPosition = get_valid_move_resp.web_game.state;
-            values_map = get_valid_move_resp.moves;
-            console.log(starterPosition)
-            start_game()
+        if (xhr.readyState !== 4) {
+            return;
+        }
+        resp = JSON.parse(xhr.responseText);
+        if (xhr.status === 200 || xhr.status === 201) {
+            (on_success || showGame)(resp);
+        } else if (url.endsWith("/join")) {
+            // can't play in this game, so just watch it
+            console.log(resp.message);
+            seat = undefined;
+            sendRequest("GET", url.replace("/join", ""));
+        } else {
+            // put the piece back where it was
+            console.log(resp.message);
+            start_game();
+        }
+    };
+    xhr.send(body === undefined ? null : JSON.stringify(body));
+   }
+
+   // the token and colour this browser plays a game with
+   function takeSeat(resp) {
+    seat = {"token": resp.token, "color": resp.color};
+    localStorage.setItem("seat-" + resp.game.id, JSON.stringify(seat));
+    if (resp.invite) {
+        console.log("invite link: ?game=" + resp.game.id + "&invite=" + resp.invite);
+    }
+    showGame(resp.game);
+   }
+
+   // ?game=ID in the address joins that game, or watches it once both seats are taken;
+   // anything else starts a new one
+   function createGame() {
+    var params = new URLSearchParams(window.location.search);
+    if (params.has("game")) {
+        var saved = localStorage.getItem("seat-" + params.get("game"));
+        if (saved) {
+            seat = JSON.parse(saved);
+            sendRequest("GET", "/games/" + params.get("game"));
+        } else {
+            sendRequest("POST", "/games/" + params.get("game") + "/join", {"invite": params.get("invite")}, takeSeat);
+        }
+    } else {
+        sendRequest("POST", "/games", {}, takeSeat);
+    }
+   }
+
+   // the other player's moves arrive here
+   function watchGame(id) {
+    var query = seat ? "?token=" + seat.token : "";
+    socket = new WebSocket(socketBase + "/games/" + id + "/ws" + query);
+    socket.onmessage = function (message) {
+        var event = JSON.parse(message.data);
+        if (event.game) {
+            showGame(event.game);
+        } else if (event.type === "game_over") {
+            console.log(event.status + " " + event.result);
+        } else if (event.type === "error") {
+            console.log(event.message);
         }
     };
-    var data = JSON.stringify({"message": ""});
-    xhr.send(data);
+   }
+
+   function playMove(chess_move) {
+    sendRequest("POST", "/games/" + game_id + "/moves", {"move": chess_move});
+   }
+
+   function showGame(game) {
+    if (game_id !== game.id) {
+        game_id = game.id;
+        if (!window.location.search.includes("game=" + game.id)) {
+            window.history.replaceState(null, "", "?game=" + game.id);
+        }
+        watchGame(game.id);
+    }
+    starterPosition = game.web_game.state;
+    values_map = game.valid_moves;
+    // only your own pieces move, and only on your turn
+    start_player = seat && seat.color === game.turn ? game.turn : "nobody";
+    if (game.result) {
+        console.log(game.status + " " + game.result);
+    }
+    start_game();
    }
 
   function getSquareId(col_num, row_num) {
@@ -171,6 +254,8 @@ function getMousePosition(evt) {
 function startDrag(evt) {
   if (evt.target.classList.contains('draggable')) {
     selectedElement = evt.target;
+    from_square = getSquareId((parseFloat(selectedElement.getAttributeNS(null, "x")) - 15) / width,
+                              (parseFloat(selectedElement.getAttributeNS(null, "y")) - 15) / height);
     offset = getMousePosition(evt);
     offset.x -= parseFloat(selectedElement.getAttributeNS(null, "x"));
     offset.y -= parseFloat(selectedElement.getAttributeNS(null, "y"));
@@ -193,6 +278,17 @@ function endDrag(evt) {
     y_adjusted = parseInt((coord.y / height)) * height + 15 ;
     selectedElement.setAttributeNS(null, "x", x_adjusted );
     selectedElement.setAttributeNS(null, "y", y_adjusted);
+    to_square = getSquareId(parseInt(coord.x / width), parseInt(coord.y / height));
+    if (from_square in values_map && values_map[from_square].includes(to_square)) {
+      chess_move = from_square + "-" + to_square;
+      // pawns reaching the last rank become queens
+      if (selectedElement.getAttributeNS(null, "href").includes("pawn") && (to_square[1] === "8" || to_square[1] === "1")) {
+        chess_move += "pq";
+      }
+      playMove(chess_move);
+    } else {
+      start_game();
+    }
   }
   selectedElement = null;
 }